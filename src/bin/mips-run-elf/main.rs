@@ -1,10 +1,13 @@
 use std::str::FromStr;
 
+use libpsx::cpu::backend::CpuBackend;
 use libpsx::cpu::bus::{BusDevice, SizedReadResult};
-use libpsx::cpu::CpuState;
+#[cfg(feature = "jit")]
+use libpsx::cpu::llvm::{JitConfig, OptLevel};
+use libpsx::cpu::{BusErrorPolicy, CpuState};
 use object::{Object, ObjectSection};
 
-use argparse::{ArgumentParser, Store};
+use argparse::{ArgumentParser, Store, StoreTrue};
 
 fn load_section(bus: &mut dyn BusDevice, addr: u32, buf: &[u8]) {
     let len = buf.len();
@@ -42,25 +45,56 @@ impl libpsx::cpu::bus::BusDevice for DiscountUart {
 }
 
 enum ExecType {
+    #[cfg(feature = "jit")]
     JIT,
     Interpreter,
+    #[cfg(feature = "threaded")]
     ThreadedInt,
 }
 
+impl Default for ExecType {
+    #[cfg(feature = "jit")]
+    fn default() -> Self {
+        Self::JIT
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn default() -> Self {
+        Self::Interpreter
+    }
+}
+
 impl FromStr for ExecType {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
+            #[cfg(feature = "jit")]
             "jit" => Ok(Self::JIT),
+            #[cfg(not(feature = "jit"))]
+            "jit" => Err(String::from("Built without the jit feature")),
             "int" | "interpreter" => Ok(Self::Interpreter),
+            #[cfg(feature = "threaded")]
             "thr" | "threaded" | "cached" => Ok(Self::ThreadedInt),
+            #[cfg(not(feature = "threaded"))]
+            "thr" | "threaded" | "cached" => {
+                Err(String::from("Built without the threaded feature"))
+            }
             _ => Err(String::from("Invalid execution mode")),
         }
     }
 }
 
 fn main() {
-    let mut exec_mode = ExecType::JIT;
+    let mut exec_mode = ExecType::default();
+    let mut bus_error_policy = BusErrorPolicy::Exception;
+    let mut stats_json = false;
+    let mut icache = false;
+    #[cfg(feature = "jit")]
+    let mut jit_opt = OptLevel::Less;
+    #[cfg(feature = "jit")]
+    let mut jit_passes = String::new();
+    #[cfg(feature = "jit")]
+    let mut jit_cache = String::new();
     let mut file = String::new();
 
     {
@@ -68,12 +102,51 @@ fn main() {
         ap.set_description("Run a MIPS elf file");
         ap.refer(&mut exec_mode)
             .add_option(&["-m", "--mode"], Store, "Execution mode");
+        ap.refer(&mut bus_error_policy).add_option(
+            &["--bus-errors"],
+            Store,
+            "Bus error handling (exception, abort)",
+        );
+        ap.refer(&mut stats_json).add_option(
+            &["--stats-json"],
+            StoreTrue,
+            "Print execution stats as JSON on the last line of output",
+        );
+        ap.refer(&mut icache).add_option(
+            &["--icache"],
+            StoreTrue,
+            "Model the instruction cache, which makes cached fetches faster (interpreter only)",
+        );
+        #[cfg(feature = "jit")]
+        ap.refer(&mut jit_opt).add_option(
+            &["--jit-opt"],
+            Store,
+            "LLVM code generation level for compiled blocks (0-3)",
+        );
+        #[cfg(feature = "jit")]
+        ap.refer(&mut jit_passes).add_option(
+            &["--jit-passes"],
+            Store,
+            "Comma separated LLVM passes to run on compiled blocks, such as mem2reg,instcombine,gvn",
+        );
+        #[cfg(feature = "jit")]
+        ap.refer(&mut jit_cache).add_option(
+            &["--jit-cache"],
+            Store,
+            "Directory to keep compiled blocks in between runs",
+        );
         ap.refer(&mut file)
             .add_argument("Object File", Store, "MIPS File")
             .required();
         ap.parse_args_or_exit();
     }
 
+    // The translated backends don't look fetches up in the cache, so would time them differently
+    if icache && !matches!(exec_mode, ExecType::Interpreter) {
+        eprintln!("--icache is only supported in interpreter mode");
+        std::process::exit(2);
+    }
+
     let buf: Vec<u8> = std::fs::read(&file).unwrap();
     let obj = object::File::parse(&*buf).unwrap();
 
@@ -99,11 +172,47 @@ fn main() {
 
     let mut state = CpuState::default();
     state.set_pc(obj.entry() as u32);
+    state.set_bus_error_policy(bus_error_policy);
+    if icache {
+        state.enable_icache();
+    }
+
+    #[cfg(feature = "jit")]
+    let jit_config = JitConfig {
+        opt_level: jit_opt,
+        passes: match JitConfig::parse_passes(&jit_passes) {
+            Ok(passes) => passes,
+            Err(e) => {
+                eprintln!("--jit-passes: {}", e);
+                std::process::exit(2);
+            }
+        },
+        cache_dir: (!jit_cache.is_empty()).then(|| jit_cache.into()),
+    };
+
+    #[cfg(feature = "jit")]
+    let ctx = inkwell::context::Context::create();
+    let mut backend: Box<dyn CpuBackend + '_> = match exec_mode {
+        #[cfg(feature = "jit")]
+        ExecType::JIT => Box::new(libpsx::cpu::jit::Jit::with_config(&ctx, jit_config)),
+        ExecType::Interpreter => Box::new(libpsx::cpu::interpret::Interpreter::default()),
+        #[cfg(feature = "threaded")]
+        ExecType::ThreadedInt => Box::new(libpsx::cpu::threaded::Threaded::default()),
+    };
+
+    let stats = match libpsx::cpu::backend::run_until_halted(&mut *backend, &mut bus, &mut state) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Execution stopped: {}", e);
+            eprintln!("CpuState: {:x?}", state);
+            std::process::exit(1);
+        }
+    };
 
-    match exec_mode {
-        ExecType::JIT => libpsx::cpu::jit::execute(&mut bus, &mut state),
-        ExecType::Interpreter => libpsx::cpu::interpret::execute(&mut bus, &mut state),
-        ExecType::ThreadedInt => libpsx::cpu::threaded::execute(&mut bus, &mut state),
+    if stats_json {
+        println!("{}", stats.to_json());
+    } else {
+        println!("CpuState: {:x?}", state);
+        println!("{}", stats);
     }
-    .unwrap();
 }
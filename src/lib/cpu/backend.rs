@@ -0,0 +1,361 @@
+use super::bus::{BusDevice, SizedReadResult};
+use super::bus_vec::VecBus;
+use super::{decode, CpuState};
+
+// Outcome of a single step of a backend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepResult {
+    // Executed this many instructions. This can be 0 if an exception was raised before anything
+    // could execute, such as for an interrupt.
+    Executed(u64),
+    // The guest is spinning on a branch to itself, which is how test programs signal completion
+    Halted,
+}
+
+// Why a backend stopped running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    BudgetExhausted,
+    Halted,
+}
+
+// Counters for the backends that translate guest code ahead of executing it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TranslationStats {
+    pub blocks_compiled: u64,
+    // Total time spent translating and compiling blocks
+    pub compile_time: std::time::Duration,
+    // Number of times a store caused translated blocks to be discarded
+    pub invalidations: u64,
+    // Blocks loaded from the on-disk cache rather than translated
+    pub cache_hits: u64,
+    // Hot blocks translated again as traces, which are also counted as blocks compiled
+    pub traces_formed: u64,
+}
+
+// Performance of a run of a backend
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExecStats {
+    pub icount: u64,
+    // Guest cycles elapsed, including stalls
+    pub cycles: u64,
+    pub elapsed: std::time::Duration,
+    // Millions of instructions per second, sampled over every thousand or so instructions
+    pub mips_avg: f64,
+    pub mips_min: f64,
+    pub mips_max: f64,
+    pub translation: Option<TranslationStats>,
+}
+
+// JSON has no representation for infinities or NaN, so they are written as null
+fn json_f64(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        String::from("null")
+    }
+}
+
+impl ExecStats {
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"icount\":{},\"cycles\":{},\"elapsed_secs\":{},\"mips_avg\":{},\"mips_min\":{},\
+             \"mips_max\":{}",
+            self.icount,
+            self.cycles,
+            self.elapsed.as_secs_f64(),
+            json_f64(self.mips_avg),
+            json_f64(self.mips_min),
+            json_f64(self.mips_max)
+        );
+
+        if let Some(t) = self.translation {
+            json += &format!(
+                ",\"blocks_compiled\":{},\"compile_secs\":{},\"invalidations\":{},\
+                 \"cache_hits\":{},\"traces_formed\":{}",
+                t.blocks_compiled,
+                t.compile_time.as_secs_f64(),
+                t.invalidations,
+                t.cache_hits,
+                t.traces_formed
+            );
+        }
+
+        json + "}"
+    }
+}
+
+impl std::fmt::Display for ExecStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "elapsed time: {}", self.elapsed.as_secs_f64())?;
+        writeln!(f, "icount: {}", self.icount)?;
+        writeln!(f, "cycles: {}", self.cycles)?;
+        writeln!(f, "MIPS (average): {}", self.mips_avg)?;
+        writeln!(f, "MIPS (min): {}", self.mips_min)?;
+        write!(f, "MIPS (max): {}", self.mips_max)?;
+
+        if let Some(t) = self.translation {
+            writeln!(f)?;
+            writeln!(f, "blocks compiled: {}", t.blocks_compiled)?;
+            writeln!(f, "compile time: {}", t.compile_time.as_secs_f64())?;
+            writeln!(f, "invalidations: {}", t.invalidations)?;
+            writeln!(f, "cache hits: {}", t.cache_hits)?;
+            write!(f, "traces formed: {}", t.traces_formed)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Whether the code at `pc` does nothing but branch back to itself, which backends report as
+// StepResult::Halted
+pub(super) fn spins_at(bus: &mut dyn BusDevice, pc: u32) -> bool {
+    match (bus.read(pc, 32), bus.read(pc.wrapping_add(4), 32)) {
+        (Ok(SizedReadResult::Dword(branch)), Ok(SizedReadResult::Dword(delay_slot))) => {
+            decode::mips_is_spin_loop(pc, branch, delay_slot)
+        }
+        _ => false,
+    }
+}
+
+// An execution backend for the CPU.
+// Backends hold only what they need to execute quickly, such as translated code and pipeline
+// state. The CPU state and the bus are owned by the caller, so that devices can be serviced
+// between calls.
+pub trait CpuBackend {
+    // Executes the smallest unit of work the backend supports: a single instruction for the
+    // interpreter, or a translated block for the others.
+    fn step(&mut self, bus: &mut VecBus, state: &mut CpuState) -> Result<StepResult, String>;
+
+    // Executes until at least `budget` cycles have elapsed, or the guest halts.
+    // Backends that execute whole blocks at a time may overrun the budget by up to a block.
+    fn run(
+        &mut self,
+        bus: &mut VecBus,
+        state: &mut CpuState,
+        budget: u64,
+    ) -> Result<StopReason, String> {
+        let start = state.cycles();
+        self.set_deadline(Some(start.saturating_add(budget)));
+
+        let mut stop = Ok(StopReason::BudgetExhausted);
+        while state.cycles() - start < budget {
+            match self.step(bus, state) {
+                Ok(StepResult::Executed(_)) => {}
+                Ok(StepResult::Halted) => {
+                    stop = Ok(StopReason::Halted);
+                    break;
+                }
+                Err(e) => {
+                    stop = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.set_deadline(None);
+        stop
+    }
+
+    // Sets the cycle count that steps shouldn't run past, for backends that can execute many
+    // blocks in a single step. Each step still executes at least one block.
+    fn set_deadline(&mut self, _deadline: Option<u64>) {}
+
+    // Translation counters accumulated since the backend was created or reset, for backends that
+    // translate guest code
+    fn translation_stats(&self) -> Option<TranslationStats> {
+        None
+    }
+
+    // Resets the CPU to its power-on state, and discards anything the backend has cached, such as
+    // translated code
+    fn reset(&mut self, state: &mut CpuState);
+}
+
+// Runs the backend until the guest halts, returning how quickly it ran
+pub fn run_until_halted(
+    backend: &mut dyn CpuBackend,
+    bus: &mut VecBus,
+    state: &mut CpuState,
+) -> Result<ExecStats, String> {
+    let mut icount: u64 = 0;
+    let mut icount_tot = 0;
+    let start_cycles = state.cycles();
+    let now = std::time::Instant::now();
+    let mut prev_elapsed: u128 = 0;
+
+    let mut mips_avg: f64 = 0.0;
+    let mut mips_min: f64 = f64::MAX;
+    let mut mips_max: f64 = 0.0;
+    let mut mips_avg_count: u128 = 0;
+
+    let timing_scale = 1_000;
+
+    while let StepResult::Executed(n) = backend.step(bus, state)? {
+        icount += n;
+
+        if icount > timing_scale {
+            let elapsed_micros_tot = now.elapsed().as_micros();
+            let elapsed_micros = elapsed_micros_tot - prev_elapsed;
+
+            // Too quick a window to time, so it is carried on into the next one
+            if elapsed_micros == 0 {
+                continue;
+            }
+            prev_elapsed = elapsed_micros_tot;
+            let elapsed = (elapsed_micros as f64) / 1_000_000.0;
+            let mips = (icount as f64) / elapsed / 1_000_000.0;
+            mips_min = f64::min(mips_min, mips);
+            mips_max = f64::max(mips_max, mips);
+            mips_avg += mips;
+            mips_avg_count += 1;
+
+            icount_tot += icount;
+            icount = 0;
+        }
+    }
+
+    let elapsed = now.elapsed();
+    let icount = icount_tot + icount;
+
+    // Too short a run to sample, so fall back to the rate over the whole run
+    if mips_avg_count == 0 {
+        let mips = if elapsed.is_zero() {
+            0.0
+        } else {
+            (icount as f64) / elapsed.as_secs_f64() / 1_000_000.0
+        };
+        mips_avg = mips;
+        mips_min = mips;
+        mips_max = mips;
+        mips_avg_count = 1;
+    }
+
+    Ok(ExecStats {
+        icount,
+        cycles: state.cycles() - start_cycles,
+        elapsed,
+        mips_avg: mips_avg / (mips_avg_count as f64),
+        mips_min,
+        mips_max,
+        translation: backend.translation_stats(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CpuBackend, ExecStats, StopReason, TranslationStats};
+    use crate::cpu::interpret::Interpreter;
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn interpret_test_run_budget() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("addiu", 0, 0, 2, 2, 0);
+        th.push_instr("addiu", 0, 0, 3, 3, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let mut backend = Interpreter::default();
+
+                assert_eq!(backend.run(bus, state, 2)?, StopReason::BudgetExhausted);
+                assert_eq!(state.gpr[1], 2);
+                assert_eq!(state.gpr[2], 0);
+
+                assert_eq!(backend.run(bus, state, 100)?, StopReason::Halted);
+                assert_eq!(state.gpr[2], 3);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn interpret_test_reset() {
+        let mut state = crate::cpu::CpuState::default();
+        let mut backend = Interpreter::default();
+
+        state.set_pc(0x1000);
+        state.set_reg_val(1, 10);
+        backend.reset(&mut state);
+
+        assert_eq!(state.pc, 0xbfc0_0000);
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Sr as usize],
+            1 << 22
+        );
+    }
+
+    #[test]
+    fn interpret_test_run_until_halted_stats() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("addiu", 0, 0, 2, 2, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let mut backend = Interpreter::default();
+                let stats = super::run_until_halted(&mut backend, bus, state)?;
+
+                // The branch to self is executed before the halt is detected, but not counted
+                assert_eq!(stats.icount, 2);
+                assert_eq!(stats.cycles, 3);
+                assert!(stats.translation.is_none());
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn exec_stats_json() {
+        let stats = ExecStats {
+            icount: 10,
+            cycles: 20,
+            elapsed: std::time::Duration::from_millis(1500),
+            mips_avg: 2.0,
+            mips_min: 1.0,
+            mips_max: 3.5,
+            translation: Some(TranslationStats {
+                blocks_compiled: 4,
+                compile_time: std::time::Duration::from_millis(250),
+                invalidations: 1,
+                cache_hits: 2,
+                traces_formed: 1,
+            }),
+        };
+
+        assert_eq!(
+            stats.to_json(),
+            "{\"icount\":10,\"cycles\":20,\"elapsed_secs\":1.5,\"mips_avg\":2,\"mips_min\":1,\"mips_max\":3.5,\
+             \"blocks_compiled\":4,\"compile_secs\":0.25,\"invalidations\":1,\"cache_hits\":2,\"traces_formed\":1}"
+        );
+    }
+
+    #[test]
+    fn exec_stats_json_non_finite() {
+        let stats = ExecStats {
+            icount: 10,
+            mips_avg: f64::NAN,
+            mips_min: f64::INFINITY,
+            mips_max: f64::INFINITY,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stats.to_json(),
+            "{\"icount\":10,\"cycles\":0,\"elapsed_secs\":0,\"mips_avg\":null,\"mips_min\":null,\
+             \"mips_max\":null}"
+        );
+    }
+}
@@ -21,6 +21,29 @@ pub trait BusDevice {
     fn validate(&mut self, base_addr: u32, size: u32);
     fn read(&mut self, addr: u32, size: u32) -> Result<SizedReadResult, MemAccessError>;
     fn write(&mut self, addr: u32, size: u32, value: u32) -> Result<(), MemAccessError>;
+
+    // Extra cycles the CPU stalls for when reading `size` bits at `addr`, on top of the cycle the
+    // instruction itself takes
+    fn wait_states(&self, _addr: u32, _size: u32) -> u32 {
+        0
+    }
+
+    // Regions of plain memory that the CPU may access directly instead of through `read` and
+    // `write`, such as RAM. Devices whose accesses have side effects must not expose any.
+    fn host_regions(&mut self) -> Vec<HostRegion> {
+        Vec::new()
+    }
+}
+
+// A region of a device's address space backed by host memory, which stays at the same place for
+// the life of the device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HostRegion {
+    pub addr: u32,
+    pub size: u32,
+    pub ptr: *mut u8,
+    // Stall cycles for a read, as for BusDevice::wait_states
+    pub wait_states: u32,
 }
 
 #[derive(Debug, Clone)]
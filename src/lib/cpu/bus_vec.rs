@@ -1,8 +1,11 @@
-use super::bus::{BusDevice, MemAccessError, MemAccessErrorType, SizedReadResult};
+use super::bus::{BusDevice, HostRegion, MemAccessError, MemAccessErrorType, SizedReadResult};
 
 struct BusEntry {
     addr: u32,
     size: u32,
+    // Stall cycles for a read from this region, as set up by the BIOS through the memory control
+    // registers on hardware
+    wait_states: u32,
     device: Box<dyn BusDevice>,
 }
 
@@ -33,7 +36,20 @@ impl VecBus {
             }
         }
 
-        self.bus.push(BusEntry { addr, size, device });
+        self.bus.push(BusEntry {
+            addr,
+            size,
+            wait_states: 0,
+            device,
+        });
+    }
+
+    // Sets the stall cycles for reads from the region mapped at `addr`. Regions default to none.
+    pub fn set_wait_states(&mut self, addr: u32, wait_states: u32) {
+        match self.bus.iter_mut().find(|ent| ent.addr == addr) {
+            Some(ent) => ent.wait_states = wait_states,
+            None => panic!("No bus entry mapped at {:#x}", addr),
+        }
     }
 }
 
@@ -69,6 +85,36 @@ impl BusDevice for VecBus {
             err: MemAccessErrorType::NoEntry,
         })
     }
+
+    fn wait_states(&self, mut addr: u32, size: u32) -> u32 {
+        addr &= 0x1fff_ffff;
+
+        for ent in &self.bus {
+            if ent.addr <= addr && addr <= ent.addr + ent.size {
+                return ent.wait_states + ent.device.wait_states(addr - ent.addr, size);
+            }
+        }
+
+        // The access will fail, and the bus error is what gets modelled
+        0
+    }
+
+    fn host_regions(&mut self) -> Vec<HostRegion> {
+        let mut regions = Vec::new();
+
+        for ent in &mut self.bus {
+            for region in ent.device.host_regions() {
+                regions.push(HostRegion {
+                    addr: ent.addr + region.addr,
+                    size: region.size.min(ent.size.saturating_sub(region.addr)),
+                    wait_states: ent.wait_states + region.wait_states,
+                    ..region
+                });
+            }
+        }
+
+        regions
+    }
 }
 
 #[cfg(test)]
@@ -203,6 +249,40 @@ mod test {
         }
     }
 
+    #[test]
+    fn bus_test_wait_states() {
+        let mut bus = super::VecBus::default();
+
+        bus.map(0x1000, 0x1000, Box::new(SingleMemoryAddress { value: 0 }));
+        bus.map(0x3000, 0x1000, Box::new(SingleMemoryAddress { value: 0 }));
+        bus.set_wait_states(0x3000, 4);
+
+        assert_eq!(bus.wait_states(0x1004, 32), 0);
+        assert_eq!(bus.wait_states(0x3004, 32), 4);
+        // The segment bits are ignored, as for reads
+        assert_eq!(bus.wait_states(0xa000_3004, 32), 4);
+    }
+
+    #[test]
+    fn bus_test_host_regions() {
+        let mut bus = super::VecBus::default();
+
+        bus.map(0x1000, 0x1000, Box::new(SingleMemoryAddress { value: 0 }));
+        bus.map(
+            0x4000,
+            0x800,
+            Box::new(crate::mem::memory::RAM::new(0x1000)),
+        );
+        bus.set_wait_states(0x4000, 2);
+
+        // Only the RAM is backed by host memory, and only as much of it as is mapped
+        let regions = bus.host_regions();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].addr, 0x4000);
+        assert_eq!(regions[0].size, 0x800);
+        assert_eq!(regions[0].wait_states, 2);
+    }
+
     #[test]
     #[should_panic]
     fn bus_test_overlapping_device_panics() {
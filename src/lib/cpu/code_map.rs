@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+pub const PAGE_SHIFT: u32 = 12;
+const PAGE_COUNT: usize = 1 << (29 - PAGE_SHIFT);
+
+fn page(addr: u32) -> u32 {
+    (addr & 0x1fff_ffff) >> PAGE_SHIFT
+}
+
+// Tracks which pages of the physical address space hold translated code, so that stores can tell
+// cheaply whether they might have modified it
+pub struct CodeMap {
+    // One bit per page, set if any block covers it
+    bitmap: Vec<u64>,
+    // Blocks covering each code page, by start address, with blocks spanning pages listed on each
+    // of them
+    pages: HashMap<u32, Vec<u32>>,
+    // Code each block was translated from, as start and end addresses. A block that follows jumps
+    // covers more than one range.
+    ranges: HashMap<u32, Vec<(u32, u32)>>,
+}
+
+impl Default for CodeMap {
+    fn default() -> Self {
+        Self {
+            bitmap: vec![0; PAGE_COUNT / 64],
+            pages: HashMap::new(),
+            ranges: HashMap::new(),
+        }
+    }
+}
+
+impl CodeMap {
+    pub fn contains_code(&self, addr: u32) -> bool {
+        let page = page(addr) as usize;
+        self.bitmap[page / 64] & (1 << (page % 64)) != 0
+    }
+
+    // Records the block at `block` as covering `ranges`, each from a start address up to, but not
+    // including, an end address
+    pub fn insert(&mut self, block: u32, ranges: &[(u32, u32)]) {
+        let block = block & 0x1fff_ffff;
+        self.remove(block);
+
+        let mut covered = Vec::new();
+        for (start, end) in ranges {
+            let len = end.wrapping_sub(*start);
+            if len == 0 {
+                continue;
+            }
+
+            let start = start & 0x1fff_ffff;
+            let end = start + len;
+            covered.push((start, end));
+
+            for page in page(start)..=page(end - 1) {
+                self.bitmap[page as usize / 64] |= 1 << (page % 64);
+                let blocks = self.pages.entry(page).or_default();
+                if !blocks.contains(&block) {
+                    blocks.push(block);
+                }
+            }
+        }
+
+        if !covered.is_empty() {
+            self.ranges.insert(block, covered);
+        }
+    }
+
+    // Forgets every block covering the word at `addr`, returning their start addresses
+    pub fn take_overlapping(&mut self, addr: u32) -> Vec<u32> {
+        if !self.contains_code(addr) {
+            return Vec::new();
+        }
+
+        let word = addr & 0x1fff_fffc;
+        let overlapping: Vec<u32> = self.pages[&page(word)]
+            .iter()
+            .filter(|block| {
+                self.ranges[*block]
+                    .iter()
+                    .any(|(start, end)| *start <= word && word < *end)
+            })
+            .copied()
+            .collect();
+
+        for block in &overlapping {
+            self.remove(*block);
+        }
+
+        overlapping
+    }
+
+    // Forgets the block at `block`, if it is recorded
+    pub fn remove(&mut self, block: u32) {
+        let block = block & 0x1fff_ffff;
+        for (start, end) in self.ranges.remove(&block).unwrap_or_default() {
+            for page in page(start)..=page(end - 1) {
+                self.remove_from_page(page, block);
+            }
+        }
+    }
+
+    fn remove_from_page(&mut self, page: u32, block: u32) {
+        // A page covered by more than one of the block's ranges has already been dealt with
+        let blocks = match self.pages.get_mut(&page) {
+            Some(blocks) => blocks,
+            None => return,
+        };
+        blocks.retain(|b| *b != block);
+
+        if blocks.is_empty() {
+            self.pages.remove(&page);
+            self.bitmap[page as usize / 64] &= !(1 << (page % 64));
+        }
+    }
+
+    // Translated code tests the bitmap directly, so it is cleared in place rather than replaced
+    pub fn clear(&mut self) {
+        self.bitmap.fill(0);
+        self.pages.clear();
+        self.ranges.clear();
+    }
+
+    // The bitmap, as one bit per 4 KB page in u64 words, which stays at the same address for the
+    // life of the map
+    pub fn bitmap_ptr(&self) -> *const u64 {
+        self.bitmap.as_ptr()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CodeMap;
+
+    #[test]
+    fn code_map_test_data_pages() {
+        let mut map = CodeMap::default();
+
+        map.insert(0x8000_1000, &[(0x8000_1000, 0x8000_1020)]);
+
+        assert!(map.contains_code(0x1010));
+        assert!(!map.contains_code(0x2000));
+        assert!(map.take_overlapping(0x8000_2000).is_empty());
+    }
+
+    #[test]
+    fn code_map_test_take_overlapping() {
+        let mut map = CodeMap::default();
+
+        map.insert(0x1000, &[(0x1000, 0x1020)]);
+        map.insert(0x1010, &[(0x1010, 0x1020)]);
+        map.insert(0x1020, &[(0x1020, 0x1040)]);
+
+        let mut taken = map.take_overlapping(0x1012);
+        taken.sort();
+        assert_eq!(taken, vec![0x1000, 0x1010]);
+
+        // Only the block that wasn't overlapped is left
+        assert!(map.take_overlapping(0x1000).is_empty());
+        assert_eq!(map.take_overlapping(0x1020), vec![0x1020]);
+        assert!(!map.contains_code(0x1020));
+    }
+
+    #[test]
+    fn code_map_test_spanning_pages() {
+        let mut map = CodeMap::default();
+
+        map.insert(0x1ff0, &[(0x1ff0, 0x2010)]);
+
+        assert!(map.contains_code(0x1000));
+        assert!(map.contains_code(0x2000));
+        assert_eq!(map.take_overlapping(0x2004), vec![0x1ff0]);
+        assert!(!map.contains_code(0x1000));
+    }
+
+    #[test]
+    fn code_map_test_multiple_ranges() {
+        let mut map = CodeMap::default();
+
+        // A block that jumps from its first page to code on another
+        map.insert(0x1000, &[(0x1000, 0x1010), (0x3000, 0x3008)]);
+        map.insert(0x3000, &[(0x3000, 0x3008)]);
+
+        let mut taken = map.take_overlapping(0x3004);
+        taken.sort();
+        assert_eq!(taken, vec![0x1000, 0x3000]);
+
+        // Every range of the block is forgotten along with it
+        assert!(!map.contains_code(0x1000));
+        assert!(!map.contains_code(0x3000));
+
+        map.insert(0x1000, &[(0x1000, 0x1010), (0x3000, 0x3008)]);
+        map.remove(0x8000_1000);
+        assert!(!map.contains_code(0x3000));
+    }
+}
@@ -1,4 +1,6 @@
-use super::opcode::{MipsBranchSpecial, MipsCopOperation, MipsFunction, MipsOpcode};
+use super::opcode::{
+    MipsBranchSpecial, MipsCop0Command, MipsCopOperation, MipsFunction, MipsOpcode,
+};
 
 #[derive(Debug)]
 pub struct MipsRInstr {
@@ -26,6 +28,20 @@ pub struct MipsCopInstr {
     pub d_reg: u8,
 }
 
+// A coprocessor command (with the CO bit set), whose meaning is defined by the coprocessor
+#[derive(Debug)]
+pub struct MipsCopCmdInstr {
+    pub opcode: MipsOpcode,
+    pub cop: u8,
+    pub command: u32,
+}
+
+impl MipsCopCmdInstr {
+    pub fn cop0_command(&self) -> MipsCop0Command {
+        num::FromPrimitive::from_u32(self.command).unwrap_or(MipsCop0Command::Invalid)
+    }
+}
+
 #[derive(Debug)]
 pub struct MipsCopMemInstr {
     pub opcode: MipsOpcode,
@@ -47,10 +63,70 @@ pub enum MipsInstr {
     IType(MipsIInstr),
     JType(MipsJInstr),
     Cop(MipsCopInstr),
+    CopCmd(MipsCopCmdInstr),
     CopMem(MipsCopMemInstr),
     Invalid,
 }
 
+impl MipsInstr {
+    // Whether the instruction is a branch or jump, and so has a delay slot following it
+    pub fn is_branch(&self) -> bool {
+        match self {
+            MipsInstr::RType(r) => matches!(r.function, MipsFunction::Jr | MipsFunction::Jalr),
+            MipsInstr::IType(i) => matches!(
+                i.opcode,
+                MipsOpcode::RegisterImm
+                    | MipsOpcode::Beq
+                    | MipsOpcode::Bne
+                    | MipsOpcode::Blez
+                    | MipsOpcode::Bgtz
+            ),
+            MipsInstr::JType(_) => true,
+            _ => false,
+        }
+    }
+
+    // Whether the instruction writes its result after a load delay, rather than immediately
+    pub fn has_load_delay(&self) -> bool {
+        match self {
+            MipsInstr::IType(i) => matches!(
+                i.opcode,
+                MipsOpcode::Lb
+                    | MipsOpcode::Lbu
+                    | MipsOpcode::Lh
+                    | MipsOpcode::Lhu
+                    | MipsOpcode::Lw
+                    | MipsOpcode::Lwl
+                    | MipsOpcode::Lwr
+            ),
+            MipsInstr::Cop(c) => matches!(c.operation, MipsCopOperation::MoveFrom),
+            _ => false,
+        }
+    }
+}
+
+// Whether the branch or jump at `pc` leads back to itself, with a NOP in its delay slot, so that
+// it does nothing but spin. Programs spin like this while they wait for an interrupt, and test
+// programs to signal that they are done.
+pub fn mips_is_spin_loop(pc: u32, branch_raw: u32, delay_slot_raw: u32) -> bool {
+    if delay_slot_raw != 0 {
+        return false;
+    }
+
+    match mips_decode(branch_raw) {
+        MipsInstr::IType(i) => {
+            matches!(
+                i.opcode,
+                MipsOpcode::Beq | MipsOpcode::Bne | MipsOpcode::Blez | MipsOpcode::Bgtz
+            ) && i.immediate as i16 == -1
+        }
+        MipsInstr::JType(j) => {
+            matches!(j.opcode, MipsOpcode::J) && j.target << 2 == pc & 0x0fff_ffff
+        }
+        _ => false,
+    }
+}
+
 fn mips_decode_rtype(instr_raw: u32) -> MipsInstr {
     let s_reg = ((instr_raw >> 21) & 0x1f) as u8;
     let t_reg = ((instr_raw >> 16) & 0x1f) as u8;
@@ -113,6 +189,15 @@ fn mips_decode_cop_mem(opcode: MipsOpcode, cop: u8, instr_raw: u32) -> MipsInstr
 }
 
 fn mips_decode_cop(cop: u8, instr_raw: u32) -> MipsInstr {
+    // The CO bit selects a coprocessor command instead of a move
+    if instr_raw & (1 << 25) != 0 {
+        return MipsInstr::CopCmd(MipsCopCmdInstr {
+            opcode: MipsOpcode::CoProc,
+            cop,
+            command: instr_raw & 0x1ff_ffff,
+        });
+    }
+
     let operation = num::FromPrimitive::from_u8(((instr_raw >> 21) & 0x1f) as u8);
 
     if let Some(operation) = operation {
@@ -194,6 +279,14 @@ fn mips_encode_cop(instr: &MipsCopInstr) -> u32 {
     res
 }
 
+fn mips_encode_cop_cmd(instr: &MipsCopCmdInstr) -> u32 {
+    let mut res: u32 = ((instr.opcode as u32) | (instr.cop as u32)) << 26;
+    res |= 1 << 25;
+    res |= instr.command & 0x1ff_ffff;
+
+    res
+}
+
 fn mips_encode_jtype(instr: &MipsJInstr) -> u32 {
     let mut res: u32 = (instr.opcode as u32) << 26;
     res |= instr.target;
@@ -207,6 +300,7 @@ pub fn mips_encode(instr: &MipsInstr) -> Option<u32> {
         MipsInstr::IType(i) => Some(mips_encode_itype(i)),
         MipsInstr::JType(j) => Some(mips_encode_jtype(j)),
         MipsInstr::Cop(c) => Some(mips_encode_cop(c)),
+        MipsInstr::CopCmd(c) => Some(mips_encode_cop_cmd(c)),
         MipsInstr::CopMem(c) => Some(mips_encode_cop_mem(c)),
         MipsInstr::Invalid => None,
     }
@@ -223,6 +317,14 @@ pub fn mips_encode_str(istr: &str, d: u8, s: u8, t: u8, imm: u16, tgt: u32) -> O
         }));
     }
 
+    if let Some(command) = MipsCop0Command::from_str(istr) {
+        return mips_encode(&MipsInstr::CopCmd(MipsCopCmdInstr {
+            opcode: MipsOpcode::CoProc,
+            cop: 0,
+            command: command as u32,
+        }));
+    }
+
     if let Some((op, cop)) = MipsOpcode::cop_mem_from_str(istr) {
         return mips_encode(&MipsInstr::CopMem(MipsCopMemInstr {
             opcode: op,
@@ -233,6 +335,15 @@ pub fn mips_encode_str(istr: &str, d: u8, s: u8, t: u8, imm: u16, tgt: u32) -> O
         }));
     }
 
+    if let Some(special_op) = MipsBranchSpecial::from_str(istr) {
+        return mips_encode(&MipsInstr::IType(MipsIInstr {
+            opcode: MipsOpcode::RegisterImm,
+            s_reg: s,
+            t_reg: special_op as u8,
+            immediate: imm,
+        }));
+    }
+
     if let Some(op) = MipsOpcode::from_str(istr) {
         let instr = match op {
             MipsOpcode::J | MipsOpcode::Jal => MipsInstr::JType(MipsJInstr {
@@ -330,6 +441,18 @@ impl std::fmt::Display for MipsCopInstr {
     }
 }
 
+impl std::fmt::Display for MipsCopCmdInstr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.cop {
+            0 => match self.cop0_command() {
+                MipsCop0Command::Invalid => write!(f, "cop0 {:#x}", self.command),
+                command => command.fmt(f),
+            },
+            _ => write!(f, "cop{} {:#x}", self.cop, self.command),
+        }
+    }
+}
+
 impl std::fmt::Display for MipsCopMemInstr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -353,6 +476,7 @@ impl std::fmt::Display for MipsInstr {
             MipsInstr::IType(i) => i.fmt(f),
             MipsInstr::JType(j) => j.fmt(f),
             MipsInstr::Cop(c) => c.fmt(f),
+            MipsInstr::CopCmd(c) => c.fmt(f),
             MipsInstr::CopMem(c) => c.fmt(f),
             MipsInstr::Invalid => write!(f, "INVALID"),
         }
@@ -0,0 +1,107 @@
+// Model of the R3000A's 4 KB instruction cache: 256 direct-mapped lines of four words each.
+// Only which lines are resident is tracked. Instructions are always read from the bus, so guest
+// code that relies on executing stale cache contents won't behave as on hardware, but fetch timing
+// and the BIOS's flush sequence do.
+
+const LINE_COUNT: usize = 256;
+const LINE_SIZE: u32 = 16;
+
+#[derive(Clone, Copy, Default)]
+struct Line {
+    // Physical address of the start of the line
+    tag: u32,
+    valid: bool,
+}
+
+pub struct ICache {
+    lines: [Line; LINE_COUNT],
+}
+
+impl Default for ICache {
+    fn default() -> Self {
+        Self {
+            lines: [Line::default(); LINE_COUNT],
+        }
+    }
+}
+
+impl std::fmt::Debug for ICache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let valid_lines = self.lines.iter().filter(|l| l.valid).count();
+        f.debug_struct("ICache")
+            .field("valid_lines", &valid_lines)
+            .finish()
+    }
+}
+
+fn line_index(addr: u32) -> usize {
+    ((addr / LINE_SIZE) as usize) % LINE_COUNT
+}
+
+fn line_tag(addr: u32) -> u32 {
+    addr & 0x1fff_fff0
+}
+
+impl ICache {
+    // Only KUSEG and KSEG0 are cached. KSEG1, which the BIOS runs from, bypasses the cache.
+    pub fn is_cacheable(addr: u32) -> bool {
+        addr < 0xa000_0000
+    }
+
+    // Looks up the instruction at `addr`, filling its line on a miss.
+    // Returns whether it was a hit.
+    pub fn fetch(&mut self, addr: u32) -> bool {
+        let line = &mut self.lines[line_index(addr)];
+        let tag = line_tag(addr);
+
+        if line.valid && line.tag == tag {
+            return true;
+        }
+
+        line.tag = tag;
+        line.valid = true;
+        false
+    }
+
+    // Handles a store while the cache is isolated from memory. The BIOS flushes the cache by
+    // storing to each line in turn, so the line the store falls on is invalidated.
+    pub fn isolated_store(&mut self, addr: u32) {
+        self.lines[line_index(addr)].valid = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ICache;
+
+    #[test]
+    fn icache_test_fetch_hit_miss() {
+        let mut cache = ICache::default();
+
+        assert!(!cache.fetch(0x8000_1000));
+        assert!(cache.fetch(0x8000_100c));
+        // Same physical address through KUSEG
+        assert!(cache.fetch(0x0000_1004));
+        // Next line
+        assert!(!cache.fetch(0x8000_1010));
+        // Same line index, different tag
+        assert!(!cache.fetch(0x8000_2000));
+        assert!(!cache.fetch(0x8000_1000));
+    }
+
+    #[test]
+    fn icache_test_isolated_store_invalidates() {
+        let mut cache = ICache::default();
+
+        cache.fetch(0x8001_0020);
+        // The flush loop stores to the low addresses, which index the same lines
+        cache.isolated_store(0x20);
+        assert!(!cache.fetch(0x8001_0020));
+    }
+
+    #[test]
+    fn icache_test_uncached_segment() {
+        assert!(ICache::is_cacheable(0x8000_0000));
+        assert!(!ICache::is_cacheable(0xbfc0_0000));
+    }
+}
@@ -1,4 +1,12 @@
-use super::{BusType, CpuState, MipsIInstr};
+use super::{cop0, BusType, CpuState, InterpretResult, MipsBranchSpecial, MipsIInstr};
+
+fn branch_target(instr: &MipsIInstr, state: &CpuState, taken: bool) -> u32 {
+    if taken {
+        (state.pc as i32 + (instr.immediate as i16 as i32) * 4 + 4) as u32
+    } else {
+        state.pc + 8
+    }
+}
 
 pub(super) fn interpret_bne(
     instr: &MipsIInstr,
@@ -9,13 +17,7 @@ pub(super) fn interpret_bne(
     let s_val = state.get_reg_val(instr.s_reg);
     let t_val = state.get_reg_val(instr.t_reg);
 
-    let target = if s_val != t_val {
-        (state.pc as i32 + (instr.immediate as i16 as i32) * 4 + 4) as u32
-    } else {
-        state.pc + 8
-    };
-
-    target
+    branch_target(instr, state, s_val != t_val)
 }
 
 pub(super) fn interpret_beq(
@@ -27,13 +29,7 @@ pub(super) fn interpret_beq(
     let s_val = state.get_reg_val(instr.s_reg);
     let t_val = state.get_reg_val(instr.t_reg);
 
-    let target = if s_val == t_val {
-        (state.pc as i32 + (instr.immediate as i16 as i32) * 4 + 4) as u32
-    } else {
-        state.pc + 8
-    };
-
-    target
+    branch_target(instr, state, s_val == t_val)
 }
 
 pub(super) fn interpret_bgtz(
@@ -42,13 +38,137 @@ pub(super) fn interpret_bgtz(
     state: &mut CpuState,
     _next_pc: &u32,
 ) -> u32 {
-    let s_val = state.get_reg_val(instr.s_reg);
+    let s_val = state.get_reg_val(instr.s_reg) as i32;
 
-    let target = if s_val > 0 {
-        (state.pc as i32 + (instr.immediate as i16 as i32) * 4 + 4) as u32
-    } else {
-        state.pc + 8
+    branch_target(instr, state, s_val > 0)
+}
+
+pub(super) fn interpret_blez(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    _next_pc: &u32,
+) -> u32 {
+    let s_val = state.get_reg_val(instr.s_reg) as i32;
+
+    branch_target(instr, state, s_val <= 0)
+}
+
+pub(super) fn interpret_special_branch(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    _next_pc: &u32,
+) -> InterpretResult {
+    let special_op = num::FromPrimitive::from_u8(instr.t_reg).unwrap_or(MipsBranchSpecial::Invalid);
+    let s_val = state.get_reg_val(instr.s_reg) as i32;
+
+    let (taken, link) = match special_op {
+        MipsBranchSpecial::Bltz => (s_val < 0, false),
+        MipsBranchSpecial::Bgez => (s_val >= 0, false),
+        MipsBranchSpecial::Bltzal => (s_val < 0, true),
+        MipsBranchSpecial::Bgezal => (s_val >= 0, true),
+        MipsBranchSpecial::Invalid => return Err(cop0::ExceptionCause::ReservedInstruction),
     };
 
-    target
+    // The link register is written regardless of whether the branch is taken
+    let target = branch_target(instr, state, taken);
+    if link {
+        state.set_reg_val(31, state.pc + 8);
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn interpret_test_blez_taken() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, -1i16 as u16, 0);
+        th.push_instr("blez", 0, 1, 0, 2, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.push_instr("addiu", 0, 0, 2, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 0);
+    }
+
+    #[test]
+    fn interpret_test_bgtz_signed() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, -1i16 as u16, 0);
+        th.push_instr("bgtz", 0, 1, 0, 2, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.push_instr("addiu", 0, 0, 2, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 1);
+    }
+
+    #[test]
+    fn interpret_test_bltzal_not_taken_links() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("bltzal", 0, 0, 0, 2, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.push_instr("addiu", 0, 0, 2, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 1);
+        assert_eq!(state.gpr[30], 0x1008);
+    }
+
+    #[test]
+    fn interpret_test_bgez_taken() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("bgez", 0, 0, 0, 2, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.push_instr("addiu", 0, 0, 2, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 0);
+        assert_eq!(state.gpr[30], 0);
+    }
+
+    #[test]
+    fn interpret_test_regimm_reserved() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        // A REGIMM encoding whose t register field isn't one of the branches
+        th.push_raw(0x0402_0002);
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
 }
@@ -0,0 +1,304 @@
+use super::{
+    cop0, BusType, CpuState, InterpretResult, MipsCop0Command, MipsCopCmdInstr, MipsCopInstr,
+    MipsCopMemInstr, MipsCopOperation,
+};
+
+fn interpret_cop0_mtc(
+    instr: &MipsCopInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    state.cop0_reg[instr.d_reg as usize] = state.get_reg_val(instr.t_reg);
+    next_pc + 4
+}
+
+fn interpret_cop0_mfc(
+    instr: &MipsCopInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    // Like loads, the result isn't available to the following instruction
+    state.stage_load(instr.t_reg, state.cop0_reg[instr.d_reg as usize]);
+    next_pc + 4
+}
+
+fn interpret_cop0_instr(
+    instr: &MipsCopInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    match instr.operation {
+        MipsCopOperation::MoveTo => Ok(interpret_cop0_mtc(instr, bus, state, next_pc)),
+        MipsCopOperation::MoveFrom => Ok(interpret_cop0_mfc(instr, bus, state, next_pc)),
+        // COP0 has no control registers
+        _ => Err(cop0::ExceptionCause::ReservedInstruction),
+    }
+}
+
+// Raises the exception for an instruction for a coprocessor other than COP0
+fn interpret_missing_cop(cop: u8, state: &mut CpuState) -> InterpretResult {
+    state.check_cop_usable(cop)?;
+
+    // FIXME: COP2 should be handled once the GTE exists
+    Err(cop0::ExceptionCause::ReservedInstruction)
+}
+
+pub(super) fn interpret_cop_instr(
+    instr: &MipsCopInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    match instr.cop {
+        0 => interpret_cop0_instr(instr, bus, state, next_pc),
+        cop => interpret_missing_cop(cop, state),
+    }
+}
+
+fn interpret_cop0_rfe(
+    _instr: &MipsCopCmdInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    // Pop the KU/IE mode stack, leaving the old mode bits in place
+    let sr = state.cop0_reg[cop0::Register::Sr as usize];
+    state.cop0_reg[cop0::Register::Sr as usize] = (sr & !0xf) | ((sr >> 2) & 0xf);
+    next_pc + 4
+}
+
+fn interpret_cop0_command(
+    instr: &MipsCopCmdInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    match instr.cop0_command() {
+        MipsCop0Command::Rfe => Ok(interpret_cop0_rfe(instr, bus, state, next_pc)),
+        // The PS1 has no TLB, so RFE is the only command
+        MipsCop0Command::Invalid => Err(cop0::ExceptionCause::ReservedInstruction),
+    }
+}
+
+pub(super) fn interpret_cop_command(
+    instr: &MipsCopCmdInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    match instr.cop {
+        0 => interpret_cop0_command(instr, bus, state, next_pc),
+        cop => interpret_missing_cop(cop, state),
+    }
+}
+
+pub(super) fn interpret_cop_mem(
+    instr: &MipsCopMemInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    _next_pc: &u32,
+) -> InterpretResult {
+    match instr.cop {
+        // There are no COP0 loads or stores
+        0 => Err(cop0::ExceptionCause::ReservedInstruction),
+        cop => interpret_missing_cop(cop, state),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn interpret_test_mtc0_mfc0() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let value = 10;
+
+        th.load32(1, value);
+
+        th.push_instr("mtc0", 1, 0, 1, 0, 0);
+        th.push_instr("mfc0", 1, 0, 2, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.cop0_reg[1], value);
+        assert_eq!(state.gpr[1], value);
+    }
+
+    #[test]
+    fn interpret_test_rfe() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 0b11_0110);
+        th.push_instr("mtc0", crate::cpu::cop0::Register::Sr as u8, 0, 1, 0, 0);
+        th.push_instr("rfe", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Sr as usize],
+            0b11_1101
+        );
+    }
+
+    #[test]
+    fn interpret_test_syscall_bev() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 1 << 22);
+        th.push_instr("mtc0", crate::cpu::cop0::Register::Sr as u8, 0, 1, 0, 0);
+        th.push_instr("syscall", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0xbfc0_0184);
+    }
+
+    #[test]
+    fn interpret_test_cop2_unusable() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.push_instr("mtc2", 1, 0, 1, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            (2 << 28) | (0xb << 2)
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+    }
+
+    #[test]
+    fn interpret_test_cop2_usable() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 1 << 30);
+        th.push_instr("mtc0", crate::cpu::cop0::Register::Sr as u8, 0, 1, 0, 0);
+        th.push_instr("mtc2", 1, 0, 1, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        // There is no GTE yet, but the access must not raise CopUnusable
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
+
+    #[test]
+    fn interpret_test_lwc1_unusable() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 0xf000_0000);
+        th.push_instr("mtc0", crate::cpu::cop0::Register::Sr as u8, 0, 1, 0, 0);
+        th.push_instr("lwc1", 0, 0, 1, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            (1 << 28) | (0xb << 2)
+        );
+    }
+
+    #[test]
+    fn interpret_test_interrupt() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        state.interrupt_lines().assert(0);
+
+        th.map_exception_vector();
+        th.load32(1, (1 << 10) | 1);
+        th.push_instr("mtc0", crate::cpu::cop0::Register::Sr as u8, 0, 1, 0, 0);
+        th.push_instr("addiu", 0, 0, 2, 10, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 0);
+        assert_eq!(state.pc, 0x8000_0084);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            1 << 10
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x100c
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Sr as usize],
+            (1 << 10) | 0b100
+        );
+    }
+
+    #[test]
+    fn interpret_test_interrupt_masked() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        state.interrupt_lines().assert(1);
+
+        th.map_exception_vector();
+        th.load32(1, (1 << 10) | 1);
+        th.push_instr("mtc0", crate::cpu::cop0::Register::Sr as u8, 0, 1, 0, 0);
+        th.push_instr("addiu", 0, 0, 2, 10, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 10);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            1 << 11
+        );
+    }
+
+    #[test]
+    fn interpret_test_cop0_reserved_command() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        // TLBR, which the PS1 has no TLB for
+        th.push_raw(0x4200_0001);
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
+}
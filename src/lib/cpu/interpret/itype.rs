@@ -1 +1,230 @@
-use super::{CpuState, BusType, MipsInstr, MipsOpcode};
+use super::{branch, mem};
+use super::{cop0, BusType, CpuState, InterpretResult, MipsIInstr, MipsOpcode};
+
+fn interpret_addiu(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = (state.get_reg_val(instr.s_reg) as i32).wrapping_add(instr.immediate as i16 as i32);
+    state.set_reg_val(instr.t_reg, val as u32);
+
+    next_pc + 4
+}
+
+fn interpret_addi(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    // The destination is left unchanged on overflow
+    let val = (state.get_reg_val(instr.s_reg) as i32)
+        .checked_add(instr.immediate as i16 as i32)
+        .ok_or(cop0::ExceptionCause::Overflow)?;
+    state.set_reg_val(instr.t_reg, val as u32);
+
+    Ok(next_pc + 4)
+}
+
+fn interpret_sltiu(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    // The immediate is sign extended, then compared as unsigned
+    let s_val = state.get_reg_val(instr.s_reg);
+    let val = if s_val < instr.immediate as i16 as u32 {
+        1
+    } else {
+        0
+    };
+
+    state.set_reg_val(instr.t_reg, val as u32);
+
+    next_pc + 4
+}
+
+fn interpret_slti(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let s_val = state.get_reg_val(instr.s_reg) as i32;
+    let val = if s_val < instr.immediate as i16 as i32 {
+        1
+    } else {
+        0
+    };
+
+    state.set_reg_val(instr.t_reg, val as u32);
+
+    next_pc + 4
+}
+
+fn interpret_andi(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = state.get_reg_val(instr.s_reg) & instr.immediate as u32;
+    state.set_reg_val(instr.t_reg, val);
+
+    next_pc + 4
+}
+
+fn interpret_ori(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = state.get_reg_val(instr.s_reg) | instr.immediate as u32;
+    state.set_reg_val(instr.t_reg, val);
+
+    next_pc + 4
+}
+
+fn interpret_xori(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = state.get_reg_val(instr.s_reg) ^ instr.immediate as u32;
+    state.set_reg_val(instr.t_reg, val);
+
+    next_pc + 4
+}
+
+fn interpret_lui(
+    instr: &MipsIInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = (instr.immediate as u32) << 16;
+    state.set_reg_val(instr.t_reg, val);
+
+    next_pc + 4
+}
+
+pub(super) fn interpret_i_instr(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    match instr.opcode {
+        MipsOpcode::AddI => interpret_addi(instr, bus, state, next_pc),
+        MipsOpcode::AddIU => Ok(interpret_addiu(instr, bus, state, next_pc)),
+        MipsOpcode::SltIU => Ok(interpret_sltiu(instr, bus, state, next_pc)),
+        MipsOpcode::SltI => Ok(interpret_slti(instr, bus, state, next_pc)),
+        MipsOpcode::AndI => Ok(interpret_andi(instr, bus, state, next_pc)),
+        MipsOpcode::OrI => Ok(interpret_ori(instr, bus, state, next_pc)),
+        MipsOpcode::XorI => Ok(interpret_xori(instr, bus, state, next_pc)),
+        MipsOpcode::Lui => Ok(interpret_lui(instr, bus, state, next_pc)),
+        MipsOpcode::Lb => mem::interpret_lb(instr, bus, state, next_pc),
+        MipsOpcode::Lbu => mem::interpret_lbu(instr, bus, state, next_pc),
+        MipsOpcode::Lh => mem::interpret_lh(instr, bus, state, next_pc),
+        MipsOpcode::Lhu => mem::interpret_lhu(instr, bus, state, next_pc),
+        MipsOpcode::Lw => mem::interpret_lw(instr, bus, state, next_pc),
+        MipsOpcode::Lwl => mem::interpret_lwl(instr, bus, state, next_pc),
+        MipsOpcode::Lwr => mem::interpret_lwr(instr, bus, state, next_pc),
+        MipsOpcode::Sb => mem::interpret_sb(instr, bus, state, next_pc),
+        MipsOpcode::Sh => mem::interpret_sh(instr, bus, state, next_pc),
+        MipsOpcode::Sw => mem::interpret_sw(instr, bus, state, next_pc),
+        MipsOpcode::Swl => mem::interpret_swl(instr, bus, state, next_pc),
+        MipsOpcode::Swr => mem::interpret_swr(instr, bus, state, next_pc),
+        MipsOpcode::RegisterImm => branch::interpret_special_branch(instr, bus, state, next_pc),
+        MipsOpcode::Bne => Ok(branch::interpret_bne(instr, bus, state, next_pc)),
+        MipsOpcode::Beq => Ok(branch::interpret_beq(instr, bus, state, next_pc)),
+        MipsOpcode::Blez => Ok(branch::interpret_blez(instr, bus, state, next_pc)),
+        MipsOpcode::Bgtz => Ok(branch::interpret_bgtz(instr, bus, state, next_pc)),
+        _ => panic!("Not implemented: {} @ {:08x}", instr.opcode, state.pc),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn interpret_test_andi() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("ori", 0, 0, 1, 42, 0);
+        th.push_instr("andi", 0, 1, 2, 0xf, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 42 & 0xf);
+    }
+
+    #[test]
+    fn interpret_test_xori() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("ori", 0, 0, 1, 42, 0);
+        th.push_instr("xori", 0, 1, 2, 0xff, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 42 ^ 0xff);
+    }
+
+    #[test]
+    fn interpret_test_addi() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("ori", 0, 0, 1, 42, 0);
+        th.push_instr("addi", 0, 1, 2, -50i16 as u16, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], -8i32 as u32);
+    }
+
+    #[test]
+    fn interpret_test_addi_overflow() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 0x7fff_fff0);
+        th.push_instr("addi", 0, 1, 1, 0x10, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0x7fff_fff0);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xc << 2
+        );
+    }
+
+    #[test]
+    fn interpret_test_sltiu_sign_extends() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 0x8000_0000);
+        th.push_instr("sltiu", 0, 1, 2, -1i16 as u16, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 1);
+    }
+}
@@ -1,24 +1,38 @@
 use super::{BusType, CpuState, MipsJInstr, MipsOpcode};
 
-fn interpret_j(instr: &MipsJInstr, _bus: &mut BusType, _state: &mut CpuState) -> u32 {
-    let target = instr.target;
-    target << 2
+// Jumps keep the upper bits of the delay slot address
+fn jump_target(instr: &MipsJInstr, next_pc: &u32) -> u32 {
+    (next_pc & 0xf000_0000) | (instr.target << 2)
 }
 
-fn interpret_jal(instr: &MipsJInstr, _bus: &mut BusType, state: &mut CpuState) -> u32 {
-    let target = instr.target;
-    state.gpr[30] = state.pc + 8;
-    target << 2
+fn interpret_j(
+    instr: &MipsJInstr,
+    _bus: &mut BusType,
+    _state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    jump_target(instr, next_pc)
+}
+
+fn interpret_jal(
+    instr: &MipsJInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    state.set_reg_val(31, state.pc + 8);
+    jump_target(instr, next_pc)
 }
 
 pub(super) fn interpret_j_instr(
     instr: &MipsJInstr,
     bus: &mut BusType,
     state: &mut CpuState,
+    next_pc: &u32,
 ) -> u32 {
     match instr.opcode {
-        MipsOpcode::J => interpret_j(instr, bus, state),
-        MipsOpcode::Jal => interpret_jal(instr, bus, state),
+        MipsOpcode::J => interpret_j(instr, bus, state, next_pc),
+        MipsOpcode::Jal => interpret_jal(instr, bus, state, next_pc),
         _ => panic!("Not J type instruction: {}", instr.opcode),
     }
 }
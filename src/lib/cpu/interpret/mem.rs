@@ -1,27 +1,29 @@
-use super::{BusType, CpuState, MipsIInstr};
+use super::{cop0, BusType, CpuState, InterpretResult, MipsIInstr};
 use crate::cpu::bus::{BusDevice, SizedReadResult};
 
+fn decode_vaddr(instr: &MipsIInstr, state: &CpuState) -> u32 {
+    let base = state.get_reg_val(instr.s_reg);
+    (base as i32).wrapping_add(instr.immediate as i16 as i32) as u32
+}
+
 fn interpret_mem_read(
     instr: &MipsIInstr,
     size: u32,
     bus: &mut BusType,
     state: &mut CpuState,
     sign_extend: bool,
-) {
-    let base = if instr.s_reg == 0 {
-        0
-    } else {
-        state.gpr[(instr.s_reg - 1) as usize]
-    };
-    let addr = (base as i32 + instr.immediate as i16 as i32) as u32;
+) -> Result<(), cop0::ExceptionCause> {
+    let addr = decode_vaddr(instr, state);
     let reg = instr.t_reg;
 
-    let read_result = bus.read(addr, size).unwrap();
-    if reg == 0 {
-        return;
-    }
+    state.check_alignment(addr, size, false)?;
 
-    state.gpr[(reg - 1) as usize] = match read_result {
+    let read_result = bus
+        .read(addr, size)
+        .map_err(|e| state.bus_error(e, false))?;
+    state.add_cycles(bus.wait_states(addr, size) as u64);
+
+    let val = match read_result {
         SizedReadResult::Byte(b) => {
             if sign_extend {
                 b as i8 as u32
@@ -38,6 +40,9 @@ fn interpret_mem_read(
         }
         SizedReadResult::Dword(d) => d,
     };
+
+    state.stage_load(reg, val);
+    Ok(())
 }
 
 pub(super) fn interpret_lb(
@@ -45,9 +50,9 @@ pub(super) fn interpret_lb(
     bus: &mut BusType,
     state: &mut CpuState,
     next_pc: &u32,
-) -> u32 {
-    interpret_mem_read(instr, 8, bus, state, true);
-    next_pc + 4
+) -> InterpretResult {
+    interpret_mem_read(instr, 8, bus, state, true)?;
+    Ok(next_pc + 4)
 }
 
 pub(super) fn interpret_lbu(
@@ -55,9 +60,29 @@ pub(super) fn interpret_lbu(
     bus: &mut BusType,
     state: &mut CpuState,
     next_pc: &u32,
-) -> u32 {
-    interpret_mem_read(instr, 8, bus, state, false);
-    next_pc + 4
+) -> InterpretResult {
+    interpret_mem_read(instr, 8, bus, state, false)?;
+    Ok(next_pc + 4)
+}
+
+pub(super) fn interpret_lh(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    interpret_mem_read(instr, 16, bus, state, true)?;
+    Ok(next_pc + 4)
+}
+
+pub(super) fn interpret_lhu(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    interpret_mem_read(instr, 16, bus, state, false)?;
+    Ok(next_pc + 4)
 }
 
 pub(super) fn interpret_lw(
@@ -65,27 +90,92 @@ pub(super) fn interpret_lw(
     bus: &mut BusType,
     state: &mut CpuState,
     next_pc: &u32,
-) -> u32 {
-    interpret_mem_read(instr, 32, bus, state, false);
-    next_pc + 4
+) -> InterpretResult {
+    interpret_mem_read(instr, 32, bus, state, false)?;
+    Ok(next_pc + 4)
 }
 
-fn interpret_mem_write(instr: &MipsIInstr, size: u32, bus: &mut BusType, state: &mut CpuState) {
-    let base = if instr.s_reg == 0 {
-        0
+fn read_aligned_word(
+    addr: u32,
+    bus: &mut BusType,
+    state: &mut CpuState,
+) -> Result<u32, cop0::ExceptionCause> {
+    match bus
+        .read(addr & 0xffff_fffc, 32)
+        .map_err(|e| state.bus_error(e, false))?
+    {
+        SizedReadResult::Dword(d) => Ok(d),
+        r => panic!("Read size of 32 didn't return dword, instead have {:?}", r),
+    }
+}
+
+fn interpret_unaligned_load(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    left: bool,
+) -> Result<(), cop0::ExceptionCause> {
+    let addr = decode_vaddr(instr, state);
+    let mem_val = read_aligned_word(addr, bus, state)?;
+    state.add_cycles(bus.wait_states(addr, 32) as u64);
+
+    // If the previous instruction was a load to the same register, merge with its value rather
+    // than waiting for the load delay
+    let curr_val = if state.load_delay_register == instr.t_reg as u32 {
+        state.load_delay_register_value
     } else {
-        state.gpr[(instr.s_reg - 1) as usize]
+        state.get_reg_val(instr.t_reg)
     };
-    let addr = (base as i32 + instr.immediate as i16 as i32) as u32;
-    let reg = instr.t_reg;
 
-    let value = if reg == 0 {
-        0
+    let alignment = (addr & 0x3) * 8;
+    let inv_alignment = 24 - alignment;
+
+    let new_val = if left {
+        (curr_val & (0x00ff_ffff >> alignment)) | (mem_val << inv_alignment)
     } else {
-        state.gpr[(reg - 1) as usize]
+        (curr_val & (0xffff_ff00 << inv_alignment)) | (mem_val >> alignment)
     };
 
-    bus.write(addr, size, value).unwrap();
+    state.stage_load(instr.t_reg, new_val);
+    Ok(())
+}
+
+pub(super) fn interpret_lwl(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    interpret_unaligned_load(instr, bus, state, true)?;
+    Ok(next_pc + 4)
+}
+
+pub(super) fn interpret_lwr(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    interpret_unaligned_load(instr, bus, state, false)?;
+    Ok(next_pc + 4)
+}
+
+fn interpret_mem_write(
+    instr: &MipsIInstr,
+    size: u32,
+    bus: &mut BusType,
+    state: &mut CpuState,
+) -> Result<(), cop0::ExceptionCause> {
+    let addr = decode_vaddr(instr, state);
+    state.check_alignment(addr, size, true)?;
+
+    let value = state.get_reg_val(instr.t_reg);
+    if state.store_isolated(addr) {
+        return Ok(());
+    }
+
+    bus.write(addr, size, value)
+        .map_err(|e| state.bus_error(e, false))
 }
 
 pub(super) fn interpret_sw(
@@ -93,9 +183,19 @@ pub(super) fn interpret_sw(
     bus: &mut BusType,
     state: &mut CpuState,
     next_pc: &u32,
-) -> u32 {
-    interpret_mem_write(instr, 32, bus, state);
-    next_pc + 4
+) -> InterpretResult {
+    interpret_mem_write(instr, 32, bus, state)?;
+    Ok(next_pc + 4)
+}
+
+pub(super) fn interpret_sh(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    interpret_mem_write(instr, 16, bus, state)?;
+    Ok(next_pc + 4)
 }
 
 pub(super) fn interpret_sb(
@@ -103,7 +203,324 @@ pub(super) fn interpret_sb(
     bus: &mut BusType,
     state: &mut CpuState,
     next_pc: &u32,
-) -> u32 {
-    interpret_mem_write(instr, 8, bus, state);
-    next_pc + 4
+) -> InterpretResult {
+    interpret_mem_write(instr, 8, bus, state)?;
+    Ok(next_pc + 4)
+}
+
+fn interpret_unaligned_store(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    left: bool,
+) -> Result<(), cop0::ExceptionCause> {
+    let addr = decode_vaddr(instr, state);
+    if state.store_isolated(addr) {
+        return Ok(());
+    }
+
+    let mem_val = read_aligned_word(addr, bus, state)?;
+    let source_val = state.get_reg_val(instr.t_reg);
+
+    let alignment = (addr & 0x3) * 8;
+    let inv_alignment = 24 - alignment;
+
+    let new_val = if left {
+        (mem_val & (0xffff_ff00 << alignment)) | (source_val >> inv_alignment)
+    } else {
+        (mem_val & (0x00ff_ffff >> inv_alignment)) | (source_val << alignment)
+    };
+
+    bus.write(addr & 0xffff_fffc, 32, new_val)
+        .map_err(|e| state.bus_error(e, false))
+}
+
+pub(super) fn interpret_swl(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    interpret_unaligned_store(instr, bus, state, true)?;
+    Ok(next_pc + 4)
+}
+
+pub(super) fn interpret_swr(
+    instr: &MipsIInstr,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    interpret_unaligned_store(instr, bus, state, false)?;
+    Ok(next_pc + 4)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn interpret_test_sh_lh() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let addr = 0x1400;
+        let val = -10;
+
+        th.push_instr("addiu", 0, 0, 1, addr, 0);
+        th.push_instr("addiu", 0, 0, 2, val as u16, 0);
+        th.push_instr("sh", 0, 1, 2, 0, 0);
+        th.push_instr("lh", 0, 1, 3, 0, 0);
+        th.push_instr("lhu", 0, 1, 4, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], val as u32);
+        assert_eq!(state.gpr[3], val as u16 as u32);
+    }
+
+    #[test]
+    fn interpret_test_load_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let addr = 0x1400;
+        let val = 42;
+        let delay_imm = 10;
+
+        th.push_instr("addiu", 0, 0, 1, addr, 0);
+        th.push_instr("addiu", 0, 0, 2, val as u16, 0);
+        th.load32(3, delay_imm);
+        th.push_instr("sw", 0, 1, 2, 0, 0);
+        th.push_instr("lw", 0, 1, 3, 0, 0);
+        th.push_instr("addu", 4, 3, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], val);
+        assert_eq!(state.gpr[3], delay_imm);
+    }
+
+    #[test]
+    fn interpret_test_load_delay_slot_overwritten() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let addr = 0x1400;
+
+        th.push_instr("addiu", 0, 0, 1, addr, 0);
+        th.push_instr("addiu", 0, 0, 2, 42, 0);
+        th.push_instr("sw", 0, 1, 2, 0, 0);
+        th.push_instr("lw", 0, 1, 3, 0, 0);
+        th.push_instr("addiu", 0, 0, 3, 10, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 10);
+    }
+
+    #[test]
+    fn interpret_test_load_delay_slot_same_register() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 5, 0x1400, 0);
+        th.push_instr("addiu", 0, 0, 6, 42, 0);
+        th.push_instr("sw", 0, 5, 6, 0, 0);
+        th.push_instr("addiu", 0, 0, 6, 43, 0);
+        th.push_instr("sw", 0, 5, 6, 4, 0);
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.push_instr("lw", 0, 5, 1, 0, 0);
+        th.push_instr("lw", 0, 5, 1, 4, 0);
+        th.push_instr("addu", 2, 1, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        // The second load replaces the first before it is written back, so the first is never seen
+        assert_eq!(state.gpr[1], 10);
+        assert_eq!(state.gpr[0], 43);
+    }
+
+    #[test]
+    fn interpret_test_lwr_lwl() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let addr = 0x1400;
+
+        th.load32(10, 0x11223344);
+        th.load32(11, 0x55667788);
+
+        th.push_instr("addiu", 0, 0, 1, addr, 0);
+        th.push_instr("sw", 0, 1, 10, 0, 0);
+        th.push_instr("sw", 0, 1, 11, 4, 0);
+
+        th.push_instr("lwl", 0, 1, 3, 4, 0);
+        th.push_instr("lwr", 0, 1, 3, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 0x88112233);
+    }
+
+    #[test]
+    fn interpret_test_swr_swl() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let addr = 0x1400;
+
+        th.load32(10, 0xdeadbeef);
+
+        th.push_instr("addiu", 0, 0, 1, addr, 0);
+        th.push_instr("swl", 0, 1, 10, 1, 0);
+        th.push_instr("swr", 0, 1, 10, 6, 0);
+
+        th.push_instr("lw", 0, 1, 2, 0, 0);
+        th.push_instr("lw", 0, 1, 3, 4, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 0x0000_dead);
+        assert_eq!(state.gpr[2], 0xbeef_0000);
+    }
+
+    #[test]
+    fn interpret_test_load_delay_slot_faulting_load() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.push_handler_instr("addu", 3, 1, 0, 0, 0);
+        th.push_instr("lw", 0, 0, 1, 0x1000, 0);
+        th.push_instr("lw", 0, 0, 2, 0x1001, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        // The first load is written back even though the second faults, so the handler sees it
+        let first_instr = crate::cpu::decode::mips_encode_str("lw", 0, 0, 1, 0x1000, 0).unwrap();
+        assert_eq!(state.gpr[0], first_instr);
+        assert_eq!(state.gpr[2], first_instr);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x4 << 2
+        );
+    }
+
+    #[test]
+    fn interpret_test_lw_misaligned() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.push_instr("addiu", 0, 0, 1, 0x1402, 0);
+        th.push_instr("lw", 0, 1, 2, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 0);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x4 << 2
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::BadVaddr as usize],
+            0x1402
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1004
+        );
+    }
+
+    #[test]
+    fn interpret_test_sh_misaligned() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.push_instr("addiu", 0, 0, 1, 0x1400, 0);
+        th.push_instr("addiu", 0, 0, 2, 42, 0);
+        th.push_instr("sh", 0, 1, 2, 1, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x5 << 2
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::BadVaddr as usize],
+            0x1401
+        );
+    }
+
+    #[test]
+    fn interpret_test_lw_unmapped() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 0x0800_0000);
+        th.push_instr("lw", 0, 1, 2, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x7 << 2
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1008
+        );
+    }
+
+    #[test]
+    fn interpret_test_sw_unmapped_abort() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+        state.set_bus_error_policy(crate::cpu::BusErrorPolicy::Abort);
+
+        th.map_exception_vector();
+        th.load32(1, 0x0800_0000);
+        th.push_instr("sw", 0, 1, 1, 0, 0);
+        th.finish_loop();
+
+        assert!(th.execute_interpreter(&mut state).is_err());
+    }
+
+    #[test]
+    fn interpret_test_sw_cache_isolated() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let sr = crate::cpu::cop0::Register::Sr as u8;
+
+        th.load32(1, 1 << 16);
+        th.push_instr("addiu", 0, 0, 2, 0x1400, 0);
+        th.push_instr("addiu", 0, 0, 3, 42, 0);
+        th.push_instr("mtc0", sr, 0, 1, 0, 0);
+        th.push_instr("sw", 0, 2, 3, 0, 0);
+        th.push_instr("mtc0", sr, 0, 0, 0, 0);
+        th.push_instr("lw", 0, 2, 4, 0, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        // The store went to the cache rather than memory
+        assert_eq!(state.gpr[3], 0);
+        assert!(state.take_icache_flush());
+    }
 }
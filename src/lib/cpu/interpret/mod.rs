@@ -1,129 +1,95 @@
+use super::backend::{spins_at, CpuBackend, StepResult};
 use super::bus::{BusDevice, SizedReadResult};
 use super::bus_vec::VecBus;
-use super::decode::{MipsIInstr, MipsInstr, MipsJInstr, MipsRInstr};
-use super::opcode::{MipsFunction, MipsOpcode};
-use super::CpuState;
+use super::decode::{
+    MipsCopCmdInstr, MipsCopInstr, MipsCopMemInstr, MipsIInstr, MipsInstr, MipsJInstr, MipsRInstr,
+};
+use super::opcode::{
+    MipsBranchSpecial, MipsCop0Command, MipsCopOperation, MipsFunction, MipsOpcode,
+};
+use super::{cop0, CpuState};
 
 type BusType = VecBus;
 
+// Result of executing a single instruction: the address of the instruction to execute after the
+// one following this (to account for the delay slot), or the exception that was raised.
+type InterpretResult = Result<u32, cop0::ExceptionCause>;
+
 mod branch;
+mod cop;
+mod itype;
 mod jtype;
 mod mem;
 mod mult;
 mod rtype;
 
-fn interpret_addiu(
-    instr: &MipsIInstr,
-    _bus: &mut BusType,
-    state: &mut CpuState,
-    next_pc: &u32,
-) -> u32 {
-    let val = state.get_reg_val(instr.s_reg) as i32 + instr.immediate as i16 as i32;
-    state.set_reg_val(instr.t_reg, val as u32);
-
-    next_pc + 4
-}
-
-fn interpret_sltiu(
-    instr: &MipsIInstr,
-    _bus: &mut BusType,
+fn interpret_instruction(
+    bus: &mut BusType,
     state: &mut CpuState,
     next_pc: &u32,
-) -> u32 {
-    let s_val = state.get_reg_val(instr.s_reg);
-    let val = if s_val < instr.immediate as u32 { 1 } else { 0 };
-
-    state.set_reg_val(instr.t_reg, val as u32);
+    delay_slot: &mut bool,
+) -> Result<u32, String> {
+    // Interrupts are taken between instructions, but not ahead of a delay slot
+    if !*delay_slot && state.raise_if_interrupt_pending() {
+        return Ok(state.pc + 4);
+    }
 
-    next_pc + 4
-}
+    if state.raise_if_pc_misaligned() {
+        *delay_slot = false;
+        return Ok(state.pc + 4);
+    }
 
-fn interpret_slti(
-    instr: &MipsIInstr,
-    _bus: &mut BusType,
-    state: &mut CpuState,
-    next_pc: &u32,
-) -> u32 {
-    let s_val = state.get_reg_val(instr.s_reg) as i32;
-    let val = if s_val < instr.immediate as i16 as i32 {
-        1
-    } else {
-        0
+    let read_result = match bus.read(state.pc, 32) {
+        Ok(r) => r,
+        Err(e) => {
+            let cause = state.bus_error(e, true);
+            state.apply_load_delay();
+            state.pc = state.raise_exception(&cause, state.pc, *delay_slot);
+            *delay_slot = false;
+            return Ok(state.pc + 4);
+        }
     };
 
-    state.set_reg_val(instr.t_reg, val as u32);
-
-    next_pc + 4
-}
-
-fn interpret_ori(
-    instr: &MipsIInstr,
-    _bus: &mut BusType,
-    state: &mut CpuState,
-    next_pc: &u32,
-) -> u32 {
-    let val = state.get_reg_val(instr.s_reg) | instr.immediate as u32;
-    state.set_reg_val(instr.t_reg, val as u32);
+    // Every instruction takes a cycle, plus however long the fetch stalls for
+    let wait_states = state.fetch_wait_states(bus, state.pc);
+    state.add_cycles(1 + wait_states);
 
-    next_pc + 4
-}
-
-fn interpret_lui(
-    instr: &MipsIInstr,
-    _bus: &mut BusType,
-    state: &mut CpuState,
-    next_pc: &u32,
-) -> u32 {
-    let val = (instr.immediate as u32) << 16;
-    state.set_reg_val(instr.t_reg, val as u32);
-
-    next_pc + 4
-}
-
-fn interpret_i_instr(
-    instr: &MipsIInstr,
-    bus: &mut BusType,
-    state: &mut CpuState,
-    next_pc: &u32,
-) -> u32 {
-    match instr.opcode {
-        MipsOpcode::AddIU => interpret_addiu(instr, bus, state, next_pc),
-        MipsOpcode::SltIU => interpret_sltiu(instr, bus, state, next_pc),
-        MipsOpcode::SltI => interpret_slti(instr, bus, state, next_pc),
-        MipsOpcode::OrI => interpret_ori(instr, bus, state, next_pc),
-        MipsOpcode::Lui => interpret_lui(instr, bus, state, next_pc),
-        MipsOpcode::Lb => mem::interpret_lb(instr, bus, state, next_pc),
-        MipsOpcode::Lbu => mem::interpret_lbu(instr, bus, state, next_pc),
-        MipsOpcode::Lw => mem::interpret_lw(instr, bus, state, next_pc),
-        MipsOpcode::Sw => mem::interpret_sw(instr, bus, state, next_pc),
-        MipsOpcode::Sb => mem::interpret_sb(instr, bus, state, next_pc),
-        MipsOpcode::Bne => branch::interpret_bne(instr, bus, state, next_pc),
-        MipsOpcode::Beq => branch::interpret_beq(instr, bus, state, next_pc),
-        MipsOpcode::Bgtz => branch::interpret_bgtz(instr, bus, state, next_pc),
-        _ => panic!("Not implemented: {} @ {:08x}", instr.opcode, state.pc),
-    }
-}
-
-fn interpret_instruction(
-    bus: &mut BusType,
-    state: &mut CpuState,
-    next_pc: &u32,
-) -> Result<u32, String> {
-    let read_result = bus
-        .read(state.pc, 32)
-        .map_err(|_| format!("Failed to read instr at pc {:08x}", state.pc))?;
     if let SizedReadResult::Dword(instr_raw) = read_result {
         let instr = super::decode::mips_decode(instr_raw);
-
-        let delay_slot_action = match instr {
-            MipsInstr::RType(r) => rtype::interpret_r_instr(&r, bus, state, next_pc),
-            MipsInstr::IType(i) => interpret_i_instr(&i, bus, state, next_pc),
-            MipsInstr::JType(j) => jtype::interpret_j_instr(&j, bus, state),
-            _ => state.pc + 8,
+        let in_delay_slot = *delay_slot;
+        *delay_slot = instr.is_branch();
+
+        let delay_slot_action = match &instr {
+            MipsInstr::RType(r) => rtype::interpret_r_instr(r, bus, state, next_pc),
+            MipsInstr::IType(i) => itype::interpret_i_instr(i, bus, state, next_pc),
+            MipsInstr::JType(j) => Ok(jtype::interpret_j_instr(j, bus, state, next_pc)),
+            MipsInstr::Cop(c) => cop::interpret_cop_instr(c, bus, state, next_pc),
+            MipsInstr::CopCmd(c) => cop::interpret_cop_command(c, bus, state, next_pc),
+            MipsInstr::CopMem(c) => cop::interpret_cop_mem(c, bus, state, next_pc),
+            MipsInstr::Invalid => Err(cop0::ExceptionCause::ReservedInstruction),
         };
 
-        state.pc = *next_pc;
-        Ok(delay_slot_action)
+        // Loads will have written back any pending load themselves before staging their own
+        if !instr.has_load_delay() {
+            state.apply_load_delay();
+        }
+
+        match delay_slot_action {
+            Ok(target) => {
+                state.pc = *next_pc;
+                Ok(target)
+            }
+            Err(cause) => {
+                // A load or MFC0 that faults never stages its own value, so the pending load it
+                // would have written back must still complete before the handler runs
+                state.apply_load_delay();
+
+                // No delay slot on exception raise
+                *delay_slot = false;
+                state.pc = state.raise_exception(&cause, state.pc, in_delay_slot);
+                Ok(state.pc + 4)
+            }
+        }
     } else {
         panic!(
             "Read size of 32 didn't return dword, instead have {:?}",
@@ -132,57 +98,76 @@ fn interpret_instruction(
     }
 }
 
-pub fn execute(bus: &mut BusType, state: &mut CpuState) -> Result<(), String> {
-    let mut icount: u64 = 0;
-    let mut icount_tot = 0;
-    let now = std::time::Instant::now();
-    let mut prev_elapsed: u128 = 0;
-
-    let mut mips_avg: f64 = 0.0;
-    let mut mips_min: f64 = f64::MAX;
-    let mut mips_max: f64 = 0.0;
-    let mut mips_avg_count: u128 = 0;
-
-    let mut next_pc = state.pc + 4;
-    let mut prev_pc: u32;
-
-    let timing_scale = 1_000;
-
-    loop {
-        prev_pc = state.pc;
-        next_pc = interpret_instruction(bus, state, &next_pc)?;
-        icount += 1;
-
-        if icount > timing_scale {
-            let elapsed_micros_tot = now.elapsed().as_micros();
-            let elapsed_micros = elapsed_micros_tot - prev_elapsed;
-            prev_elapsed = elapsed_micros_tot;
-            let elapsed = (elapsed_micros as f64) / 1_000_000.0;
-            let mips = (icount as f64) / elapsed / 1_000_000.0;
-            mips_min = f64::min(mips_min, mips);
-            mips_max = f64::max(mips_max, mips);
-            mips_avg += mips;
-            mips_avg_count += 1;
-
-            icount_tot += icount;
-            icount = 0;
+// Executes one instruction at a time, without any translation
+#[derive(Default)]
+pub struct Interpreter {
+    // The PC that `next_pc` and `delay_slot` were computed for. If the PC has been changed from
+    // outside the backend since, they no longer apply.
+    pc: Option<u32>,
+    next_pc: u32,
+    delay_slot: bool,
+}
+
+impl CpuBackend for Interpreter {
+    fn step(&mut self, bus: &mut BusType, state: &mut CpuState) -> Result<StepResult, String> {
+        if self.pc != Some(state.pc) {
+            self.next_pc = state.pc + 4;
+            self.delay_slot = false;
+        }
+
+        let prev_pc = state.pc;
+        self.next_pc = interpret_instruction(bus, state, &self.next_pc, &mut self.delay_slot)?;
+        self.pc = Some(state.pc);
+        state.take_bus_error()?;
+
+        if self.next_pc == prev_pc && spins_at(bus, prev_pc) {
+            Ok(StepResult::Halted)
+        } else {
+            Ok(StepResult::Executed(1))
         }
+    }
+
+    fn reset(&mut self, state: &mut CpuState) {
+        *self = Self::default();
+        state.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
 
-        //println!("{:08x} {:08x}", state.pc, next_pc);
-        if next_pc == prev_pc {
-            break;
+    #[test]
+    fn interpret_test_icache_fetch_timing() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+        state.enable_icache();
+
+        th.set_wait_states(0x1000, 2);
+        for _ in 0..4 {
+            th.push_instr("sll", 0, 0, 0, 0, 0);
         }
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        // Only the first fetch from each line stalls
+        assert_eq!(state.cycles(), 5 + 2 * 2);
     }
 
-    let elapsed_micros = now.elapsed().as_micros();
-    let elapsed = (elapsed_micros as f64) / 1_000_000.0;
+    #[test]
+    fn interpret_test_two_instruction_loop() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // A loop to itself that isn't a spin, as its delay slot counts down
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.push_instr("bne", 0, 1, 0, -1i16 as u16, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.finish_loop();
 
-    println!("CpuState: {:x?}", state);
-    println!("elapsed time: {}", elapsed);
-    println!("icount: {}", icount_tot + icount);
-    println!("MIPS (average): {}", mips_avg / (mips_avg_count as f64));
-    println!("MIPS (min): {}", mips_min);
-    println!("MIPS (max): {}", mips_max);
+        th.execute_interpreter(&mut state).unwrap();
 
-    Ok(())
+        assert_eq!(state.gpr[0], -1i32 as u32);
+    }
 }
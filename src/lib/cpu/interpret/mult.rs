@@ -1,4 +1,5 @@
-use super::{BusType, CpuState, MipsRInstr};
+use super::{BusType, CpuState, MipsFunction, MipsRInstr};
+use crate::cpu::timing;
 
 pub(super) fn interpret_mflo(
     instr: &MipsRInstr,
@@ -6,6 +7,7 @@ pub(super) fn interpret_mflo(
     state: &mut CpuState,
     next_pc: &u32,
 ) -> u32 {
+    state.wait_for_hilo(0);
     state.set_reg_val(instr.d_reg, state.lo);
     next_pc + 4
 }
@@ -16,6 +18,7 @@ pub(super) fn interpret_mfhi(
     state: &mut CpuState,
     next_pc: &u32,
 ) -> u32 {
+    state.wait_for_hilo(0);
     state.set_reg_val(instr.d_reg, state.hi);
     next_pc + 4
 }
@@ -48,6 +51,10 @@ pub(super) fn interpret_mult(
 ) -> u32 {
     let multiplier = state.get_reg_val(instr.s_reg) as i32;
     let multiplicand = state.get_reg_val(instr.t_reg) as i32;
+    state.start_hilo_op(
+        timing::hilo_latency(&MipsFunction::Mult, multiplier as u32),
+        0,
+    );
 
     let product = (multiplier as i64) * (multiplicand as i64);
     state.lo = product as u32;
@@ -64,6 +71,7 @@ pub(super) fn interpret_multu(
 ) -> u32 {
     let multiplier = state.get_reg_val(instr.s_reg);
     let multiplicand = state.get_reg_val(instr.t_reg);
+    state.start_hilo_op(timing::hilo_latency(&MipsFunction::MultU, multiplier), 0);
 
     let product = (multiplier as u64) * (multiplicand as u64);
     state.lo = product as u32;
@@ -80,8 +88,12 @@ pub(super) fn interpret_divu(
 ) -> u32 {
     let dividend = state.get_reg_val(instr.s_reg);
     let divisor = state.get_reg_val(instr.t_reg);
+    state.start_hilo_op(timing::DIV_LATENCY, 0);
 
+    // This is the defined result of division by zero on the PS1
     if divisor == 0 {
+        state.lo = 0xffff_ffff;
+        state.hi = dividend;
         return next_pc + 4;
     }
 
@@ -99,8 +111,12 @@ pub(super) fn interpret_div(
 ) -> u32 {
     let dividend = state.get_reg_val(instr.s_reg) as i32;
     let divisor = state.get_reg_val(instr.t_reg) as i32;
+    state.start_hilo_op(timing::DIV_LATENCY, 0);
 
+    // This is the defined result of division by zero on the PS1
     if divisor == 0 {
+        state.lo = 0xffff_ffff;
+        state.hi = dividend as u32;
         return next_pc + 4;
     }
 
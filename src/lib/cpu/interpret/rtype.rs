@@ -1,5 +1,5 @@
 use super::mult;
-use super::{BusType, CpuState, MipsFunction, MipsRInstr};
+use super::{cop0, BusType, CpuState, InterpretResult, MipsFunction, MipsRInstr};
 
 fn interpret_jr(
     instr: &MipsRInstr,
@@ -35,7 +35,7 @@ fn interpret_sll(
     next_pc: &u32,
 ) -> u32 {
     let val = state.get_reg_val(instr.t_reg) << instr.shamt;
-    state.set_reg_val(instr.d_reg, val as u32);
+    state.set_reg_val(instr.d_reg, val);
     next_pc + 4
 }
 
@@ -46,6 +46,55 @@ fn interpret_srl(
     next_pc: &u32,
 ) -> u32 {
     let val = state.get_reg_val(instr.t_reg) >> instr.shamt;
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_sra(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = (state.get_reg_val(instr.t_reg) as i32) >> instr.shamt;
+    state.set_reg_val(instr.d_reg, val as u32);
+    next_pc + 4
+}
+
+// Variable shifts only use the low 5 bits of the shift amount register
+
+fn interpret_sllv(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let shamt = state.get_reg_val(instr.s_reg) & 0x1f;
+    let val = state.get_reg_val(instr.t_reg) << shamt;
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_srlv(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let shamt = state.get_reg_val(instr.s_reg) & 0x1f;
+    let val = state.get_reg_val(instr.t_reg) >> shamt;
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_srav(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let shamt = state.get_reg_val(instr.s_reg) & 0x1f;
+    let val = (state.get_reg_val(instr.t_reg) as i32) >> shamt;
     state.set_reg_val(instr.d_reg, val as u32);
     next_pc + 4
 }
@@ -56,8 +105,56 @@ fn interpret_addu(
     state: &mut CpuState,
     next_pc: &u32,
 ) -> u32 {
-    let val = state.get_reg_val(instr.t_reg) + state.get_reg_val(instr.s_reg);
+    let val = state
+        .get_reg_val(instr.s_reg)
+        .wrapping_add(state.get_reg_val(instr.t_reg));
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_add(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    let s_val = state.get_reg_val(instr.s_reg) as i32;
+    let t_val = state.get_reg_val(instr.t_reg) as i32;
+
+    // The destination is left unchanged on overflow
+    let val = s_val
+        .checked_add(t_val)
+        .ok_or(cop0::ExceptionCause::Overflow)?;
     state.set_reg_val(instr.d_reg, val as u32);
+    Ok(next_pc + 4)
+}
+
+fn interpret_sub(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> InterpretResult {
+    let s_val = state.get_reg_val(instr.s_reg) as i32;
+    let t_val = state.get_reg_val(instr.t_reg) as i32;
+
+    let val = s_val
+        .checked_sub(t_val)
+        .ok_or(cop0::ExceptionCause::Overflow)?;
+    state.set_reg_val(instr.d_reg, val as u32);
+    Ok(next_pc + 4)
+}
+
+fn interpret_subu(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = state
+        .get_reg_val(instr.s_reg)
+        .wrapping_sub(state.get_reg_val(instr.t_reg));
+    state.set_reg_val(instr.d_reg, val);
     next_pc + 4
 }
 
@@ -68,6 +165,61 @@ fn interpret_or(
     next_pc: &u32,
 ) -> u32 {
     let val = state.get_reg_val(instr.t_reg) | state.get_reg_val(instr.s_reg);
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_xor(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = state.get_reg_val(instr.t_reg) ^ state.get_reg_val(instr.s_reg);
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_nor(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = !(state.get_reg_val(instr.t_reg) | state.get_reg_val(instr.s_reg));
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_and(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = state.get_reg_val(instr.t_reg) & state.get_reg_val(instr.s_reg);
+    state.set_reg_val(instr.d_reg, val);
+    next_pc + 4
+}
+
+fn interpret_sltu(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = state.get_reg_val(instr.s_reg) < state.get_reg_val(instr.t_reg);
+    state.set_reg_val(instr.d_reg, val as u32);
+    next_pc + 4
+}
+
+fn interpret_slt(
+    instr: &MipsRInstr,
+    _bus: &mut BusType,
+    state: &mut CpuState,
+    next_pc: &u32,
+) -> u32 {
+    let val = (state.get_reg_val(instr.s_reg) as i32) < (state.get_reg_val(instr.t_reg) as i32);
     state.set_reg_val(instr.d_reg, val as u32);
     next_pc + 4
 }
@@ -77,15 +229,28 @@ pub(super) fn interpret_r_instr(
     bus: &mut BusType,
     state: &mut CpuState,
     next_pc: &u32,
-) -> u32 {
-    match instr.function {
+) -> InterpretResult {
+    Ok(match instr.function {
         MipsFunction::Sll => interpret_sll(instr, bus, state, next_pc),
         MipsFunction::Srl => interpret_srl(instr, bus, state, next_pc),
-        MipsFunction::Add => interpret_addu(instr, bus, state, next_pc),
+        MipsFunction::Sra => interpret_sra(instr, bus, state, next_pc),
+        MipsFunction::Sllv => interpret_sllv(instr, bus, state, next_pc),
+        MipsFunction::Slrv => interpret_srlv(instr, bus, state, next_pc),
+        MipsFunction::Srav => interpret_srav(instr, bus, state, next_pc),
+        MipsFunction::Add => return interpret_add(instr, bus, state, next_pc),
         MipsFunction::AddU => interpret_addu(instr, bus, state, next_pc),
+        MipsFunction::Sub => return interpret_sub(instr, bus, state, next_pc),
+        MipsFunction::Subu => interpret_subu(instr, bus, state, next_pc),
         MipsFunction::Or => interpret_or(instr, bus, state, next_pc),
+        MipsFunction::Xor => interpret_xor(instr, bus, state, next_pc),
+        MipsFunction::Nor => interpret_nor(instr, bus, state, next_pc),
+        MipsFunction::And => interpret_and(instr, bus, state, next_pc),
+        MipsFunction::Sltu => interpret_sltu(instr, bus, state, next_pc),
+        MipsFunction::Slt => interpret_slt(instr, bus, state, next_pc),
         MipsFunction::Jr => interpret_jr(instr, bus, state, next_pc),
         MipsFunction::Jalr => interpret_jalr(instr, bus, state, next_pc),
+        MipsFunction::Syscall => return Err(cop0::ExceptionCause::Syscall),
+        MipsFunction::Brk => return Err(cop0::ExceptionCause::Break),
         MipsFunction::Mfhi => mult::interpret_mfhi(instr, bus, state, next_pc),
         MipsFunction::Mflo => mult::interpret_mflo(instr, bus, state, next_pc),
         MipsFunction::Mthi => mult::interpret_mthi(instr, bus, state, next_pc),
@@ -94,6 +259,217 @@ pub(super) fn interpret_r_instr(
         MipsFunction::MultU => mult::interpret_multu(instr, bus, state, next_pc),
         MipsFunction::Div => mult::interpret_div(instr, bus, state, next_pc),
         MipsFunction::DivU => mult::interpret_divu(instr, bus, state, next_pc),
-        _ => panic!("Not implemented: {}", instr.function),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn interpret_test_nor() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 0x0000ffff);
+        th.load32(2, 0xff000000);
+        th.push_instr("nor", 3, 1, 2, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 0x00ff0000);
+    }
+
+    #[test]
+    fn interpret_test_srav() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, -8i32 as u32);
+        th.load32(2, 33);
+        th.push_instr("srav", 3, 2, 1, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], -4i32 as u32);
+    }
+
+    #[test]
+    fn interpret_test_slt() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, -1i32 as u32);
+        th.push_instr("slt", 2, 1, 0, 0, 0);
+        th.push_instr("sltu", 3, 1, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        // -1 is less than 0 when signed, but the largest value when unsigned
+        assert_eq!(state.gpr[1], 1);
+        assert_eq!(state.gpr[2], 0);
+    }
+
+    #[test]
+    fn interpret_test_syscall() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.push_instr("syscall", 0, 0, 0, 0, 0);
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x8 << 2
+        );
+    }
+
+    #[test]
+    fn interpret_test_add_overflow() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 0x7fff_ffff);
+        th.load32(2, 1);
+        th.load32(3, 0xabcd);
+        th.push_instr("add", 3, 1, 2, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 0xabcd);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000 + 6 * 4
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xc << 2
+        );
+    }
+
+    #[test]
+    fn interpret_test_sub_overflow() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 0x8000_0000);
+        th.load32(2, 1);
+        th.push_instr("sub", 2, 1, 2, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 1);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xc << 2
+        );
+    }
+
+    #[test]
+    fn interpret_test_reserved_instruction_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.push_instr("jr", 0, 31, 0, 0, 0);
+        th.push_raw(0xfc00_0000);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            (1 << 31) | (0xa << 2)
+        );
+    }
+
+    #[test]
+    fn interpret_test_break_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.push_instr("beq", 0, 0, 0, 4, 0);
+        th.push_instr("break", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            (1 << 31) | (0x9 << 2)
+        );
+    }
+
+    #[test]
+    fn interpret_test_jr_misaligned() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 0x1002);
+        th.push_instr("jr", 0, 1, 0, 0, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x4 << 2
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::BadVaddr as usize],
+            0x1002
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1002
+        );
+    }
+
+    #[test]
+    fn interpret_test_jr_unmapped() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.map_exception_vector();
+        th.load32(1, 0x0800_0000);
+        th.push_instr("jr", 0, 1, 0, 0, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x6 << 2
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x0800_0000
+        );
     }
 }
@@ -0,0 +1,47 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+// The hardware interrupt lines into the CPU, which appear in COP0 Cause as IP2 to IP7.
+// Clones share the same lines, so devices can hold one to raise interrupts while the CPU
+// executes.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptLines {
+    lines: Rc<Cell<u8>>,
+}
+
+impl InterruptLines {
+    pub const COUNT: u8 = 6;
+
+    pub fn assert(&self, line: u8) {
+        assert!(line < Self::COUNT);
+        self.lines.set(self.lines.get() | (1 << line));
+    }
+
+    pub fn deassert(&self, line: u8) {
+        assert!(line < Self::COUNT);
+        self.lines.set(self.lines.get() & !(1 << line));
+    }
+
+    // Bitmask of the lines that are currently asserted, with line 0 in bit 0
+    pub fn asserted(&self) -> u8 {
+        self.lines.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InterruptLines;
+
+    #[test]
+    fn interrupt_lines_shared() {
+        let lines = InterruptLines::default();
+        let device = lines.clone();
+
+        device.assert(0);
+        device.assert(3);
+        assert_eq!(lines.asserted(), 0b1001);
+
+        device.deassert(0);
+        assert_eq!(lines.asserted(), 0b1000);
+    }
+}
@@ -3,36 +3,44 @@ use super::{DelaySlotArg, TranslationBlock};
 
 impl<'ctx> TranslationBlock<'ctx> {
     fn branch_delay_slot_action<'a, 'b>(tb: &'a mut TranslationBlock<'b>) {
+        tb.in_delay_slot = true;
         let arg = tb.delay_slot_arg.as_ref().unwrap();
+        let (count, immed, cond) = (arg.count, arg.immed, arg.value.into_int_value());
         let i32_type = tb.ctx.i32_type();
 
-        let immed_i = arg.immed as i16;
-        let target = (immed_i as i32) * 4;
+        // Offsets from the block's start, with the target relative to the delay slot
+        let curr_pc_off = tb.pc_offset(tb.count_uniq);
+        let taken_off = curr_pc_off.wrapping_add((immed as i16 as i32 * 4) as u32);
+        let not_taken_off = curr_pc_off.wrapping_add(4);
 
-        let curr_pc_off = (tb.count_uniq * 4) as i32;
-        let target_taken = i32_type.const_int((target + curr_pc_off) as u64, true);
+        if tb.follow_branch(cond, taken_off, not_taken_off) {
+            return;
+        }
 
-        let target_not_taken = i32_type.const_int((tb.count_uniq * 4 + 4) as u64, false);
+        let target_taken = i32_type.const_int(taken_off as u64, false);
+        let target_not_taken = i32_type.const_int(not_taken_off as u64, false);
 
         let target_v = tb.builder.build_select(
-            arg.value.into_int_value(),
+            cond,
             target_taken,
             target_not_taken,
-            &format!("b_select_{}", arg.count),
+            &format!("b_select_{}", count),
         );
 
-        let pc_ptr = tb.gep_pc(&format!("beq_{}", arg.count));
+        let pc_ptr = tb.gep_pc(&format!("beq_{}", count));
         let pc_val = tb
             .builder
-            .build_load(pc_ptr, &format!("beq_{}_pc_val", arg.count));
+            .build_load(pc_ptr, &format!("beq_{}_pc_val", count));
 
         let next_pc = tb.builder.build_int_add(
             pc_val.into_int_value(),
             target_v.into_int_value(),
-            &format!("beq_{}_next_pc", arg.count),
+            &format!("beq_{}_next_pc", count),
         );
 
         tb.builder.build_store(pc_ptr, next_pc);
+        tb.add_successor(tb.pc.wrapping_add(taken_off));
+        tb.add_successor(tb.pc.wrapping_add(not_taken_off));
         tb.finalized = true;
     }
 
@@ -53,6 +61,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: instr.immediate,
+            target: None,
             value: cmp.into(),
         });
         self.delay_slot_hazard = Some(Self::branch_delay_slot_action);
@@ -75,11 +84,72 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: instr.immediate,
+            target: None,
             value: cmp.into(),
         });
         self.delay_slot_hazard = Some(Self::branch_delay_slot_action);
     }
 
+    // Shared by BLTZ/BGEZ and their linking variants, which compare rs against zero
+    fn emit_zero_cmp_branch(
+        &mut self,
+        instr: &decode::MipsIInstr,
+        pred: inkwell::IntPredicate,
+        link: bool,
+        name: &str,
+    ) {
+        let s_val = self.get_gpr_value(instr.s_reg, &format!("{}_{}", name, self.count_uniq));
+        let i32_type = self.ctx.i32_type();
+        let zero = i32_type.const_zero();
+        let cmp = self.builder.build_int_compare(
+            pred,
+            s_val,
+            zero,
+            &format!("{}_{}_cmp", name, self.count_uniq),
+        );
+
+        // The return address is written whether or not the branch is taken
+        if link {
+            let pc = self.gep_pc(&format!("{}_{}", name, self.count_uniq));
+
+            let pc_incr = i32_type.const_int(
+                self.pc_offset(self.count_uniq).wrapping_add(8) as u64,
+                false,
+            );
+            let pc_val = self
+                .builder
+                .build_load(pc, &format!("{}_{}_pc_val", name, self.count_uniq));
+            let ra_val = self.builder.build_int_add(
+                pc_val.into_int_value(),
+                pc_incr,
+                &format!("{}_{}_ra_val", name, self.count_uniq),
+            );
+
+            self.set_gpr_value(31, ra_val);
+        }
+
+        let count = self.count_uniq;
+        self.instr_finished_emitting();
+
+        self.delay_slot_arg = Some(DelaySlotArg {
+            count,
+            immed: instr.immediate,
+            target: None,
+            value: cmp.into(),
+        });
+        self.delay_slot_hazard = Some(Self::branch_delay_slot_action);
+    }
+
+    pub(super) fn emit_bltz(&mut self, instr: &decode::MipsIInstr, link: bool) {
+        let name = if link { "bltzal" } else { "bltz" };
+        self.emit_zero_cmp_branch(instr, inkwell::IntPredicate::SLT, link, name);
+    }
+
+    pub(super) fn emit_bgez(&mut self, instr: &decode::MipsIInstr, link: bool) {
+        let name = if link { "bgezal" } else { "bgez" };
+        self.emit_zero_cmp_branch(instr, inkwell::IntPredicate::SGE, link, name);
+    }
+
     pub(super) fn emit_beq(&mut self, instr: &decode::MipsIInstr) {
         let s_val = self.get_gpr_value(instr.s_reg, &format!("beq_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("beq_{}", self.count_uniq));
@@ -96,6 +166,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: instr.immediate,
+            target: None,
             value: cmp.into(),
         });
         self.delay_slot_hazard = Some(Self::branch_delay_slot_action);
@@ -117,6 +188,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: instr.immediate,
+            target: None,
             value: cmp.into(),
         });
         self.delay_slot_hazard = Some(Self::branch_delay_slot_action);
@@ -243,4 +315,118 @@ mod test {
 
         assert_eq!(state.pc, 0x1000 + 8 + (target << 2) as u32);
     }
+
+    #[test]
+    fn jit_test_bltz_taken() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("addiu", 0, 0, 1, -1 as i16 as u16, 0);
+        th.push_instr("bltz", 0, 1, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 8 + (target << 2) as u32);
+    }
+
+    #[test]
+    fn jit_test_bltz_not_taken() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("bltz", 0, 0, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 8);
+    }
+
+    #[test]
+    fn jit_test_bgez_taken() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("bgez", 0, 0, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 4 + (target << 2) as u32);
+    }
+
+    #[test]
+    fn jit_test_bgez_not_taken() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("addiu", 0, 0, 1, -1 as i16 as u16, 0);
+        th.push_instr("bgez", 0, 1, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 0xc);
+    }
+
+    #[test]
+    fn jit_test_bltzal_not_taken_links() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("bltzal", 0, 1, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 0xc);
+        assert_eq!(state.gpr[30], 0x1000 + 0xc);
+    }
+
+    #[test]
+    fn jit_test_bgezal_taken_links() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("bgezal", 0, 1, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 8 + (target << 2) as u32);
+        assert_eq!(state.gpr[30], 0x1000 + 0xc);
+    }
+
+    #[test]
+    fn jit_test_regimm_reserved() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // A REGIMM encoding whose t register field isn't one of the branches
+        th.push_raw(0x0402_0002);
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
 }
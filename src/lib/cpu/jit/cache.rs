@@ -0,0 +1,450 @@
+use super::engine::Runtime;
+use super::TranslationBlock;
+use crate::cpu::bus::{BusDevice, SizedReadResult};
+use crate::cpu::llvm::JitConfig;
+use inkwell::memory_buffer::MemoryBuffer;
+use inkwell::module::Module;
+use inkwell::types::BasicType;
+use inkwell::values::GlobalValue;
+use std::path::PathBuf;
+
+// Bumped whenever translation changes what is generated for the same guest code, so that blocks
+// cached by an older version are never loaded
+const JIT_VERSION: u32 = 2;
+
+const MAGIC: &[u8; 4] = b"PXTB";
+
+fn links_name(id: u64) -> String {
+    format!("tb_links_{}", id)
+}
+
+fn exec_count_name(id: u64) -> String {
+    format!("tb_count_{}", id)
+}
+
+fn host_region_name(index: usize) -> String {
+    format!("tb_host_{}", index)
+}
+
+const CODE_PAGES_NAME: &str = "tb_code_pages";
+
+// Blocks kept on disk between runs, one file per block.
+// MCJIT can't be handed object code, so what's kept is each block's module as bitcode, after the
+// configured passes have run. Loading a block skips translating it and running the passes, though
+// machine code is still generated for it.
+pub(super) struct DiskCache {
+    dir: PathBuf,
+}
+
+// What's kept of a block: enough to check it against the guest code, and to stand in for
+// translating it
+struct CacheEntry<'a> {
+    // Id the block had in the run that stored it, which its function and links are named after
+    id: u64,
+    pc: u32,
+    // Start and end of each range of guest code the block covers
+    ranges: Vec<(u32, u32)>,
+    count_uniq: u64,
+    cycles: u64,
+    successors: Vec<u32>,
+    // The guest code the block was translated from, one range after another
+    words: Vec<u32>,
+    bitcode: &'a [u8],
+}
+
+// Reads the guest code from `pc` up to `end`, or None if any of it can't be read
+fn read_words(bus: &mut impl BusDevice, pc: u32, end: u32) -> Option<Vec<u32>> {
+    (pc..end)
+        .step_by(4)
+        .map(|addr| match bus.read(addr, 32) {
+            Ok(SizedReadResult::Dword(word)) => Some(word),
+            _ => None,
+        })
+        .collect()
+}
+
+// Reads the guest code in each of `ranges` in turn, or None if any of it can't be read
+fn read_ranges(bus: &mut impl BusDevice, ranges: &[(u32, u32)]) -> Option<Vec<u32>> {
+    let mut words = Vec::new();
+    for (start, end) in ranges {
+        words.extend(read_words(bus, *start, *end)?);
+    }
+    Some(words)
+}
+
+// 64-bit FNV-1a, which unlike the standard library's hasher is the same from one build to the next
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, val: u32) {
+        self.write(&val.to_le_bytes());
+    }
+}
+
+impl<'a> CacheEntry<'a> {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(MAGIC);
+        data.extend(JIT_VERSION.to_le_bytes());
+        data.extend(self.id.to_le_bytes());
+        data.extend(self.pc.to_le_bytes());
+        data.extend((self.ranges.len() as u32).to_le_bytes());
+        for (start, end) in &self.ranges {
+            data.extend(start.to_le_bytes());
+            data.extend(end.to_le_bytes());
+        }
+        data.extend(self.count_uniq.to_le_bytes());
+        data.extend(self.cycles.to_le_bytes());
+        data.extend((self.successors.len() as u32).to_le_bytes());
+        for successor in &self.successors {
+            data.extend(successor.to_le_bytes());
+        }
+        for word in &self.words {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend(self.bitcode);
+        data
+    }
+
+    fn decode(data: &'a [u8]) -> Option<Self> {
+        let mut reader = Reader(data);
+        if reader.take(4)? != MAGIC || reader.u32()? != JIT_VERSION {
+            return None;
+        }
+
+        let id = reader.u64()?;
+        let pc = reader.u32()?;
+        let ranges = (0..reader.u32()?)
+            .map(|_| Some((reader.u32()?, reader.u32()?)))
+            .collect::<Option<Vec<_>>>()?;
+        if ranges
+            .iter()
+            .any(|(start, end)| end < start || (end - start) % 4 != 0)
+        {
+            return None;
+        }
+
+        let count_uniq = reader.u64()?;
+        let cycles = reader.u64()?;
+        let successors = (0..reader.u32()?)
+            .map(|_| reader.u32())
+            .collect::<Option<Vec<_>>>()?;
+        let len: usize = ranges
+            .iter()
+            .map(|(start, end)| (end - start) as usize / 4)
+            .sum();
+        let words = (0..len).map(|_| reader.u32()).collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            id,
+            pc,
+            ranges,
+            count_uniq,
+            cycles,
+            successors,
+            words,
+            bitcode: reader.0,
+        })
+    }
+}
+
+// Reads little endian fields from the front of an entry
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+impl DiskCache {
+    pub(super) fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.tb", key))
+    }
+
+    // Hashes what the code for a block at `pc` depends on. That's mostly the guest code from `pc` up
+    // to the end of its line of 64 instructions, along with the wait states and memory layout baked
+    // into the code, and the configuration it was compiled with. Blocks can extend further, and
+    // follow jumps elsewhere, which is only checked as the block is loaded.
+    pub(super) fn key(&self, config: &JitConfig, bus: &mut impl BusDevice, pc: u32) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write_u32(JIT_VERSION);
+        hash.write(format!("{:?}{:?}", config.opt_level, config.passes).as_bytes());
+
+        for region in bus.host_regions() {
+            hash.write_u32(region.addr);
+            hash.write_u32(region.size);
+            hash.write_u32(region.wait_states);
+        }
+
+        hash.write_u32(pc);
+        let mut addr = pc;
+        loop {
+            match bus.read(addr, 32) {
+                Ok(SizedReadResult::Dword(word)) => {
+                    hash.write_u32(word);
+                    hash.write_u32(bus.wait_states(addr, 32));
+                }
+                _ => break,
+            }
+
+            addr += 4;
+            if (addr >> 2) & 0x3f == 0 {
+                break;
+            }
+        }
+
+        hash.0
+    }
+
+    // Stores a block that has been translated, but not yet compiled
+    pub(super) fn store(
+        &self,
+        key: u64,
+        tb: &TranslationBlock,
+        bus: &mut impl BusDevice,
+    ) -> std::io::Result<()> {
+        // Nothing is kept of a block whose code can't be read back to check it against later
+        let words = match read_ranges(bus, &tb.ranges) {
+            Some(words) => words,
+            None => return Ok(()),
+        };
+
+        let bitcode = tb.module.write_bitcode_to_memory();
+        let entry = CacheEntry {
+            id: tb.id,
+            pc: tb.pc,
+            ranges: tb.ranges.clone(),
+            count_uniq: tb.count_uniq,
+            cycles: tb.cycles,
+            successors: tb.successors.clone(),
+            words,
+            bitcode: bitcode.as_slice(),
+        };
+
+        // Written under another name first, so that a run never loads a partly written entry
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp_path, entry.encode())?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    // Loads the block at `pc` stored under `key`, provided the guest code still matches it. The
+    // block is ready to be compiled.
+    pub(super) fn load<'ctx>(
+        &self,
+        key: u64,
+        ctx: &'ctx inkwell::context::Context,
+        runtime: &mut Runtime<'ctx>,
+        bus: &mut impl BusDevice,
+        pc: u32,
+    ) -> Option<TranslationBlock<'ctx>> {
+        let data = std::fs::read(self.path(key)).ok()?;
+        let entry = CacheEntry::decode(&data)?;
+
+        // The key only makes a mismatch unlikely
+        if entry.pc != pc || read_ranges(bus, &entry.ranges)? != entry.words {
+            return None;
+        }
+
+        let buffer = MemoryBuffer::create_from_memory_range_copy(entry.bitcode, "tb_cache");
+        let module = Module::parse_bitcode_from_buffer(&buffer, ctx).ok()?;
+        let func = module.get_function(&format!("tb_func_{}", entry.id))?;
+
+        // The names must be unique within the engine, which the old id doesn't guarantee
+        let (id, ee) = runtime.allocate().ok()?;
+        func.as_global_value()
+            .as_pointer_value()
+            .set_name(&format!("tb_func_{}", id));
+        if let Some(links) = module.get_global(&links_name(entry.id)) {
+            links.as_pointer_value().set_name(&links_name(id));
+        }
+        if let Some(count) = module.get_global(&exec_count_name(entry.id)) {
+            count.as_pointer_value().set_name(&exec_count_name(id));
+        }
+
+        let mut tb = super::tb_with_module(ctx, id, ee, module, func).ok()?;
+        tb.pc = pc;
+        tb.count_uniq = entry.count_uniq;
+        tb.cycles = entry.cycles;
+        tb.ranges = entry.ranges;
+        tb.successors = entry.successors;
+        tb.host_regions = bus.host_regions();
+        tb.finalized = true;
+
+        Some(tb)
+    }
+}
+
+// Host addresses the code uses, of memory it accesses directly and of the block's links and
+// execution count, are referenced through external globals, which are only mapped to the addresses as the block is
+// compiled. That way, nothing in a block's module is particular to the run that translated it.
+impl<'ctx> TranslationBlock<'ctx> {
+    fn host_global(&self, name: &str, ty: impl BasicType<'ctx>) -> GlobalValue<'ctx> {
+        self.module
+            .get_global(name)
+            .unwrap_or_else(|| self.module.add_global(ty, None, name))
+    }
+
+    // Slots the block's exits are linked through, as an array of function addresses
+    pub(super) fn links_global(&self) -> GlobalValue<'ctx> {
+        let ty = self
+            .ctx
+            .i64_type()
+            .array_type(super::chain::LINK_SLOTS as u32);
+        self.host_global(&links_name(self.id), ty)
+    }
+
+    // Count of the times the block has been entered
+    pub(super) fn exec_count_global(&self) -> GlobalValue<'ctx> {
+        self.host_global(&exec_count_name(self.id), self.ctx.i64_type())
+    }
+
+    // Start of the host memory backing the host region at `index`
+    pub(super) fn host_region_global(&self, index: usize) -> GlobalValue<'ctx> {
+        self.host_global(&host_region_name(index), self.ctx.i8_type())
+    }
+
+    // Bitmap of the pages holding translated code
+    pub(super) fn code_pages_global(&self) -> GlobalValue<'ctx> {
+        self.host_global(CODE_PAGES_NAME, self.ctx.i64_type())
+    }
+
+    // Maps the globals the block uses to the addresses they stand for in this run
+    pub(super) fn map_host_globals(&self) {
+        let mut mappings = vec![
+            (links_name(self.id), self.links.as_ptr() as usize),
+            (exec_count_name(self.id), self.exec_count as usize),
+            (String::from(CODE_PAGES_NAME), self.code_pages as usize),
+        ];
+        for (i, region) in self.host_regions.iter().enumerate() {
+            mappings.push((host_region_name(i), region.ptr as usize));
+        }
+
+        for (name, addr) in mappings {
+            if let Some(global) = self.module.get_global(&name) {
+                self.ee.add_global_mapping(&global.as_pointer_value(), addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CacheEntry;
+    use crate::cpu::backend::run_until_halted;
+    use crate::cpu::bus::BusDevice;
+    use crate::cpu::jit::harness::TestHarness;
+    use crate::cpu::jit::Jit;
+    use crate::cpu::llvm::JitConfig;
+
+    #[test]
+    fn jit_test_cache_entry_roundtrip() {
+        let bitcode = [1, 2, 3];
+        let entry = CacheEntry {
+            id: 7,
+            pc: 0x1000,
+            ranges: vec![(0x1000, 0x1004), (0x1100, 0x1104)],
+            count_uniq: 2,
+            cycles: 2,
+            successors: vec![0x1008, 0x1100],
+            words: vec![0x2401_0005, 0],
+            bitcode: &bitcode,
+        };
+
+        let data = entry.encode();
+        let decoded = CacheEntry::decode(&data).unwrap();
+        assert_eq!(decoded.id, 7);
+        assert_eq!(decoded.ranges, entry.ranges);
+        assert_eq!(decoded.successors, entry.successors);
+        assert_eq!(decoded.words, entry.words);
+        assert_eq!(decoded.bitcode, &bitcode);
+
+        assert!(CacheEntry::decode(&data[..20]).is_none());
+    }
+
+    #[test]
+    fn jit_test_disk_cache_reload() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.push_instr("addu", 2, 2, 1, 0, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.push_instr("bne", 0, 1, 0, -3i16 as u16, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        let dir = std::env::temp_dir().join(format!("psx_tb_cache_{}", std::process::id()));
+        let config = JitConfig {
+            cache_dir: Some(dir.clone()),
+            ..JitConfig::default()
+        };
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+
+                let mut backend = Jit::with_config(&ctx, config.clone());
+                let stats = run_until_halted(&mut backend, bus, state)?;
+                assert_eq!(stats.translation.unwrap().cache_hits, 0);
+                assert_eq!(state.gpr[1], 55);
+
+                // A later run loads every block instead of translating it
+                let mut state = crate::cpu::CpuState::default();
+                state.set_pc(0x1000);
+                let mut backend = Jit::with_config(&ctx, config.clone());
+                let stats = run_until_halted(&mut backend, bus, &mut state)?;
+                let translation = stats.translation.unwrap();
+                assert_eq!(translation.cache_hits, translation.blocks_compiled);
+                assert_eq!(state.gpr[1], 55);
+
+                // Once the code has changed, the first block is translated again
+                let instr = crate::cpu::decode::mips_encode_str("addiu", 0, 0, 1, 4, 0).unwrap();
+                bus.write(0x1000, 32, instr).unwrap();
+                let mut state = crate::cpu::CpuState::default();
+                state.set_pc(0x1000);
+                let mut backend = Jit::with_config(&ctx, config.clone());
+                let stats = run_until_halted(&mut backend, bus, &mut state)?;
+                let translation = stats.translation.unwrap();
+                assert_eq!(translation.cache_hits, translation.blocks_compiled - 1);
+                assert_eq!(state.gpr[1], 10);
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use super::{TbManager, TranslationBlock};
+use std::rc::Rc;
+
+// One slot per successor, plus a last slot that is never linked, which exits to anywhere else use.
+// Blocks have up to two successors, and traces one more for each of their side exits.
+pub(super) const LINK_SLOTS: usize = 8;
+
+// Cycles a chain of linked blocks may run for before returning to the dispatcher. This also bounds
+// how deep the native stack gets, should LLVM not turn the chaining calls into tail calls.
+pub(super) const CHAIN_CYCLES: u64 = 2048;
+
+impl<'ctx> TranslationBlock<'ctx> {
+    // Adds the cycles and instructions up to the exit being emitted to the state
+    pub(super) fn emit_exit_accounting(&self, cycles: u64, icount: u64) {
+        let i64_type = self.ctx.i64_type();
+
+        let counters = [
+            (self.gep_cycles("exit"), cycles, "exit_cycles"),
+            (self.gep_chain_icount("exit"), icount, "exit_icount"),
+        ];
+
+        for (ptr, count, name) in counters {
+            let val = self.builder.build_load(ptr, name).into_int_value();
+            let new_val = self.builder.build_int_add(
+                val,
+                i64_type.const_int(count, false),
+                &format!("{}_new", name),
+            );
+            self.builder.build_store(ptr, new_val);
+        }
+    }
+
+    // Records an address the block can leave to, which gets a link slot of its own
+    pub(super) fn add_successor(&mut self, addr: u32) {
+        if !self.successors.contains(&addr) {
+            self.successors.push(addr);
+        }
+    }
+
+    // Leaves the block, calling straight into the next block if the exit taken has been linked to
+    // it. Exits that aren't linked yet ask the dispatcher to link them.
+    pub(super) fn emit_block_exit(&mut self) {
+        self.emit_writeback();
+        self.emit_chained_exit();
+    }
+
+    // Leaves the block through whichever exit the PC in the state leads to, once the registers
+    // have been stored back
+    pub(super) fn emit_chained_exit(&self) {
+        self.emit_exit_accounting(self.cycles, self.count_uniq);
+
+        if self.successors.is_empty() {
+            self.builder.build_return(None);
+            return;
+        }
+
+        let i32_type = self.ctx.i32_type();
+        let i64_type = self.ctx.i64_type();
+        let links = self.links_global().as_pointer_value();
+        let slot_ptr = |slot: usize| unsafe {
+            links.const_in_bounds_gep(&[
+                i32_type.const_zero(),
+                i32_type.const_int(slot as u64, false),
+            ])
+        };
+
+        let pc_ptr = self.gep_pc("exit");
+        let pc = self.builder.build_load(pc_ptr, "exit_pc").into_int_value();
+
+        let mut slot = slot_ptr(LINK_SLOTS - 1);
+        for (i, successor) in self.successors.iter().enumerate() {
+            let taken = self.builder.build_int_compare(
+                inkwell::IntPredicate::EQ,
+                pc,
+                i32_type.const_int(*successor as u64, false),
+                &format!("exit_to_{:x}", successor),
+            );
+            slot = self
+                .builder
+                .build_select(taken, slot_ptr(i), slot, "exit_slot")
+                .into_pointer_value();
+        }
+
+        let func_addr = self
+            .builder
+            .build_load(slot, "exit_func_addr")
+            .into_int_value();
+        let linked = self.builder.build_int_compare(
+            inkwell::IntPredicate::NE,
+            func_addr,
+            i64_type.const_zero(),
+            "exit_linked",
+        );
+
+        let budget_block = self.ctx.append_basic_block(self.func, "exit_budget");
+        let chain_block = self.ctx.append_basic_block(self.func, "exit_chain");
+        let unlinked_block = self.ctx.append_basic_block(self.func, "exit_unlinked");
+        let return_block = self.ctx.append_basic_block(self.func, "exit_return");
+
+        self.builder
+            .build_conditional_branch(linked, budget_block, unlinked_block);
+
+        self.builder.position_at_end(budget_block);
+        let cycles_ptr = self.gep_cycles("exit_budget");
+        let cycles = self.builder.build_load(cycles_ptr, "exit_budget_cycles");
+        let limit_ptr = self.gep_chain_limit("exit_budget");
+        let limit = self.builder.build_load(limit_ptr, "exit_budget_limit");
+        let in_budget = self.builder.build_int_compare(
+            inkwell::IntPredicate::ULT,
+            cycles.into_int_value(),
+            limit.into_int_value(),
+            "exit_in_budget",
+        );
+        self.builder
+            .build_conditional_branch(in_budget, chain_block, return_block);
+
+        self.builder.position_at_end(chain_block);
+        let func_ptr = self.builder.build_int_to_ptr(
+            func_addr,
+            self.func
+                .get_type()
+                .ptr_type(inkwell::AddressSpace::Generic),
+            "exit_chain_func",
+        );
+        let call = self.builder.build_call(
+            inkwell::values::CallableValue::try_from(func_ptr).unwrap(),
+            &[
+                self.state_arg.into(),
+                self.bus_arg.into(),
+                self.mgr_arg.into(),
+            ],
+            "exit_chain_call",
+        );
+        call.set_tail_call(true);
+        self.builder.build_return(None);
+
+        self.builder.position_at_end(unlinked_block);
+        let request_link_fn = self.module.get_function("tb_request_link").unwrap();
+        self.builder.build_call(
+            request_link_fn,
+            &[
+                self.mgr_arg.into(),
+                i32_type.const_int(self.pc as u64, false).into(),
+            ],
+            "exit_request_link",
+        );
+        self.builder.build_return(None);
+
+        self.builder.position_at_end(return_block);
+        self.builder.build_return(None);
+    }
+
+    // Stops the block from chaining anywhere, so it returns to the dispatcher
+    pub(super) fn unlink_all(&self) {
+        for link in self.links.iter() {
+            link.set(0);
+        }
+    }
+}
+
+impl<'ctx> TbManager<'ctx> {
+    // Links the exit of the block at `src` that leads to `pc`, where `dst` was found, so that it
+    // calls `dst` directly rather than returning to the dispatcher
+    pub(super) fn link(&mut self, src: u32, pc: u32, dst: &Rc<TranslationBlock<'ctx>>) {
+        // A block that only branches to itself is waiting for an interrupt, or for a test program
+        // to be stopped, neither of which can happen without the dispatcher
+        if (src == pc && dst.spins) || dst.func_addr == 0 {
+            return;
+        }
+
+        let src_tb = match self.trie.lookup(src) {
+            Some(tb) => tb,
+            None => return,
+        };
+
+        if let Some(successor) = src_tb.successors.iter().position(|s| *s == pc) {
+            src_tb.links[successor].set(dst.func_addr);
+            self.incoming
+                .entry(pc & 0x1fff_ffff)
+                .or_default()
+                .push((src, successor));
+        }
+    }
+
+    // Undoes the links into the block at `pc`, so the blocks that chained into it return to the
+    // dispatcher instead
+    pub(super) fn unlink_into(&mut self, pc: u32) {
+        for (src, successor) in self
+            .incoming
+            .remove(&(pc & 0x1fff_ffff))
+            .unwrap_or_default()
+        {
+            if let Some(src_tb) = self.trie.lookup(src) {
+                src_tb.links[successor].set(0);
+            }
+        }
+    }
+}
+
+// Called by a block leaving through an exit that isn't linked yet, for the dispatcher to link once
+// it has the block the exit leads to
+#[no_mangle]
+pub(crate) unsafe extern "C" fn tb_request_link(mgr: *mut TbManager, src: u32) {
+    (*mgr).pending_link = Some(src);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::backend::{run_until_halted, CpuBackend, StopReason};
+    use crate::cpu::jit::harness::TestHarness;
+    use crate::cpu::jit::Jit;
+
+    #[test]
+    fn jit_test_chained_loop() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 100, 0);
+        th.push_instr("addiu", 0, 2, 2, 3, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.push_instr("bne", 0, 1, 0, -3i16 as u16, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut backend = Jit::new(&ctx);
+                let stats = run_until_halted(&mut backend, bus, state)?;
+
+                // The loop body links to itself after its first pass, and is only compiled once
+                assert_eq!(stats.translation.unwrap().blocks_compiled, 3);
+                assert_eq!(stats.icount, 5 + 4 * 99 + 2);
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[1], 300);
+        assert_eq!(state.gpr[0], 0);
+    }
+
+    #[test]
+    fn jit_test_chain_stops_at_budget() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 100, 0);
+        th.push_instr("addiu", 0, 2, 2, 3, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.push_instr("bne", 0, 1, 0, -3i16 as u16, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut backend = Jit::new(&ctx);
+
+                // The loop body chains to itself, but stops once the budget is used up rather
+                // than running a full chain
+                assert_eq!(backend.run(bus, state, 20)?, StopReason::BudgetExhausted);
+                assert!(state.cycles() < 20 + 4);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn jit_test_two_instruction_loop() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // A loop to itself that isn't a spin, as its delay slot counts down
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.push_instr("bne", 0, 1, 0, -1i16 as u16, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut backend = Jit::new(&ctx);
+                run_until_halted(&mut backend, bus, state)?;
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[0], -1i32 as u32);
+    }
+}
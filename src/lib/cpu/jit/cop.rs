@@ -1,7 +1,7 @@
 use crate::cpu::{
     cop0,
-    decode::{MipsCopInstr, MipsRInstr},
-    opcode::MipsCopOperation,
+    decode::{MipsCopCmdInstr, MipsCopInstr, MipsCopMemInstr, MipsRInstr},
+    opcode::{MipsCop0Command, MipsCopOperation},
 };
 
 use super::TranslationBlock;
@@ -10,7 +10,7 @@ impl<'ctx> TranslationBlock<'ctx> {
     pub(super) fn emit_cop_operation(&mut self, instr: &MipsCopInstr) {
         match instr.cop {
             0 => self.emit_cop0_operation(instr),
-            _ => panic!("Unimplemented COP: {}", instr.cop),
+            cop => self.emit_missing_cop(cop),
         }
     }
 
@@ -18,11 +18,105 @@ impl<'ctx> TranslationBlock<'ctx> {
         match instr.operation {
             MipsCopOperation::MoveTo => self.emit_cop0_mtc(instr),
             MipsCopOperation::MoveFrom => self.emit_cop0_mfc(instr),
-            _ => panic!("Unimplemented operation {} for CP0", instr.operation),
+            // COP0 has no control registers
+            _ => self.emit_reserved_instruction(),
         }
     }
 
-    fn gep_cop0_reg(&self, reg: u8, name: &str) -> inkwell::values::PointerValue<'ctx> {
+    pub(super) fn emit_cop_command(&mut self, instr: &MipsCopCmdInstr) {
+        match instr.cop {
+            0 => self.emit_cop0_command(instr),
+            cop => self.emit_missing_cop(cop),
+        }
+    }
+
+    fn emit_cop0_command(&mut self, instr: &MipsCopCmdInstr) {
+        match instr.cop0_command() {
+            MipsCop0Command::Rfe => self.emit_rfe(),
+            // The PS1 has no TLB, so RFE is the only command
+            MipsCop0Command::Invalid => self.emit_reserved_instruction(),
+        }
+    }
+
+    pub(super) fn emit_cop_mem(&mut self, instr: &MipsCopMemInstr) {
+        match instr.cop {
+            // There are no COP0 loads or stores
+            0 => self.emit_reserved_instruction(),
+            cop => self.emit_missing_cop(cop),
+        }
+    }
+
+    // Raises the exception for an instruction for a coprocessor other than COP0
+    fn emit_missing_cop(&mut self, cop: u8) {
+        if self.finalized {
+            self.instr_finished_emitting();
+            return;
+        }
+
+        let i32_type = self.ctx.i32_type();
+        let name = format!("cop{}", cop);
+
+        let pc_reg = self.gep_pc(&format!("{}_{}", name, self.count_uniq));
+        let pc_val = self
+            .builder
+            .build_load(pc_reg, &format!("{}_{}_pc_val", name, self.count_uniq))
+            .into_int_value();
+        let count = self.count_uniq;
+
+        self.instr_finished_emitting();
+
+        if cop != 2 {
+            // The PS1 has no COP1 or COP3
+            self.raise_exception(
+                &cop0::ExceptionCause::CopUnusable(cop),
+                &name,
+                &pc_reg,
+                &pc_val,
+                true,
+                count,
+            );
+            return;
+        }
+
+        let cop0_sr =
+            self.gep_cop0_reg(cop0::Register::Sr as u8, &format!("{}_{}_sr", name, count));
+        let cop0_sr_val = self
+            .builder
+            .build_load(cop0_sr, &format!("{}_{}_sr_val", name, count))
+            .into_int_value();
+        let cu2 = self.builder.build_and(
+            cop0_sr_val,
+            i32_type.const_int(1 << 30, false),
+            &format!("{}_{}_cu2", name, count),
+        );
+        let unusable = self.builder.build_int_compare(
+            inkwell::IntPredicate::EQ,
+            cu2,
+            i32_type.const_zero(),
+            &format!("{}_{}_unusable", name, count),
+        );
+
+        self.raise_exception_if(
+            unusable,
+            &cop0::ExceptionCause::CopUnusable(cop),
+            &name,
+            &pc_reg,
+            &pc_val,
+            count,
+        );
+
+        // FIXME: COP2 should be handled once the GTE exists
+        self.raise_exception(
+            &cop0::ExceptionCause::ReservedInstruction,
+            &name,
+            &pc_reg,
+            &pc_val,
+            true,
+            count,
+        );
+    }
+
+    pub(super) fn gep_cop0_reg(&self, reg: u8, name: &str) -> inkwell::values::PointerValue<'ctx> {
         assert!(reg <= 15);
         self.builder
             .build_struct_gep(self.state_arg, (36 + reg) as u32, name)
@@ -40,14 +134,14 @@ impl<'ctx> TranslationBlock<'ctx> {
     ) {
         let i32_type = self.ctx.i32_type();
 
-        let pc_incr = if self.finalized {
+        let pc_incr = if self.in_delay_slot {
             // In delay slot, EPC should point to the branch instruction
             // (i.e. the one preceeding this one)
             assert!(count >= 1);
-            count * 4 - 4
+            self.pc_offset(count - 1)
         } else {
             // Outside of the delay slot, point to the current instruction
-            count * 4
+            self.pc_offset(count)
         };
 
         let pc_val = self.builder.build_int_add(
@@ -64,7 +158,7 @@ impl<'ctx> TranslationBlock<'ctx> {
 
         let mut cop0_cause_val = ((cause.to_int()) << 2) as u32;
 
-        if self.finalized {
+        if self.in_delay_slot {
             cop0_cause_val |= 1 << 31;
         }
 
@@ -76,10 +170,23 @@ impl<'ctx> TranslationBlock<'ctx> {
             cop0::Register::Cause as u8,
             &format!("syscall_{}_cause", count),
         );
-        self.builder.build_store(
-            cop0_cause_reg,
+
+        // The pending interrupt bits are left in place
+        let cop0_cause_old = self
+            .builder
+            .build_load(cop0_cause_reg, &format!("{}_{}_cause_old", instr, count))
+            .into_int_value();
+        let cop0_cause_ip = self.builder.build_and(
+            cop0_cause_old,
+            i32_type.const_int(0xff00, false),
+            &format!("{}_{}_cause_ip", instr, count),
+        );
+        let cop0_cause_new = self.builder.build_or(
+            cop0_cause_ip,
             i32_type.const_int(cop0_cause_val as u64, false),
+            &format!("{}_{}_cause_new", instr, count),
         );
+        self.builder.build_store(cop0_cause_reg, cop0_cause_new);
 
         let cop0_sr = self.gep_cop0_reg(cop0::Register::Sr as u8, &format!("{}_{}_sr", "", count));
         let cop0_sr_val = self
@@ -132,10 +239,100 @@ impl<'ctx> TranslationBlock<'ctx> {
         if main_branch {
             self.finalized = true;
         } else {
+            // The block is left early, having executed up to and including this instruction
+            self.emit_dirty_stores();
+            self.emit_exit_accounting(self.cycles + 1, count + 1);
             self.builder.build_return(None);
         }
     }
 
+    // Branches to an exception exit when `cond` is set, and continues emitting the rest of the
+    // instruction on the path where it is not.
+    // Should be called after instr_finished_emitting, with the PC value loaded before it, so that
+    // exceptions in the delay slot are attributed to the branch.
+    pub(super) fn raise_exception_if(
+        &mut self,
+        cond: inkwell::values::IntValue<'ctx>,
+        cause: &cop0::ExceptionCause,
+        instr: &str,
+        pc_reg: &inkwell::values::PointerValue<'ctx>,
+        curr_pc_val: &inkwell::values::IntValue<'ctx>,
+        count: u64,
+    ) {
+        let exc_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_{}_exception", instr, count));
+        let cont_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_{}_continue", instr, count));
+
+        self.builder
+            .build_conditional_branch(cond, exc_block, cont_block);
+
+        self.builder.position_at_end(exc_block);
+        self.raise_exception(cause, instr, pc_reg, curr_pc_val, false, count);
+
+        self.builder.position_at_end(cont_block);
+    }
+
+    // Raises an address error when `addr` isn't aligned for an access of `size` bits, recording
+    // the faulting address in BadVaddr. Follows the same conventions as raise_exception_if.
+    pub(super) fn raise_address_error_if_misaligned(
+        &mut self,
+        addr: inkwell::values::IntValue<'ctx>,
+        size: u32,
+        store: bool,
+        instr: &str,
+        pc_reg: &inkwell::values::PointerValue<'ctx>,
+        curr_pc_val: &inkwell::values::IntValue<'ctx>,
+        count: u64,
+    ) {
+        if size == 8 {
+            return;
+        }
+
+        let i32_type = self.ctx.i32_type();
+
+        let misaligned_bits = self.builder.build_and(
+            addr,
+            i32_type.const_int((size / 8 - 1) as u64, false),
+            &format!("{}_{}_misaligned_bits", instr, count),
+        );
+        let misaligned = self.builder.build_int_compare(
+            inkwell::IntPredicate::NE,
+            misaligned_bits,
+            i32_type.const_zero(),
+            &format!("{}_{}_misaligned", instr, count),
+        );
+
+        let exc_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_{}_addr_error", instr, count));
+        let cont_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_{}_aligned", instr, count));
+
+        self.builder
+            .build_conditional_branch(misaligned, exc_block, cont_block);
+
+        self.builder.position_at_end(exc_block);
+
+        let bad_vaddr = self.gep_cop0_reg(
+            cop0::Register::BadVaddr as u8,
+            &format!("{}_{}_bad_vaddr", instr, count),
+        );
+        self.builder.build_store(bad_vaddr, addr);
+
+        let cause = if store {
+            cop0::ExceptionCause::AddressErrOnStore
+        } else {
+            cop0::ExceptionCause::AddressErrOnLoad
+        };
+        self.raise_exception(&cause, instr, pc_reg, curr_pc_val, false, count);
+
+        self.builder.position_at_end(cont_block);
+    }
+
     pub(super) fn emit_syscall(&mut self, instr: &MipsRInstr) {
         if self.finalized {
             self.instr_finished_emitting();
@@ -186,6 +383,112 @@ impl<'ctx> TranslationBlock<'ctx> {
         );
     }
 
+    pub(super) fn emit_reserved_instruction(&mut self) {
+        if self.finalized {
+            self.instr_finished_emitting();
+            return;
+        }
+
+        let pc_reg = self.gep_pc(&format!("ri_{}", self.count_uniq));
+        let pc_val = self
+            .builder
+            .build_load(pc_reg, &format!("ri_{}_pc_val", self.count_uniq))
+            .into_int_value();
+        let count = self.count_uniq;
+
+        self.instr_finished_emitting();
+
+        self.raise_exception(
+            &cop0::ExceptionCause::ReservedInstruction,
+            "ri",
+            &pc_reg,
+            &pc_val,
+            true,
+            count,
+        );
+    }
+
+    pub(super) fn emit_instruction_bus_error(&mut self, addr: u32) {
+        if self.finalized {
+            self.instr_finished_emitting();
+            return;
+        }
+
+        let i32_type = self.ctx.i32_type();
+
+        let pc_reg = self.gep_pc(&format!("ibe_{}", self.count_uniq));
+        let pc_val = self
+            .builder
+            .build_load(pc_reg, &format!("ibe_{}_pc_val", self.count_uniq))
+            .into_int_value();
+        let count = self.count_uniq;
+
+        self.instr_finished_emitting();
+        self.emit_writeback();
+
+        let fetch_error_fn = self.module.get_function("tb_fetch_error").unwrap();
+        self.builder.build_call(
+            fetch_error_fn,
+            &[
+                self.bus_arg.into(),
+                self.state_arg.into(),
+                i32_type.const_int(addr as u64, false).into(),
+            ],
+            &format!("ibe_{}_fetch_error", count),
+        );
+
+        self.raise_exception(
+            &cop0::ExceptionCause::InstructionBusError,
+            "ibe",
+            &pc_reg,
+            &pc_val,
+            true,
+            count,
+        );
+    }
+
+    fn emit_rfe(&mut self) {
+        if self.finalized {
+            self.instr_finished_emitting();
+            return;
+        }
+
+        let i32_type = self.ctx.i32_type();
+        let count = self.count_uniq;
+
+        let cop0_sr = self.gep_cop0_reg(cop0::Register::Sr as u8, &format!("rfe_{}_sr", count));
+        let cop0_sr_val = self
+            .builder
+            .build_load(cop0_sr, &format!("rfe_{}_sr_val", count))
+            .into_int_value();
+
+        // Pop the KU/IE mode stack, leaving the old mode bits in place
+        let cop0_sr_clear_mode = self.builder.build_and(
+            cop0_sr_val,
+            i32_type.const_int(!0xf, false),
+            &format!("rfe_{}_clear_mode", count),
+        );
+        let cop0_sr_mode_shift = self.builder.build_right_shift(
+            cop0_sr_val,
+            i32_type.const_int(2, false),
+            false,
+            &format!("rfe_{}_mode_shift", count),
+        );
+        let cop0_sr_new_mode = self.builder.build_and(
+            cop0_sr_mode_shift,
+            i32_type.const_int(0xf, false),
+            &format!("rfe_{}_new_mode", count),
+        );
+        let cop0_sr_new_val = self.builder.build_or(
+            cop0_sr_clear_mode,
+            cop0_sr_new_mode,
+            &format!("rfe_{}_new_sr", count),
+        );
+
+        self.instr_finished_emitting();
+        self.builder.build_store(cop0_sr, cop0_sr_new_val);
+    }
+
     fn emit_cop0_mtc(&mut self, instr: &MipsCopInstr) {
         if self.finalized {
             self.instr_finished_emitting();
@@ -221,7 +524,13 @@ impl<'ctx> TranslationBlock<'ctx> {
         let delay_val = self.gep_load_delay_value(&format!("mfc0_{}_delay_val", self.count_uniq));
         self.builder.build_store(delay_val, cop_val);
 
-        self.delay_slot_hazard = Some(|tb| tb.apply_load_delay_if_present());
+        // The value arrives like that of a load, so is completed the same way
+        if self.finalized {
+            return;
+        }
+
+        self.delay_slot_load_register = Some(instr.t_reg);
+        self.delay_slot_hazard = Some(Self::load_delay_slot_action);
     }
 }
 
@@ -249,6 +558,24 @@ mod test {
         assert_eq!(state.gpr[1], value);
     }
 
+    #[test]
+    fn jit_test_rfe() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 0b11_0110);
+        th.push_instr("mtc0", super::cop0::Register::Sr as u8, 0, 1, 0, 0);
+        th.push_instr("rfe", 0, 0, 0, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Sr as usize],
+            0b11_1101
+        );
+    }
+
     #[test]
     fn jit_test_syscall() {
         let mut th = TestHarness::default();
@@ -359,6 +686,45 @@ mod test {
         );
     }
 
+    #[test]
+    fn jit_test_reserved_instruction() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_raw(0xfc00_0000);
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(state.cop0_reg[super::cop0::Register::Epc as usize], 0x1000);
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
+
+    #[test]
+    fn jit_test_reserved_instruction_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("jr", 31, 0, 0, 0, 0);
+        th.push_raw(0xfc00_0000);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(state.cop0_reg[super::cop0::Register::Epc as usize], 0x1000);
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Cause as usize],
+            (1 << 31) | (0xa << 2)
+        );
+    }
+
     #[test]
     fn jit_test_break_bev() {
         let mut th = TestHarness::default();
@@ -386,4 +752,102 @@ mod test {
             0x9 << 2
         );
     }
+
+    #[test]
+    fn jit_test_instruction_bus_error() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.addr = 0x0800_0000;
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Epc as usize],
+            0x0800_0000
+        );
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Cause as usize],
+            0x6 << 2
+        );
+    }
+
+    #[test]
+    fn jit_test_cop2_unusable() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("mfc2", 1, 0, 1, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(state.cop0_reg[super::cop0::Register::Epc as usize], 0x1000);
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Cause as usize],
+            (2 << 28) | (0xb << 2)
+        );
+    }
+
+    #[test]
+    fn jit_test_swc3_unusable_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("jr", 31, 0, 0, 0, 0);
+        th.push_instr("swc3", 0, 0, 1, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(state.cop0_reg[super::cop0::Register::Epc as usize], 0x1000);
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Cause as usize],
+            (1 << 31) | (3 << 28) | (0xb << 2)
+        );
+    }
+
+    #[test]
+    fn jit_test_exception_preserves_pending_interrupts() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 1 << 8);
+        th.push_instr("mtc0", super::cop0::Register::Cause as u8, 0, 1, 0, 0);
+        th.push_instr("syscall", 0, 0, 0, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(
+            state.cop0_reg[super::cop0::Register::Cause as usize],
+            (1 << 8) | (0x8 << 2)
+        );
+    }
+
+    #[test]
+    fn jit_test_cop0_reserved_command() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // TLBR, which the PS1 has no TLB for
+        th.push_raw(0x4200_0001);
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
 }
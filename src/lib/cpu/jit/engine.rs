@@ -0,0 +1,256 @@
+use crate::cpu::llvm::JitConfig;
+use inkwell::context::Context;
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::module::Module;
+use inkwell::types::{FunctionType, PointerType};
+
+// Blocks compiled into an execution engine before a fresh one is started. An engine only releases
+// its code memory once every block compiled into it has been dropped, so this bounds how much an
+// engine holds on to for blocks invalidated long ago.
+const ENGINE_BLOCKS: u64 = 512;
+
+// What every block is compiled against: the types of the state and helpers, which only need
+// creating once per context, and the execution engine blocks are currently being added to.
+// Each block still gets a module of its own, since a module can't be added to once MCJIT has
+// compiled it, but setting up a module is cheap compared to setting up an engine.
+pub(super) struct Runtime<'ctx> {
+    ctx: &'ctx Context,
+    pub(super) config: JitConfig,
+    pub(super) state_type: PointerType<'ctx>,
+    pub(super) bus_type: PointerType<'ctx>,
+    pub(super) mgr_type: PointerType<'ctx>,
+    // Helpers the blocks call, by name, with their signature and address
+    helpers: Vec<(&'static str, FunctionType<'ctx>, usize)>,
+
+    ee: Option<ExecutionEngine<'ctx>>,
+    ee_blocks: u64,
+    // Serial number for the next block, which keeps function names unique within an engine even
+    // when the same address is translated again
+    next_id: u64,
+}
+
+impl<'ctx> Runtime<'ctx> {
+    pub(super) fn new(ctx: &'ctx Context, config: JitConfig) -> Self {
+        let i32_type = ctx.i32_type();
+        let i8_type = ctx.i8_type();
+        let i64_type = ctx.i64_type();
+        let bool_type = ctx.bool_type();
+        let void_type = ctx.void_type();
+
+        let mips_state_type = ctx.opaque_struct_type("mips_state");
+        let mut state_fields = vec![i32_type.into(); 52];
+        // Cycle count, chain limit and chained instruction count
+        state_fields.extend([i64_type.into(); 3]);
+        mips_state_type.set_body(&state_fields, false);
+        let state_type = mips_state_type.ptr_type(inkwell::AddressSpace::Generic);
+
+        let bus_type = ctx
+            .opaque_struct_type("mips_bus")
+            .ptr_type(inkwell::AddressSpace::Generic);
+        let mgr_type = ctx
+            .opaque_struct_type("tb_manager")
+            .ptr_type(inkwell::AddressSpace::Generic);
+
+        let helpers = vec![
+            (
+                "tb_mem_read",
+                bool_type.fn_type(
+                    &[
+                        bus_type.into(),
+                        mgr_type.into(),
+                        state_type.ptr_type(inkwell::AddressSpace::Generic).into(),
+                        i32_type.into(),
+                        i32_type.into(),
+                        i8_type.into(),
+                        bool_type.into(),
+                    ],
+                    false,
+                ),
+                super::tb_mem_read as usize,
+            ),
+            (
+                "tb_mem_write",
+                bool_type.fn_type(
+                    &[
+                        bus_type.into(),
+                        mgr_type.into(),
+                        state_type.into(),
+                        i32_type.into(),
+                        i32_type.into(),
+                        i32_type.into(),
+                    ],
+                    false,
+                ),
+                super::tb_mem_write as usize,
+            ),
+            (
+                "tb_fetch_error",
+                void_type.fn_type(
+                    &[bus_type.into(), state_type.into(), i32_type.into()],
+                    false,
+                ),
+                super::tb_fetch_error as usize,
+            ),
+            (
+                "tb_hilo_start",
+                void_type.fn_type(
+                    &[
+                        state_type.into(),
+                        i8_type.into(),
+                        i32_type.into(),
+                        i32_type.into(),
+                    ],
+                    false,
+                ),
+                super::tb_hilo_start as usize,
+            ),
+            (
+                "tb_hilo_wait",
+                void_type.fn_type(&[state_type.into(), i32_type.into()], false),
+                super::tb_hilo_wait as usize,
+            ),
+            (
+                "tb_request_link",
+                void_type.fn_type(&[mgr_type.into(), i32_type.into()], false),
+                super::chain::tb_request_link as usize,
+            ),
+        ];
+
+        Self {
+            ctx,
+            config,
+            state_type,
+            bus_type,
+            mgr_type,
+            helpers,
+            ee: None,
+            ee_blocks: 0,
+            next_id: 0,
+        }
+    }
+
+    // Declares the helpers in a block's module, for the block to call
+    pub(super) fn declare_helpers(&self, module: &Module<'ctx>) {
+        for (name, fn_type, _) in &self.helpers {
+            module.add_function(name, *fn_type, None);
+        }
+    }
+
+    // Takes an id for a new block, along with the engine to compile it with
+    pub(super) fn allocate(&mut self) -> Result<(u64, ExecutionEngine<'ctx>), String> {
+        if self.ee_blocks >= ENGINE_BLOCKS {
+            self.retire_engine();
+        }
+
+        let ee = match self.ee.as_ref() {
+            Some(ee) => ee.clone(),
+            None => {
+                let ee = self.new_engine()?;
+                self.ee = Some(ee.clone());
+                ee
+            }
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ee_blocks += 1;
+        Ok((id, ee))
+    }
+
+    // Stops adding blocks to the current engine. It lives on until the blocks in it are dropped.
+    pub(super) fn retire_engine(&mut self) {
+        self.ee = None;
+        self.ee_blocks = 0;
+    }
+
+    fn new_engine(&self) -> Result<ExecutionEngine<'ctx>, String> {
+        // The engine needs a module to be created with. The helpers are mapped through it, and
+        // MCJIT resolves the declarations in the modules added later by name.
+        let module = self
+            .ctx
+            .create_module(&format!("tb_engine_{}", self.next_id));
+        let ee = self.config.create_engine(&module)?;
+
+        for (name, fn_type, addr) in &self.helpers {
+            let func = module.add_function(name, *fn_type, None);
+            ee.add_global_mapping(&func, *addr);
+        }
+
+        Ok(ee)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::backend::run_until_halted;
+    use crate::cpu::bus::BusDevice;
+    use crate::cpu::jit::harness::TestHarness;
+    use crate::cpu::jit::{Jit, TbManager};
+    use crate::cpu::llvm::{JitConfig, OptLevel};
+
+    #[test]
+    fn jit_test_shared_engine_retranslate() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 5, 0);
+        th.finish();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut tb_mgr = TbManager::new();
+
+                let tb = tb_mgr.get_tb(&ctx, 0x1000, bus)?;
+                tb.execute(state, bus, &mut tb_mgr)?;
+                assert_eq!(state.gpr[0], 5);
+
+                let instr = crate::cpu::decode::mips_encode_str("addiu", 0, 0, 1, 7, 0).unwrap();
+                bus.write(0x1000, 32, instr).unwrap();
+                tb_mgr.invalidate(0x1000);
+
+                // The new block has a function of its own, alongside the old one in the engine
+                let tb = tb_mgr.get_tb(&ctx, 0x1000, bus)?;
+                tb.execute(state, bus, &mut tb_mgr)?;
+                assert_eq!(state.gpr[0], 7);
+                assert_eq!(tb_mgr.runtime.as_ref().unwrap().ee_blocks, 2);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn jit_test_optimized_pipeline() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.push_instr("addu", 2, 2, 1, 0, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.push_instr("bne", 0, 1, 0, -3i16 as u16, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let config = JitConfig {
+                    opt_level: OptLevel::Aggressive,
+                    passes: JitConfig::parse_passes(
+                        "mem2reg,instcombine,reassociate,gvn,early-cse,sccp,dse,simplifycfg,adce",
+                    )?,
+                    ..JitConfig::default()
+                };
+                let mut backend = Jit::with_config(&ctx, config);
+                run_until_halted(&mut backend, bus, state).map(|_| ())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.gpr[1], 55);
+    }
+}
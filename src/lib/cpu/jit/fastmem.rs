@@ -0,0 +1,361 @@
+use super::TranslationBlock;
+use crate::cpu::bus::HostRegion;
+use crate::cpu::cop0;
+use inkwell::basic_block::BasicBlock;
+use inkwell::values::{BasicValue, BasicValueEnum, IntValue, PointerValue};
+
+// Loads and stores to memory the bus backs with host memory, such as RAM, are done in place by
+// the block. Everything else, and anything the helpers have to see, goes through the helpers.
+impl<'ctx> TranslationBlock<'ctx> {
+    // Branches to a fast path for each host region `addr` could fall in, where `access` is emitted
+    // with a pointer to the host memory, and on to `slow_block` for addresses outside all of them.
+    // Returns the fast path blocks, which all continue to `done_block`.
+    fn emit_host_access(
+        &self,
+        addr: IntValue<'ctx>,
+        size: u32,
+        name: &str,
+        slow_block: BasicBlock<'ctx>,
+        done_block: BasicBlock<'ctx>,
+        access: impl Fn(&Self, PointerValue<'ctx>, &HostRegion),
+    ) -> Vec<BasicBlock<'ctx>> {
+        let i32_type = self.ctx.i32_type();
+        let i64_type = self.ctx.i64_type();
+        let ptr_type = self
+            .ctx
+            .custom_width_int_type(size)
+            .ptr_type(inkwell::AddressSpace::Generic);
+
+        let phys = self.builder.build_and(
+            addr,
+            i32_type.const_int(0x1fff_ffff, false),
+            &format!("{}_phys", name),
+        );
+
+        let mut fast_blocks = Vec::new();
+        for (i, region) in self.host_regions.iter().enumerate() {
+            let offset = self.builder.build_int_sub(
+                phys,
+                i32_type.const_int(region.addr as u64, false),
+                &format!("{}_host{}_offset", name, i),
+            );
+            let in_region = self.builder.build_int_compare(
+                inkwell::IntPredicate::ULT,
+                offset,
+                i32_type.const_int(region.size as u64, false),
+                &format!("{}_in_host{}", name, i),
+            );
+
+            let fast_block = self
+                .ctx
+                .append_basic_block(self.func, &format!("{}_host{}", name, i));
+            let next_block = self
+                .ctx
+                .append_basic_block(self.func, &format!("{}_not_host{}", name, i));
+            self.builder
+                .build_conditional_branch(in_region, fast_block, next_block);
+
+            self.builder.position_at_end(fast_block);
+            let offset_64 =
+                self.builder
+                    .build_int_z_extend(offset, i64_type, &format!("{}_offset_64", name));
+            let host_base = self
+                .host_region_global(i)
+                .as_pointer_value()
+                .const_to_int(i64_type);
+            let host_addr =
+                self.builder
+                    .build_int_add(host_base, offset_64, &format!("{}_host_addr", name));
+            let host_ptr =
+                self.builder
+                    .build_int_to_ptr(host_addr, ptr_type, &format!("{}_host_ptr", name));
+            access(self, host_ptr, region);
+            self.builder.build_unconditional_branch(done_block);
+            fast_blocks.push(fast_block);
+
+            self.builder.position_at_end(next_block);
+        }
+
+        self.builder.build_unconditional_branch(slow_block);
+        fast_blocks
+    }
+
+    // Joins the fast paths and the helper call into the access's success flag
+    fn emit_access_result(
+        &self,
+        fast_blocks: &[BasicBlock<'ctx>],
+        slow_block: BasicBlock<'ctx>,
+        slow_result: BasicValueEnum<'ctx>,
+        name: &str,
+    ) -> BasicValueEnum<'ctx> {
+        let bool_type = self.ctx.bool_type();
+        let success = self
+            .builder
+            .build_phi(bool_type, &format!("{}_success", name));
+
+        let fast_success = bool_type.const_int(1, false);
+        for block in fast_blocks {
+            success.add_incoming(&[(&fast_success, *block)]);
+        }
+        success.add_incoming(&[(&slow_result, slow_block)]);
+
+        success.as_basic_value()
+    }
+
+    // Reads `size` bits at `addr` into the load delay value, staging the load into `reg`.
+    // Returns whether the read succeeded.
+    pub(super) fn mem_read(
+        &mut self,
+        addr: IntValue<'ctx>,
+        size: u32,
+        reg: u8,
+        sign_extend: bool,
+        name: &str,
+    ) -> BasicValueEnum<'ctx> {
+        let i8_type = self.ctx.i8_type();
+        let i32_type = self.ctx.i32_type();
+        let i64_type = self.ctx.i64_type();
+        let bool_type = self.ctx.bool_type();
+
+        let slow_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_slow", name));
+        let done_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_done", name));
+
+        let fast_blocks = self.emit_host_access(
+            addr,
+            size,
+            name,
+            slow_block,
+            done_block,
+            |tb, host_ptr, region| {
+                let val = tb
+                    .builder
+                    .build_load(host_ptr, &format!("{}_host_val", name));
+                if let Some(load) = val.as_instruction_value() {
+                    // The host memory is only byte aligned
+                    load.set_alignment(1).unwrap();
+                }
+
+                let val = if size == 32 {
+                    val.into_int_value()
+                } else if sign_extend {
+                    tb.builder.build_int_s_extend(
+                        val.into_int_value(),
+                        i32_type,
+                        &format!("{}_host_sext", name),
+                    )
+                } else {
+                    tb.builder.build_int_z_extend(
+                        val.into_int_value(),
+                        i32_type,
+                        &format!("{}_host_zext", name),
+                    )
+                };
+
+                let delay_val = tb.gep_load_delay_value(&format!("{}_host", name));
+                tb.builder.build_store(delay_val, val);
+                let delay_reg = tb.gep_load_delay_register(&format!("{}_host", name));
+                tb.builder
+                    .build_store(delay_reg, i32_type.const_int(reg as u64, false));
+
+                if region.wait_states != 0 {
+                    let cycles_ptr = tb.gep_cycles(&format!("{}_host", name));
+                    let cycles = tb
+                        .builder
+                        .build_load(cycles_ptr, &format!("{}_host_cycles", name));
+                    let new_cycles = tb.builder.build_int_add(
+                        cycles.into_int_value(),
+                        i64_type.const_int(region.wait_states as u64, false),
+                        &format!("{}_host_cycles_new", name),
+                    );
+                    tb.builder.build_store(cycles_ptr, new_cycles);
+                }
+            },
+        );
+
+        self.builder.position_at_end(slow_block);
+        self.emit_dirty_stores();
+        let read_fn = self.module.get_function("tb_mem_read").unwrap();
+        let slow_result = self
+            .builder
+            .build_call(
+                read_fn,
+                &[
+                    self.bus_arg.into(),
+                    self.mgr_arg.into(),
+                    self.state_arg.into(),
+                    addr.into(),
+                    i32_type.const_int(size as u64, false).into(),
+                    i8_type.const_int(reg as u64, false).into(),
+                    bool_type.const_int(sign_extend as u64, false).into(),
+                ],
+                name,
+            )
+            .try_as_basic_value()
+            .left()
+            .unwrap();
+        self.builder.build_unconditional_branch(done_block);
+
+        self.builder.position_at_end(done_block);
+        self.emit_access_result(&fast_blocks, slow_block, slow_result, name)
+    }
+
+    // Writes the low `size` bits of `value` to `addr`, returning whether the write succeeded.
+    // Stores to pages holding translated code, and stores while the cache is isolated, are left to
+    // the helper, which handles them.
+    pub(super) fn mem_write(
+        &mut self,
+        addr: IntValue<'ctx>,
+        size: u32,
+        value: IntValue<'ctx>,
+        name: &str,
+    ) -> BasicValueEnum<'ctx> {
+        let i32_type = self.ctx.i32_type();
+        let i64_type = self.ctx.i64_type();
+        let int_type = self.ctx.custom_width_int_type(size);
+
+        let slow_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_slow", name));
+        let done_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_done", name));
+
+        let sr_ptr = self.gep_cop0_reg(cop0::Register::Sr as u8, &format!("{}_sr", name));
+        let sr = self
+            .builder
+            .build_load(sr_ptr, &format!("{}_sr_val", name))
+            .into_int_value();
+        let isolated_bit = self.builder.build_and(
+            sr,
+            i32_type.const_int(1 << 16, false),
+            &format!("{}_isolated_bit", name),
+        );
+        let isolated = self.builder.build_int_compare(
+            inkwell::IntPredicate::NE,
+            isolated_bit,
+            i32_type.const_zero(),
+            &format!("{}_isolated", name),
+        );
+
+        // Test the page's bit in the code map
+        let phys = self.builder.build_and(
+            addr,
+            i32_type.const_int(0x1fff_ffff, false),
+            &format!("{}_code_phys", name),
+        );
+        let page = self.builder.build_right_shift(
+            phys,
+            i32_type.const_int(crate::cpu::code_map::PAGE_SHIFT as u64, false),
+            false,
+            &format!("{}_page", name),
+        );
+        let word = self.builder.build_right_shift(
+            page,
+            i32_type.const_int(6, false),
+            false,
+            &format!("{}_page_word", name),
+        );
+        let word_offset = self.builder.build_int_mul(
+            self.builder
+                .build_int_z_extend(word, i64_type, &format!("{}_page_word_64", name)),
+            i64_type.const_int(8, false),
+            &format!("{}_page_word_offset", name),
+        );
+        let code_pages = self
+            .code_pages_global()
+            .as_pointer_value()
+            .const_to_int(i64_type);
+        let word_addr = self.builder.build_int_add(
+            code_pages,
+            word_offset,
+            &format!("{}_page_word_addr", name),
+        );
+        let word_ptr = self.builder.build_int_to_ptr(
+            word_addr,
+            i64_type.ptr_type(inkwell::AddressSpace::Generic),
+            &format!("{}_page_word_ptr", name),
+        );
+        let pages = self
+            .builder
+            .build_load(word_ptr, &format!("{}_page_bits", name))
+            .into_int_value();
+        let bit = self.builder.build_int_z_extend(
+            self.builder.build_and(
+                page,
+                i32_type.const_int(63, false),
+                &format!("{}_page_bit", name),
+            ),
+            i64_type,
+            &format!("{}_page_bit_64", name),
+        );
+        let page_bit = self.builder.build_and(
+            self.builder
+                .build_right_shift(pages, bit, false, &format!("{}_page_shift", name)),
+            i64_type.const_int(1, false),
+            &format!("{}_page_has_code", name),
+        );
+        let has_code = self.builder.build_int_compare(
+            inkwell::IntPredicate::NE,
+            page_bit,
+            i64_type.const_zero(),
+            &format!("{}_has_code", name),
+        );
+
+        let needs_helper =
+            self.builder
+                .build_or(isolated, has_code, &format!("{}_needs_helper", name));
+        let fast_check_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("{}_fast_check", name));
+        self.builder
+            .build_conditional_branch(needs_helper, slow_block, fast_check_block);
+        self.builder.position_at_end(fast_check_block);
+
+        let fast_blocks = self.emit_host_access(
+            addr,
+            size,
+            name,
+            slow_block,
+            done_block,
+            |tb, host_ptr, _| {
+                let val = if size == 32 {
+                    value
+                } else {
+                    tb.builder
+                        .build_int_truncate(value, int_type, &format!("{}_host_val", name))
+                };
+                let store = tb.builder.build_store(host_ptr, val);
+                store.set_alignment(1).unwrap();
+            },
+        );
+
+        self.builder.position_at_end(slow_block);
+        self.emit_dirty_stores();
+        let write_fn = self.module.get_function("tb_mem_write").unwrap();
+        let slow_result = self
+            .builder
+            .build_call(
+                write_fn,
+                &[
+                    self.bus_arg.into(),
+                    self.mgr_arg.into(),
+                    self.state_arg.into(),
+                    addr.into(),
+                    i32_type.const_int(size as u64, false).into(),
+                    value.into(),
+                ],
+                name,
+            )
+            .try_as_basic_value()
+            .left()
+            .unwrap();
+        self.builder.build_unconditional_branch(done_block);
+
+        self.builder.position_at_end(done_block);
+        self.emit_access_result(&fast_blocks, slow_block, slow_result, name)
+    }
+}
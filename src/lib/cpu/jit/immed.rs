@@ -12,9 +12,6 @@ impl<'ctx> TranslationBlock<'ctx> {
         let immed = (instr.immediate as i16) as i32;
         let const_imm = i32_type.const_int(immed as u64, true);
 
-        let dest_reg =
-            self.gep_gp_register(instr.t_reg, &format!("addiu_{}_dest", self.count_uniq));
-
         let src_reg = self.get_gpr_value(instr.s_reg, &format!("addiu_{}", self.count_uniq));
 
         let add_res =
@@ -22,7 +19,16 @@ impl<'ctx> TranslationBlock<'ctx> {
                 .build_int_add(src_reg, const_imm, &format!("addiu_{}", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(dest_reg, add_res);
+        self.set_gpr_value(instr.t_reg, add_res);
+    }
+
+    pub(super) fn emit_addi(&mut self, instr: &decode::MipsIInstr) {
+        let i32_type = self.ctx.i32_type();
+        let immed = (instr.immediate as i16) as i32;
+        let const_imm = i32_type.const_int(immed as u64, true);
+
+        let src_reg = self.get_gpr_value(instr.s_reg, &format!("addi_{}", self.count_uniq));
+        self.emit_overflow_checked("addi", instr.t_reg, src_reg, const_imm, false);
     }
 
     pub(super) fn emit_andi(&mut self, instr: &decode::MipsIInstr) {
@@ -36,14 +42,12 @@ impl<'ctx> TranslationBlock<'ctx> {
         let s_val = self.get_gpr_value(instr.s_reg, &format!("andi_{}", self.count_uniq));
         let immed = i32_type.const_int(instr.immediate as u64, true);
 
-        let t_reg = self.gep_gp_register(instr.t_reg, &format!("andi_{}_t_reg", self.count_uniq));
-
         let and_val =
             self.builder
                 .build_and(s_val, immed, &format!("andi_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(t_reg, and_val);
+        self.set_gpr_value(instr.t_reg, and_val);
     }
 
     pub(super) fn emit_ori(&mut self, instr: &decode::MipsIInstr) {
@@ -57,14 +61,12 @@ impl<'ctx> TranslationBlock<'ctx> {
         let s_val = self.get_gpr_value(instr.s_reg, &format!("ori_{}", self.count_uniq));
         let immed = i32_type.const_int(instr.immediate as u64, true);
 
-        let t_reg = self.gep_gp_register(instr.t_reg, &format!("ori_{}_t_reg", self.count_uniq));
-
         let or_val = self
             .builder
             .build_or(s_val, immed, &format!("ori_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(t_reg, or_val);
+        self.set_gpr_value(instr.t_reg, or_val);
     }
 
     pub(super) fn emit_xori(&mut self, instr: &decode::MipsIInstr) {
@@ -78,14 +80,12 @@ impl<'ctx> TranslationBlock<'ctx> {
         let s_val = self.get_gpr_value(instr.s_reg, &format!("xori_{}", self.count_uniq));
         let immed = i32_type.const_int(instr.immediate as u64, true);
 
-        let t_reg = self.gep_gp_register(instr.t_reg, &format!("xori_{}_t_reg", self.count_uniq));
-
         let xor_val =
             self.builder
                 .build_xor(s_val, immed, &format!("xori_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(t_reg, xor_val);
+        self.set_gpr_value(instr.t_reg, xor_val);
     }
 
     pub(super) fn emit_slti(&mut self, instr: &decode::MipsIInstr) {
@@ -110,10 +110,9 @@ impl<'ctx> TranslationBlock<'ctx> {
             i32_type,
             &format!("slti_{}_zext", self.count_uniq),
         );
-        let t_reg = self.gep_gp_register(instr.t_reg, &format!("slti_{}_t_reg", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(t_reg, cmp_zext);
+        self.set_gpr_value(instr.t_reg, cmp_zext);
     }
 
     pub(super) fn emit_sltiu(&mut self, instr: &decode::MipsIInstr) {
@@ -138,10 +137,9 @@ impl<'ctx> TranslationBlock<'ctx> {
             i32_type,
             &format!("sltiu_{}_zext", self.count_uniq),
         );
-        let t_reg = self.gep_gp_register(instr.t_reg, &format!("sltiu_{}_t_reg", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(t_reg, cmp_zext);
+        self.set_gpr_value(instr.t_reg, cmp_zext);
     }
 
     pub(super) fn emit_lui(&mut self, instr: &decode::MipsIInstr) {
@@ -152,11 +150,10 @@ impl<'ctx> TranslationBlock<'ctx> {
 
         let i32_type = self.ctx.i32_type();
 
-        let t_reg = self.gep_gp_register(instr.t_reg, &format!("lui_{}_t_reg", self.count_uniq));
         let immed = i32_type.const_int((instr.immediate as u64) << 16, true);
 
         self.instr_finished_emitting();
-        self.builder.build_store(t_reg, immed);
+        self.set_gpr_value(instr.t_reg, immed);
     }
 }
 
@@ -179,6 +176,39 @@ mod test {
         assert_eq!(state.gpr[0], val);
     }
 
+    #[test]
+    fn jit_test_addi() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.push_dummy_load(1);
+        th.push_instr("addi", 0, 0, 1, -42i16 as u16, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], -42i32 as u32);
+    }
+
+    #[test]
+    fn jit_test_addi_overflow() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.load32(1, 0x7fff_fff0);
+        th.push_instr("addi", 0, 1, 1, 0x10, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0x7fff_fff0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xc << 2
+        );
+    }
+
     #[test]
     fn jit_test_andi() {
         let mut th = TestHarness::default();
@@ -4,10 +4,20 @@ use super::TranslationBlock;
 
 impl<'ctx> TranslationBlock<'ctx> {
     fn jump_delay_slot_action<'a, 'b>(tb: &'a mut TranslationBlock<'b>) {
+        tb.in_delay_slot = true;
         let arg = tb.delay_slot_arg.as_ref().unwrap();
+        let (count, target, value) = (arg.count, arg.target, arg.value);
+
+        // A target known ahead of time is translated as part of the block where possible
+        if let Some(target) = target {
+            if tb.follow_jump(target.wrapping_sub(tb.pc)) {
+                return;
+            }
+            tb.add_successor(target);
+        }
 
-        let pc = tb.gep_pc(&format!("j_{}_ds_pc_gep", arg.count));
-        tb.builder.build_store(pc, arg.value);
+        let pc = tb.gep_pc(&format!("j_{}_ds_pc_gep", count));
+        tb.builder.build_store(pc, value);
         tb.finalized = true;
     }
 
@@ -34,6 +44,7 @@ impl<'ctx> TranslationBlock<'ctx> {
             pc_mask,
             &format!("j_{}_target", self.count_uniq),
         );
+        let target = (self.pc & 0xe000_0000) | (instr.target << 2);
 
         let count = self.count_uniq;
         self.instr_finished_emitting();
@@ -41,6 +52,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: 0,
+            target: Some(target),
             value: target_v.into(),
         });
         self.delay_slot_hazard = Some(Self::jump_delay_slot_action);
@@ -50,9 +62,11 @@ impl<'ctx> TranslationBlock<'ctx> {
         let i32_type = self.ctx.i32_type();
 
         let pc = self.gep_pc(&format!("jal_{}", self.count_uniq));
-        let ra = self.gep_gp_register(31, &format!("jal_{}_ra", self.count_uniq));
 
-        let pc_incr = i32_type.const_int(self.count_uniq * 4 + 8, false);
+        let pc_incr = i32_type.const_int(
+            self.pc_offset(self.count_uniq).wrapping_add(8) as u64,
+            false,
+        );
 
         let pc_val = self
             .builder
@@ -74,8 +88,9 @@ impl<'ctx> TranslationBlock<'ctx> {
             pc_mask,
             &format!("jal_{}_target", self.count_uniq),
         );
+        let target = (self.pc & 0xe000_0000) | (instr.target << 2);
 
-        self.builder.build_store(ra, ra_val);
+        self.set_gpr_value(31, ra_val);
 
         let count = self.count_uniq;
         self.instr_finished_emitting();
@@ -83,6 +98,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: 0,
+            target: Some(target),
             value: target_v.into(),
         });
         self.delay_slot_hazard = Some(Self::jump_delay_slot_action);
@@ -97,6 +113,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: 0,
+            target: None,
             value: target_v.into(),
         });
         self.delay_slot_hazard = Some(Self::jump_delay_slot_action);
@@ -107,9 +124,11 @@ impl<'ctx> TranslationBlock<'ctx> {
         let target_v = self.get_gpr_value(instr.s_reg, &format!("jalr_{}", self.count_uniq));
 
         let pc = self.gep_pc(&format!("jalr_{}", self.count_uniq));
-        let ra = self.gep_gp_register(instr.d_reg, &format!("jalr_{}_d_ra", self.count_uniq));
 
-        let pc_incr = i32_type.const_int(self.count_uniq * 4 + 8, false);
+        let pc_incr = i32_type.const_int(
+            self.pc_offset(self.count_uniq).wrapping_add(8) as u64,
+            false,
+        );
 
         let pc_val = self
             .builder
@@ -120,7 +139,7 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("jalr_{}_ra_val", self.count_uniq),
         );
 
-        self.builder.build_store(ra, ra_val);
+        self.set_gpr_value(instr.d_reg, ra_val);
 
         let count = self.count_uniq;
         self.instr_finished_emitting();
@@ -128,6 +147,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.delay_slot_arg = Some(DelaySlotArg {
             count,
             immed: 0,
+            target: None,
             value: target_v.into(),
         });
         self.delay_slot_hazard = Some(Self::jump_delay_slot_action);
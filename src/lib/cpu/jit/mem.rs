@@ -1,13 +1,14 @@
 use super::decode;
 use super::TranslationBlock;
+use crate::cpu::cop0;
 
 impl<'ctx> TranslationBlock<'ctx> {
-    fn load_delay_slot_action<'a, 'b>(tb: &'a mut TranslationBlock<'b>) {
-        tb.apply_load_delay_if_present();
-        tb.delay_slot_load_register = None;
+    pub(super) fn load_delay_slot_action<'a, 'b>(tb: &'a mut TranslationBlock<'b>) {
+        let reg = tb.delay_slot_load_register.take().unwrap();
+        tb.apply_load_delay(reg);
     }
 
-    fn decode_vaddr(&self, instr: &decode::MipsIInstr) -> inkwell::values::IntValue<'ctx> {
+    fn decode_vaddr(&mut self, instr: &decode::MipsIInstr) -> inkwell::values::IntValue<'ctx> {
         let i32_type = self.ctx.i32_type();
         let offset = i32_type.const_int(instr.immediate as u64, true);
 
@@ -23,6 +24,31 @@ impl<'ctx> TranslationBlock<'ctx> {
         )
     }
 
+    // Raises a bus error if the memory helper reports that the access failed.
+    // Follows the same conventions as raise_exception_if.
+    fn raise_bus_error_if_failed(
+        &mut self,
+        success: inkwell::values::BasicValueEnum<'ctx>,
+        instr: &str,
+        pc_reg: &inkwell::values::PointerValue<'ctx>,
+        curr_pc_val: &inkwell::values::IntValue<'ctx>,
+        count: u64,
+    ) {
+        let failed = self.builder.build_not(
+            success.into_int_value(),
+            &format!("{}_{}_failed", instr, count),
+        );
+
+        self.raise_exception_if(
+            failed,
+            &cop0::ExceptionCause::DataBusError,
+            instr,
+            pc_reg,
+            curr_pc_val,
+            count,
+        );
+    }
+
     pub(super) fn emit_load_sized(&mut self, size: u32, instr: &decode::MipsIInstr, sext: bool) {
         // FIXME: Support t_reg = 0
         assert_ne!(instr.t_reg, 0);
@@ -32,27 +58,46 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let bool_type = self.ctx.bool_type();
-        let i8_type = self.ctx.i8_type();
-        let i32_type = self.ctx.i32_type();
-
         let addr = self.decode_vaddr(instr);
 
-        let size_v = i32_type.const_int(size as u64, false);
+        let pc_reg = self.gep_pc(&format!("{}_{}", instr.opcode, self.count_uniq));
+        let pc_val = self
+            .builder
+            .build_load(
+                pc_reg,
+                &format!("{}_{}_pc_val", instr.opcode, self.count_uniq),
+            )
+            .into_int_value();
 
         // Finish emitting first, so that successive loads don't overwrite each other's save data
         let count = self.count_uniq;
         self.instr_finished_emitting();
 
-        let _read_success = self.mem_read(
-            addr.into(),
-            size_v.into(),
-            i8_type.const_int(instr.t_reg as u64, false).into(),
-            bool_type.const_int(sext as u64, false).into(),
+        self.raise_address_error_if_misaligned(
+            addr,
+            size,
+            false,
+            &format!("{}", instr.opcode),
+            &pc_reg,
+            &pc_val,
+            count,
+        );
+
+        let read_success = self.mem_read(
+            addr,
+            size,
+            instr.t_reg,
+            sext,
             &format!("{}_{}_read", instr.opcode, count),
         );
 
-        // FIXME: Check result of mem read
+        self.raise_bus_error_if_failed(
+            read_success,
+            &format!("{}", instr.opcode),
+            &pc_reg,
+            &pc_val,
+            count,
+        );
 
         // If the block is finished, then the load will complete at the beginning of the next block
         if self.finalized {
@@ -84,25 +129,49 @@ impl<'ctx> TranslationBlock<'ctx> {
     }
 
     fn emit_store_sized(&mut self, size: u32, instr: &decode::MipsIInstr) {
-        let i32_type = self.ctx.i32_type();
-
         let addr = self.decode_vaddr(instr);
         let t_val = self.get_gpr_value(
             instr.t_reg,
             &format!("{}_{}", instr.opcode, self.count_uniq),
         );
 
-        let size = i32_type.const_int(size as u64, false);
-        let _mem_res = self.mem_write(
-            addr.into(),
-            size.into(),
-            t_val.into(),
-            &format!("sb_{}_write", self.count_uniq),
+        let pc_reg = self.gep_pc(&format!("{}_{}", instr.opcode, self.count_uniq));
+        let pc_val = self
+            .builder
+            .build_load(
+                pc_reg,
+                &format!("{}_{}_pc_val", instr.opcode, self.count_uniq),
+            )
+            .into_int_value();
+
+        // Finish emitting first, so that an exception in a delay slot is attributed to the branch
+        let count = self.count_uniq;
+        self.instr_finished_emitting();
+
+        self.raise_address_error_if_misaligned(
+            addr,
+            size,
+            true,
+            &format!("{}", instr.opcode),
+            &pc_reg,
+            &pc_val,
+            count,
         );
 
-        // FIXME: Check result of mem write
+        let write_success = self.mem_write(
+            addr,
+            size,
+            t_val,
+            &format!("{}_{}_write", instr.opcode, count),
+        );
 
-        self.instr_finished_emitting();
+        self.raise_bus_error_if_failed(
+            write_success,
+            &format!("{}", instr.opcode),
+            &pc_reg,
+            &pc_val,
+            count,
+        );
     }
 
     pub(super) fn emit_sb(&mut self, instr: &decode::MipsIInstr) {
@@ -127,8 +196,6 @@ impl<'ctx> TranslationBlock<'ctx> {
         }
 
         let i32_type = self.ctx.i32_type();
-        let i8_type = self.ctx.i8_type();
-        let bool_type = self.ctx.bool_type();
 
         let addr = self.decode_vaddr(instr);
         let delay_val_ptr =
@@ -137,25 +204,19 @@ impl<'ctx> TranslationBlock<'ctx> {
         // Test if the previous instruction was a load to the same register
         // If so, we can reuse that load's delay value without waiting
         // If not, then we have to grab the source register value directly
-        let source_val = self
-            .delay_slot_load_register
-            .filter(|reg| *reg == instr.t_reg)
-            .map_or_else(
-                || {
-                    self.get_gpr_value(
-                        instr.t_reg,
-                        &format!("{}_{}_gpr_t", instr.opcode, self.count_uniq),
-                    )
-                },
-                |_| {
-                    self.builder
-                        .build_load(
-                            delay_val_ptr,
-                            &format!("{}_{}_delay_read", instr.opcode, self.count_uniq),
-                        )
-                        .into_int_value()
-                },
-            );
+        let source_val = if self.delay_slot_load_register == Some(instr.t_reg) {
+            self.builder
+                .build_load(
+                    delay_val_ptr,
+                    &format!("{}_{}_delay_read", instr.opcode, self.count_uniq),
+                )
+                .into_int_value()
+        } else {
+            self.get_gpr_value(
+                instr.t_reg,
+                &format!("{}_{}_gpr_t", instr.opcode, self.count_uniq),
+            )
+        };
 
         let addr_aligned = self.builder.build_and(
             addr,
@@ -176,17 +237,30 @@ impl<'ctx> TranslationBlock<'ctx> {
             self.delay_slot_hazard = None;
         }
 
+        let pc_reg = self.gep_pc(&format!("{}_{}", instr.opcode, count));
+        let pc_val = self
+            .builder
+            .build_load(pc_reg, &format!("{}_{}_pc_val", instr.opcode, count))
+            .into_int_value();
+
         self.instr_finished_emitting();
 
-        // FIXME: Check memory access success
-        let _read_success = self.mem_read(
-            addr_aligned.into(),
-            i32_type.const_int(32, false).into(),
-            i8_type.const_int(instr.t_reg as u64, false).into(),
-            bool_type.const_zero().into(),
+        let read_success = self.mem_read(
+            addr_aligned,
+            32,
+            instr.t_reg,
+            false,
             &format!("{}_{}_read", instr.opcode, count),
         );
 
+        self.raise_bus_error_if_failed(
+            read_success,
+            &format!("{}", instr.opcode),
+            &pc_reg,
+            &pc_val,
+            count,
+        );
+
         let mem_read_val = self.builder.build_load(
             delay_val_ptr,
             &format!("{}_{}_mem_read", instr.opcode, count),
@@ -278,8 +352,6 @@ impl<'ctx> TranslationBlock<'ctx> {
         }
 
         let i32_type = self.ctx.i32_type();
-        let i8_type = self.ctx.i8_type();
-        let bool_type = self.ctx.bool_type();
 
         let source_val = self.get_gpr_value(
             instr.t_reg,
@@ -293,21 +365,37 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("{}_{}_align", instr.opcode, self.count_uniq),
         );
 
+        let pc_reg = self.gep_pc(&format!("{}_{}", instr.opcode, self.count_uniq));
+        let pc_val = self
+            .builder
+            .build_load(
+                pc_reg,
+                &format!("{}_{}_pc_val", instr.opcode, self.count_uniq),
+            )
+            .into_int_value();
+
         // Clear out any delays that may be pending, we will be overwriting the load delay value
         // register
         let count = self.count_uniq;
         self.instr_finished_emitting();
 
-        // FIXME: Check memory access success
         // Read into zero register to discard write
-        let _read_success = self.mem_read(
-            addr_aligned.into(),
-            i32_type.const_int(32, false).into(),
-            i8_type.const_zero().into(),
-            bool_type.const_zero().into(),
+        let read_success = self.mem_read(
+            addr_aligned,
+            32,
+            0,
+            false,
             &format!("{}_{}_read", instr.opcode, count),
         );
 
+        self.raise_bus_error_if_failed(
+            read_success,
+            &format!("{}", instr.opcode),
+            &pc_reg,
+            &pc_val,
+            count,
+        );
+
         let delay_val_ptr =
             self.gep_load_delay_value(&format!("{}_{}_mem_read_ptr", instr.opcode, count));
         let mem_read_val = self.builder.build_load(
@@ -332,55 +420,63 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("{}_{}_mem_read_shift_bytes", instr.opcode, count),
         );
 
-        let mem_shift: inkwell::values::IntValue;
-        let curr_val_mask: inkwell::values::IntValue;
+        // The source register is shifted into place over the bytes of the aligned word in memory
+        let source_shift: inkwell::values::IntValue;
+        let mem_val_mask: inkwell::values::IntValue;
         if left {
-            mem_shift = self.builder.build_right_shift(
-                mem_read_val.into_int_value(),
+            source_shift = self.builder.build_right_shift(
+                source_val,
                 inv_alignment,
                 false,
-                &format!("{}_{}_mem_shift", instr.opcode, count),
+                &format!("{}_{}_source_shift", instr.opcode, count),
             );
 
-            curr_val_mask = self.builder.build_left_shift(
+            mem_val_mask = self.builder.build_left_shift(
                 i32_type.const_int(0xffff_ff00, false),
                 alignment,
-                &format!("{}_{}_curr_mask", instr.opcode, count),
+                &format!("{}_{}_mem_mask", instr.opcode, count),
             );
         } else {
-            mem_shift = self.builder.build_left_shift(
-                mem_read_val.into_int_value(),
+            source_shift = self.builder.build_left_shift(
+                source_val,
                 alignment,
-                &format!("{}_{}_mem_shift", instr.opcode, count),
+                &format!("{}_{}_source_shift", instr.opcode, count),
             );
 
-            curr_val_mask = self.builder.build_right_shift(
+            mem_val_mask = self.builder.build_right_shift(
                 i32_type.const_int(0x00ff_ffff, false),
                 inv_alignment,
                 false,
-                &format!("{}_{}_curr_mask", instr.opcode, count),
+                &format!("{}_{}_mem_mask", instr.opcode, count),
             );
         }
 
-        let curr_val = self.builder.build_and(
-            source_val,
-            curr_val_mask,
-            &format!("{}_{}_source_masked", instr.opcode, count),
+        let mem_val = self.builder.build_and(
+            mem_read_val.into_int_value(),
+            mem_val_mask,
+            &format!("{}_{}_mem_masked", instr.opcode, count),
         );
 
         let new_val = self.builder.build_or(
-            curr_val,
-            mem_shift,
+            mem_val,
+            source_shift,
             &format!("{}_{}_final_value", instr.opcode, count),
         );
 
-        // FIXME: Check write success
-        let _mem_write_success = self.mem_write(
-            addr_aligned.into(),
-            i32_type.const_int(32, false).into(),
-            new_val.into(),
+        let write_success = self.mem_write(
+            addr_aligned,
+            32,
+            new_val,
             &format!("{}_{}_mem_write", instr.opcode, count),
         );
+
+        self.raise_bus_error_if_failed(
+            write_success,
+            &format!("{}", instr.opcode),
+            &pc_reg,
+            &pc_val,
+            count,
+        );
     }
 
     pub(super) fn emit_swl(&mut self, instr: &decode::MipsIInstr) {
@@ -671,7 +767,137 @@ mod test {
         th.execute(&mut state).unwrap();
 
         println!("{:08x?}", state);
-        assert_eq!(state.gpr[1], 0xdead_0000);
-        assert_eq!(state.gpr[2], 0x0000_beef);
+        assert_eq!(state.gpr[1], 0x0000_dead);
+        assert_eq!(state.gpr[2], 0xbeef_0000);
+    }
+
+    #[test]
+    fn jit_test_lh_misaligned() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 0x1401, 0);
+        th.push_instr("lh", 0, 1, 2, 0, 0);
+        th.push_instr("addiu", 0, 0, 3, 10, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x4 << 2
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::BadVaddr as usize],
+            0x1401
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1004
+        );
+    }
+
+    #[test]
+    fn jit_test_sw_misaligned_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 0x1402, 0);
+        th.push_instr("jr", 31, 31, 31, 0, 0);
+        th.push_instr("sw", 0, 1, 1, 0, 0);
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            (1 << 31) | (0x5 << 2)
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::BadVaddr as usize],
+            0x1402
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1004
+        );
+    }
+
+    #[test]
+    fn jit_test_lw_unmapped() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.load32(1, 0x0800_0000);
+        th.push_instr("lw", 0, 1, 2, 0, 0);
+        th.push_instr("addiu", 0, 0, 3, 10, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x7 << 2
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1008
+        );
+    }
+
+    #[test]
+    fn jit_test_sw_invalidates_covering_block() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        // The first store is to the same page as the block but past its end, and the second is to
+        // the block itself
+        th.push_instr("addiu", 0, 0, 1, 0x1080, 0);
+        th.push_instr("sw", 0, 1, 0, 0, 0);
+        th.push_instr("addiu", 0, 0, 2, 0x1000, 0);
+        th.push_instr("sw", 0, 2, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut backend = crate::cpu::jit::Jit::new(&ctx);
+                let stats = crate::cpu::backend::run_until_halted(&mut backend, bus, state)?;
+
+                assert_eq!(stats.translation.unwrap().invalidations, 1);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn jit_test_sw_cache_isolated() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        let sr = crate::cpu::cop0::Register::Sr as u8;
+
+        th.load32(1, 1 << 16);
+        th.push_instr("addiu", 0, 0, 2, 0x1400, 0);
+        th.push_instr("addiu", 0, 0, 3, 42, 0);
+        th.push_instr("mtc0", sr, 0, 1, 0, 0);
+        th.push_instr("sw", 0, 2, 3, 0, 0);
+        th.push_instr("mtc0", sr, 0, 0, 0, 0);
+        th.push_instr("lw", 0, 2, 4, 0, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        // The store is left to the helper, so goes to the cache rather than memory, even though
+        // it is to RAM that loads and stores otherwise access directly
+        assert_eq!(state.gpr[3], 0);
+        assert!(state.take_icache_flush());
     }
 }
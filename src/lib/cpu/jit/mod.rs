@@ -1,5 +1,7 @@
+use super::backend::{spins_at, CpuBackend, StepResult, TranslationStats};
 use super::{decode, opcode, CpuState};
 use crate::cpu::bus::{BusDevice, SizedReadResult};
+use crate::cpu::llvm::JitConfig;
 use inkwell::values::AnyValue;
 use std::rc::Rc;
 
@@ -7,12 +9,18 @@ use std::rc::Rc;
 pub use super::test::harness;
 
 mod branch;
+mod cache;
+mod chain;
 mod cop;
+mod engine;
+mod fastmem;
 mod immed;
 mod jump;
 mod mem;
 mod mult;
+mod regcache;
 mod rtype;
+mod trace;
 
 type BusType = crate::cpu::bus_vec::VecBus;
 type TbDynFunc<'ctx> =
@@ -21,16 +29,25 @@ type TbDynFunc<'ctx> =
 struct DelaySlotArg<'ctx> {
     count: u64,
     immed: u16,
+    // Address a jump leads to, where it is known at translation time
+    target: Option<u32>,
     value: inkwell::values::BasicValueEnum<'ctx>,
 }
 
 pub struct TranslationBlock<'ctx> {
     id: u64,
+    // Address of the first instruction
+    pc: u32,
     count_uniq: u64,
+    // Cycles taken by the instructions translated so far, including fetch wait states, which the
+    // block adds to the state as it exits. Stalls on HI/LO are only known at run time, so are
+    // added to the state by the block as it executes.
+    cycles: u64,
     finalized: bool,
 
     ctx: &'ctx inkwell::context::Context,
     module: inkwell::module::Module<'ctx>,
+    // Engine the block is compiled with, shared with other blocks and kept alive by each of them
     ee: inkwell::execution_engine::ExecutionEngine<'ctx>,
     builder: inkwell::builder::Builder<'ctx>,
 
@@ -43,84 +60,105 @@ pub struct TranslationBlock<'ctx> {
     delay_slot_hazard: Option<fn(&mut TranslationBlock)>,
     delay_slot_arg: Option<DelaySlotArg<'ctx>>,
     delay_slot_load_register: Option<u8>,
+    // Set while emitting the instruction in the delay slot of a jump or branch
+    in_delay_slot: bool,
+    // Set by a jump or branch that the block carries on through, for once its delay slot is emitted
+    follow: Option<trace::Follow<'ctx>>,
+
+    // Offset from `pc` of each instruction translated, by position in the block. Once the block has
+    // followed a jump, this is no longer the position times 4.
+    offsets: Vec<u32>,
+    // Guest code the block was translated from, as start and end addresses, with a range for each
+    // stretch of straight-line code
+    ranges: Vec<(u32, u32)>,
+    // Execution counts of blocks by start address, while translating a trace, which decide the way
+    // its branches are followed
+    profile: Option<std::collections::HashMap<u32, u64>>,
+    // Set for a trace, which isn't translated again however hot it gets
+    trace: bool,
+    // Times the block has been entered, which the block counts itself
+    exec_count: *const u64,
+
+    // Guest registers held in values rather than in the state
+    regs: regcache::RegCache<'ctx>,
+    // Memory the bus backs with host memory, which loads and stores access directly
+    host_regions: Vec<crate::cpu::bus::HostRegion>,
+    // Bitmap of the pages holding translated code, which stores to must go through the helper
+    code_pages: *const u64,
 
     tb_func: Option<inkwell::execution_engine::JitFunction<'ctx, TbDynFunc<'ctx>>>,
+    // Address of the compiled function, for other blocks to chain into
+    func_addr: usize,
+
+    // Addresses the block can statically leave to, and the function each is linked to, if any
+    successors: Vec<u32>,
+    links: Box<[std::cell::Cell<usize>; chain::LINK_SLOTS]>,
+    // Set for a block that does nothing but branch back to itself
+    spins: bool,
 }
 
 pub(crate) struct TbManager<'ctx> {
+    config: JitConfig,
     trie: super::trie::Trie<TranslationBlock<'ctx>>,
+    stats: TranslationStats,
+    // Created along with the first block, as it needs the context
+    runtime: Option<engine::Runtime<'ctx>>,
+
+    // Pages holding translated code, which stores must invalidate blocks on
+    code_map: super::code_map::CodeMap,
+    // Where blocks are kept between runs, if anywhere
+    disk_cache: Option<cache::DiskCache>,
+
+    // Links into each block, as the source block and the successor linked, so they can be undone
+    // when the block is invalidated
+    incoming: std::collections::HashMap<u32, Vec<(u32, usize)>>,
+    // Block that last returned to the dispatcher through an exit that isn't linked yet
+    pending_link: Option<u32>,
+    // Invalidated blocks, which may still be executing until control returns to the dispatcher
+    retired: Vec<Rc<TranslationBlock<'ctx>>>,
+    // Times each block has been entered, by start address. These outlive the blocks counted, so
+    // that a retired block still executing has somewhere to count.
+    exec_counts: std::collections::HashMap<u32, Box<std::cell::Cell<u64>>>,
 }
 
 fn new_tb<'ctx>(
-    id: u64,
     ctx: &'ctx inkwell::context::Context,
+    runtime: &mut engine::Runtime<'ctx>,
 ) -> Result<TranslationBlock<'ctx>, String> {
+    let (id, ee) = runtime.allocate()?;
     let module = ctx.create_module(&format!("tb_mod_{}", id));
-    let ee = module
-        .create_jit_execution_engine(inkwell::OptimizationLevel::Less)
-        .map_err(|e| e.to_string())?;
-    let builder = ctx.create_builder();
-
-    let i32_type = ctx.i32_type();
-    let i8_type = ctx.i8_type();
-    let mips_state_type = ctx.opaque_struct_type("mips_state");
-    mips_state_type.set_body(&[i32_type.into(); 52], false);
-
-    let bus_type = ctx
-        .opaque_struct_type("mips_bus")
-        .ptr_type(inkwell::AddressSpace::Generic);
-    let tb_mgr_type = ctx
-        .opaque_struct_type("tb_manager")
-        .ptr_type(inkwell::AddressSpace::Generic);
-    let bool_type = ctx.bool_type();
-
-    let state_type = module
-        .get_struct_type("mips_state")
-        .unwrap()
-        .ptr_type(inkwell::AddressSpace::Generic);
-
-    let read_fn = module.add_function(
-        "tb_mem_read",
-        bool_type.fn_type(
-            &[
-                bus_type.into(),
-                tb_mgr_type.into(),
-                state_type.ptr_type(inkwell::AddressSpace::Generic).into(),
-                i32_type.into(),
-                i32_type.into(),
-                i8_type.into(),
-                bool_type.into(),
-            ],
-            false,
-        ),
-        None,
-    );
-    let write_fn = module.add_function(
-        "tb_mem_write",
-        bool_type.fn_type(
-            &[
-                bus_type.into(),
-                tb_mgr_type.into(),
-                i32_type.into(),
-                i32_type.into(),
-                i32_type.into(),
-            ],
-            false,
-        ),
-        None,
-    );
-
-    ee.add_global_mapping(&read_fn, tb_mem_read as usize);
-    ee.add_global_mapping(&write_fn, tb_mem_write as usize);
+    runtime.declare_helpers(&module);
 
     let void_type = ctx.void_type();
     let fn_type = void_type.fn_type(
-        &[state_type.into(), bus_type.into(), tb_mgr_type.into()],
+        &[
+            runtime.state_type.into(),
+            runtime.bus_type.into(),
+            runtime.mgr_type.into(),
+        ],
         false,
     );
     let func_name = format!("tb_func_{}", id);
     let func = module.add_function(&func_name, fn_type, None);
 
+    let block = ctx.append_basic_block(func, &func_name);
+    let tb = tb_with_module(ctx, id, ee, module, func)?;
+    tb.builder.position_at_end(block);
+
+    Ok(tb)
+}
+
+// Wraps the module holding a block's function, whether it is about to be translated into or was
+// loaded ready translated
+fn tb_with_module<'ctx>(
+    ctx: &'ctx inkwell::context::Context,
+    id: u64,
+    ee: inkwell::execution_engine::ExecutionEngine<'ctx>,
+    module: inkwell::module::Module<'ctx>,
+    func: inkwell::values::FunctionValue<'ctx>,
+) -> Result<TranslationBlock<'ctx>, String> {
+    let builder = ctx.create_builder();
+
     let state_arg = func
         .get_nth_param(0)
         .ok_or("No state arg")?
@@ -134,12 +172,11 @@ fn new_tb<'ctx>(
         .ok_or("No manager arg")?
         .into_pointer_value();
 
-    let block = ctx.append_basic_block(func, &func_name);
-    builder.position_at_end(block);
-
     Ok(TranslationBlock {
         id,
+        pc: 0,
         count_uniq: 0,
+        cycles: 0,
         finalized: false,
         ctx,
         module,
@@ -153,14 +190,41 @@ fn new_tb<'ctx>(
         delay_slot_hazard: None,
         delay_slot_arg: None,
         delay_slot_load_register: None,
+        in_delay_slot: false,
+        follow: None,
+        offsets: Vec::new(),
+        ranges: Vec::new(),
+        profile: None,
+        trace: false,
+        exec_count: std::ptr::null(),
+        regs: regcache::RegCache::default(),
+        host_regions: Vec::new(),
+        code_pages: std::ptr::null(),
         tb_func: None,
+        func_addr: 0,
+        successors: Vec::new(),
+        links: Box::default(),
+        spins: false,
     })
 }
 
 impl<'ctx> TbManager<'ctx> {
     pub fn new() -> Self {
+        Self::with_config(JitConfig::default())
+    }
+
+    pub fn with_config(config: JitConfig) -> Self {
         Self {
+            disk_cache: config.cache_dir.clone().map(cache::DiskCache::new),
+            config,
             trie: super::trie::Trie::default(),
+            stats: TranslationStats::default(),
+            runtime: None,
+            code_map: super::code_map::CodeMap::default(),
+            incoming: std::collections::HashMap::new(),
+            pending_link: None,
+            retired: Vec::new(),
+            exec_counts: std::collections::HashMap::new(),
         }
     }
 
@@ -170,20 +234,104 @@ impl<'ctx> TbManager<'ctx> {
         addr: u32,
         bus: &mut impl BusDevice,
     ) -> Result<Rc<TranslationBlock<'ctx>>, String> {
+        let mut profile = None;
         if let Some(tb) = self.trie.lookup(addr) {
-            return Ok(tb.clone());
+            if tb.trace || self.exec_count(addr) < trace::TRACE_THRESHOLD {
+                return Ok(tb.clone());
+            }
+
+            // Hot enough to be worth translating again as a trace, which takes the block's place
+            profile = Some(self.profile());
+            self.code_map.remove(addr);
+            self.retire(addr);
         }
 
-        let mut tb = new_tb(addr as u64, ctx)?;
-        tb.translate(bus, addr)?;
+        let start = std::time::Instant::now();
+        let runtime = self
+            .runtime
+            .get_or_insert_with(|| engine::Runtime::new(ctx, self.config.clone()));
+        // Traces depend on how the code has run as well as on the code, so aren't kept on disk
+        let key = match (&self.disk_cache, &profile) {
+            (Some(cache), None) => Some(cache.key(&runtime.config, bus, addr)),
+            _ => None,
+        };
+        let cached = match (&self.disk_cache, key) {
+            (Some(cache), Some(key)) => cache.load(key, ctx, runtime, bus, addr),
+            _ => None,
+        };
+
+        let mut tb = match cached {
+            Some(tb) => {
+                self.stats.cache_hits += 1;
+                tb
+            }
+            None => {
+                let mut tb = new_tb(ctx, runtime)?;
+                match profile {
+                    Some(profile) => tb.translate_trace(bus, addr, profile)?,
+                    None => tb.translate(bus, addr)?,
+                }
+                runtime.config.run_passes(&tb.module, tb.func);
+
+                if let (Some(cache), Some(key)) = (&self.disk_cache, key) {
+                    // The cache only saves time, so failing to write to it isn't an error
+                    let _ = cache.store(key, &tb, bus);
+                }
+                tb
+            }
+        };
+
+        tb.code_pages = self.code_map.bitmap_ptr();
+        tb.spins = spins_at(bus, addr);
+        tb.exec_count = self.exec_count_ptr(addr);
         tb.finalize();
+        self.stats.blocks_compiled += 1;
+        if tb.trace {
+            self.stats.traces_formed += 1;
+        }
+        self.stats.compile_time += start.elapsed();
+
         let tb_rc = Rc::new(tb);
         self.trie.insert(addr, &tb_rc)?;
-        return Ok(tb_rc);
+        self.code_map.insert(addr, &tb_rc.ranges);
+        Ok(tb_rc)
     }
 
+    // Discards the blocks that cover the word at `addr`, after a store to it
     fn invalidate(&mut self, addr: u32) {
-        self.trie.invalidate(addr);
+        for start in self.code_map.take_overlapping(addr) {
+            if self.retire(start) {
+                self.stats.invalidations += 1;
+            }
+        }
+    }
+
+    // Takes the block at `start` out of use, returning whether there was one
+    fn retire(&mut self, start: u32) -> bool {
+        let tb = match self.trie.remove(start) {
+            Some(tb) => tb,
+            None => return false,
+        };
+        self.unlink_into(start);
+
+        // The block may be the one storing to itself, so it is kept alive until the dispatcher has
+        // control again. It mustn't chain into anything stale meanwhile.
+        tb.unlink_all();
+        self.retired.push(tb);
+        true
+    }
+
+    // Discards every translated block. Must only be called from the dispatcher.
+    fn flush(&mut self) {
+        self.trie = super::trie::Trie::default();
+        self.code_map.clear();
+        self.incoming.clear();
+        self.pending_link = None;
+        // Nothing compiled so far is needed, so the engine's memory can go once it is unused
+        if let Some(runtime) = self.runtime.as_mut() {
+            runtime.retire_engine();
+        }
+        self.stats.invalidations += 1;
     }
 }
 
@@ -205,45 +353,66 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.count_uniq += 1;
     }
 
-    fn gep_gp_register(&self, reg: u8, name: &str) -> inkwell::values::PointerValue<'ctx> {
-        assert!(reg < 32);
-        assert!(reg > 0);
+    fn gep_pc(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
         self.builder
-            .build_struct_gep(self.state_arg, (reg as u32) - 1, name)
+            .build_struct_gep(self.state_arg, 33, &format!("{}_pc", prefix))
             .unwrap()
     }
 
-    fn gep_hi(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
+    // Sets the PC in the state to the instruction `offset` bytes from the start of the block, in
+    // whichever segment the block was entered through
+    fn emit_store_pc(&self, offset: u32, prefix: &str) {
+        let i32_type = self.ctx.i32_type();
+
+        let pc_ptr = self.gep_pc(prefix);
+        let pc_val = self
+            .builder
+            .build_load(pc_ptr, &format!("{}_pc_val", prefix))
+            .into_int_value();
+        let next_pc = self.builder.build_int_add(
+            pc_val,
+            i32_type.const_int(offset as u64, false),
+            &format!("{}_next_pc", prefix),
+        );
+        self.builder.build_store(pc_ptr, next_pc);
+    }
+
+    fn gep_load_delay_register(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
         self.builder
-            .build_struct_gep(self.state_arg, 31, &format!("{}_hi", prefix))
+            .build_struct_gep(self.state_arg, 34, &format!("{}_delay_reg", prefix))
             .unwrap()
     }
 
-    fn gep_lo(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
+    fn gep_load_delay_value(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
         self.builder
-            .build_struct_gep(self.state_arg, 32, &format!("{}_lo", prefix))
+            .build_struct_gep(self.state_arg, 35, &format!("{}_delay_value", prefix))
             .unwrap()
     }
 
-    fn gep_pc(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
+    fn gep_cycles(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
         self.builder
-            .build_struct_gep(self.state_arg, 33, &format!("{}_pc", prefix))
+            .build_struct_gep(self.state_arg, 52, &format!("{}_cycles", prefix))
             .unwrap()
     }
 
-    fn gep_load_delay_register(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
+    fn gep_chain_limit(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
         self.builder
-            .build_struct_gep(self.state_arg, 34, &format!("{}_delay_reg", prefix))
+            .build_struct_gep(self.state_arg, 53, &format!("{}_chain_limit", prefix))
             .unwrap()
     }
 
-    fn gep_load_delay_value(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
+    fn gep_chain_icount(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
         self.builder
-            .build_struct_gep(self.state_arg, 35, &format!("{}_delay_value", prefix))
+            .build_struct_gep(self.state_arg, 54, &format!("{}_chain_icount", prefix))
             .unwrap()
     }
 
+    // Applies whichever load is pending, for the start of a block, where the register is only known
+    // at run time
     fn apply_load_delay_if_present(&mut self) {
+        // The register is written through the state, so nothing cached can be relied on after
+        self.flush_regs();
+
         let i32_type = self.ctx.i32_type();
         let i64_type = self.ctx.i64_type();
 
@@ -321,65 +490,6 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.builder.build_store(register, i32_type.const_zero());
     }
 
-    fn mem_read(
-        &self,
-        addr: inkwell::values::BasicMetadataValueEnum<'ctx>,
-        size: inkwell::values::BasicMetadataValueEnum<'ctx>,
-        reg: inkwell::values::BasicMetadataValueEnum<'ctx>,
-        sign_extend: inkwell::values::BasicMetadataValueEnum<'ctx>,
-        name: &str,
-    ) -> inkwell::values::BasicValueEnum<'ctx> {
-        let read_fn = self.module.get_function("tb_mem_read").unwrap();
-        self.builder
-            .build_call(
-                read_fn,
-                &[
-                    self.bus_arg.into(),
-                    self.mgr_arg.into(),
-                    self.state_arg.into(),
-                    addr,
-                    size,
-                    reg,
-                    sign_extend,
-                ],
-                name,
-            )
-            .try_as_basic_value()
-            .left()
-            .unwrap()
-    }
-
-    fn mem_write(
-        &self,
-        addr: inkwell::values::BasicMetadataValueEnum<'ctx>,
-        size: inkwell::values::BasicMetadataValueEnum<'ctx>,
-        value: inkwell::values::BasicMetadataValueEnum<'ctx>,
-        name: &str,
-    ) -> inkwell::values::BasicValueEnum<'ctx> {
-        let write_fn = self.module.get_function("tb_mem_write").unwrap();
-        self.builder
-            .build_call(
-                write_fn,
-                &[self.bus_arg.into(), self.mgr_arg.into(), addr, size, value],
-                name,
-            )
-            .try_as_basic_value()
-            .left()
-            .unwrap()
-    }
-
-    fn get_gpr_value(&self, reg: u8, prefix: &str) -> inkwell::values::IntValue<'ctx> {
-        let i32_type = self.ctx.i32_type();
-        if reg == 0 {
-            i32_type.const_zero().into()
-        } else {
-            let ptr = self.gep_gp_register(reg, &format!("{}_src_ptr", prefix));
-            self.builder
-                .build_load(ptr, &format!("{}_src_reg", prefix))
-                .into_int_value()
-        }
-    }
-
     fn emit_r_instr(&mut self, instr: &decode::MipsRInstr) {
         match instr.function {
             opcode::MipsFunction::Sll => self.emit_sll(instr),
@@ -417,7 +527,11 @@ impl<'ctx> TranslationBlock<'ctx> {
         let special_op =
             num::FromPrimitive::from_u8(instr.t_reg).unwrap_or(opcode::MipsBranchSpecial::Invalid);
         match special_op {
-            _ => panic!("Not implemented: {}", special_op),
+            opcode::MipsBranchSpecial::Bltz => self.emit_bltz(instr, false),
+            opcode::MipsBranchSpecial::Bgez => self.emit_bgez(instr, false),
+            opcode::MipsBranchSpecial::Bltzal => self.emit_bltz(instr, true),
+            opcode::MipsBranchSpecial::Bgezal => self.emit_bgez(instr, true),
+            opcode::MipsBranchSpecial::Invalid => self.emit_reserved_instruction(),
         }
     }
 
@@ -432,7 +546,7 @@ impl<'ctx> TranslationBlock<'ctx> {
             opcode::MipsOpcode::Bne => self.emit_bne(instr),
             opcode::MipsOpcode::Blez => self.emit_blez(instr),
             opcode::MipsOpcode::Bgtz => self.emit_bgtz(instr),
-            opcode::MipsOpcode::AddI => self.emit_addiu(instr), // FIXME: Handle addi overflow
+            opcode::MipsOpcode::AddI => self.emit_addi(instr),
             opcode::MipsOpcode::AddIU => self.emit_addiu(instr),
             opcode::MipsOpcode::SltI => self.emit_slti(instr),
             opcode::MipsOpcode::SltIU => self.emit_sltiu(instr),
@@ -466,13 +580,35 @@ impl<'ctx> TranslationBlock<'ctx> {
         }
     }
 
-    pub fn translate(&mut self, bus: &mut dyn BusDevice, pc: u32) -> Result<u32, String> {
+    pub fn translate(&mut self, bus: &mut dyn BusDevice, pc: u32) -> Result<(), String> {
+        self.pc = pc;
+        self.host_regions = bus.host_regions();
+
+        self.emit_exec_count();
+
         // FIXME: Use separate branches for initial load delay application to improve performance
         self.apply_load_delay_if_present();
 
         let mut addr = pc;
         while !self.finalized {
-            let read_result = bus.read(addr, 32).map_err(|_| "Failed to read instr")?;
+            self.in_delay_slot = false;
+            self.offsets.push(addr.wrapping_sub(pc));
+
+            let read_result = match bus.read(addr, 32) {
+                Ok(r) => r,
+                Err(_) => {
+                    // The block may branch away before reaching the failed fetch, so the bus
+                    // error is only raised if the instruction is reached
+                    self.emit_instruction_bus_error(addr);
+                    continue;
+                }
+            };
+
+            match self.ranges.last_mut() {
+                Some((_, end)) if *end == addr => *end = addr.wrapping_add(4),
+                _ => self.ranges.push((addr, addr.wrapping_add(4))),
+            }
+
             if let SizedReadResult::Dword(instr_raw) = read_result {
                 let instr = super::decode::mips_decode(instr_raw);
 
@@ -481,33 +617,43 @@ impl<'ctx> TranslationBlock<'ctx> {
                     decode::MipsInstr::IType(i) => self.emit_i_instr(&i),
                     decode::MipsInstr::JType(j) => self.emit_j_instr(&j),
                     decode::MipsInstr::Cop(c) => self.emit_cop_operation(&c),
-                    _ => {
-                        // FIXME: Raise invalid instruction exception
-                        self.emit_r_instr(&decode::MipsRInstr {
-                            s_reg: 0,
-                            t_reg: 0,
-                            d_reg: 0,
-                            shamt: 0,
-                            function: opcode::MipsFunction::Sll,
-                        });
-                        eprintln!(
-                            "Ignorning unhandled instruction {:#08x}: {:#08x} {}",
-                            addr, instr_raw, instr
-                        );
-
-                        #[cfg(test)]
-                        panic!();
-                        //return Err(format!("Invalid instruction {:#08x}: {:#08x} {}", addr, instr_raw, instr));
-                    }
+                    decode::MipsInstr::CopCmd(c) => self.emit_cop_command(&c),
+                    decode::MipsInstr::CopMem(c) => self.emit_cop_mem(&c),
+                    decode::MipsInstr::Invalid => self.emit_reserved_instruction(),
                 }
 
+                // Counted after emitting, so that the instruction sees the cycles ahead of it
+                self.cycles += 1 + bus.wait_states(addr, 32) as u64;
                 addr += 4;
-                if ((addr >> 2) & 0x3f == 0) && !self.finalized {
-                    let i32_type = self.ctx.i32_type();
-                    let pc_val = i32_type.const_int(addr as u64, false);
-                    let pc_ptr = self.gep_pc("block_end");
-                    self.builder.build_store(pc_ptr, pc_val);
-                    self.builder.build_return(None);
+
+                // A jump or branch the block follows carries on at its target, unless its delay
+                // slot raised an exception
+                if let Some(follow) = self.follow.take() {
+                    if !self.finalized {
+                        if let Some((leave, offset)) = follow.side_exit {
+                            self.emit_side_exit(leave, offset);
+                        }
+
+                        let target = pc.wrapping_add(follow.offset);
+                        if bus.read(target, 32).is_ok() {
+                            addr = target;
+                        } else {
+                            // Code that can't be fetched is left to a block of its own, which
+                            // raises the bus error
+                            self.emit_store_pc(follow.offset, "follow_end");
+                            self.add_successor(target);
+                            self.finalized = true;
+                        }
+                    }
+                }
+
+                // Blocks are ended once long enough, though never in the middle of a delay slot
+                if self.count_uniq >= self.max_instrs()
+                    && !self.finalized
+                    && self.delay_slot_hazard.is_none()
+                {
+                    self.emit_store_pc(addr.wrapping_sub(pc), "block_end");
+                    self.add_successor(addr);
                     self.finalized = true;
                 }
             } else {
@@ -521,13 +667,20 @@ impl<'ctx> TranslationBlock<'ctx> {
         // Do not allow a delay action on the final instruction of the block, all must be
         // consumed
         assert!(self.delay_slot_hazard.is_none());
-        self.builder.build_return(None);
+        self.emit_block_exit();
 
-        Ok(addr)
+        Ok(())
     }
 
     pub fn finalize(&mut self) {
-        unsafe { self.tb_func = self.ee.get_function(&format!("tb_func_{}", self.id)).ok() }
+        self.map_host_globals();
+        if self.ee.add_module(&self.module).is_err() {
+            return;
+        }
+
+        let func_name = format!("tb_func_{}", self.id);
+        unsafe { self.tb_func = self.ee.get_function(&func_name).ok() }
+        self.func_addr = self.ee.get_function_address(&func_name).unwrap_or(0);
     }
 
     pub fn print(&self) {
@@ -569,6 +722,9 @@ pub(crate) unsafe extern "C" fn tb_mem_read(
 ) -> bool {
     match (*bus).read(addr, size) {
         Ok(v) => {
+            // This also charges the read that SWL and SWR are emulated with, which is close enough
+            (*state).add_cycles((*bus).wait_states(addr, size) as u64);
+
             (*state).load_delay_register_value = match v {
                 SizedReadResult::Byte(b) => {
                     if sign_extend {
@@ -592,8 +748,9 @@ pub(crate) unsafe extern "C" fn tb_mem_read(
             true
         }
         Err(e) => {
-            // FIXME: Return error for handling
-            panic!("tb_mem_read err: {:#08x?}", e);
+            // The TB raises the bus error exception when the read fails
+            (*state).bus_error(e, false);
+            false
         }
     }
 }
@@ -602,77 +759,134 @@ pub(crate) unsafe extern "C" fn tb_mem_read(
 pub(crate) unsafe extern "C" fn tb_mem_write(
     bus: *mut BusType,
     mgr: *mut TbManager,
+    state: *mut CpuState,
     addr: u32,
     size: u32,
     value: u32,
 ) -> bool {
+    if (*state).store_isolated(addr) {
+        return true;
+    }
+
     let wv = (*bus).write(addr, size, value);
     (*mgr).invalidate(addr);
-    if let Err(e) = wv {
-        // FIXME: Return error for handling
-        panic!("tb_mem_write err: {:#08x?}", e);
-    }
     match wv {
         Ok(_) => true,
-        Err(_) => false,
+        Err(e) => {
+            // The TB raises the bus error exception when the write fails
+            (*state).bus_error(e, false);
+            false
+        }
+    }
+}
+
+// Records the bus error for an instruction fetch that failed during translation
+#[no_mangle]
+pub(crate) unsafe extern "C" fn tb_fetch_error(bus: *mut BusType, state: *mut CpuState, addr: u32) {
+    if let Err(e) = (*bus).read(addr, 32) {
+        (*state).bus_error(e, true);
     }
 }
 
-pub fn execute(bus: &mut BusType, state: &mut CpuState) -> Result<(), String> {
-    let ctx = inkwell::context::Context::create();
-    let mut tb_mgr = TbManager::new();
-    let mut prev_pc = 0;
-    let mut icount_tot = 0;
-    let mut icount = 0;
-    let now = std::time::Instant::now();
-    let mut prev_elapsed: u128 = 0;
+// Starts a multiply or divide `offset` cycles into the block, with `rs` deciding how long a
+// multiply takes
+#[no_mangle]
+pub(crate) unsafe extern "C" fn tb_hilo_start(
+    state: *mut CpuState,
+    function: u8,
+    rs: u32,
+    offset: u32,
+) {
+    let function: opcode::MipsFunction = num::FromPrimitive::from_u8(function).unwrap();
+    let latency = crate::cpu::timing::hilo_latency(&function, rs);
+    (*state).start_hilo_op(latency, offset as u64);
+}
+
+// Stalls for a read of HI/LO `offset` cycles into the block
+#[no_mangle]
+pub(crate) unsafe extern "C" fn tb_hilo_wait(state: *mut CpuState, offset: u32) {
+    (*state).wait_for_hilo(offset as u64);
+}
+
+// Translates blocks of guest code to native code with LLVM.
+// The LLVM context must outlive the backend, so it is created by the caller.
+pub struct Jit<'ctx> {
+    ctx: &'ctx inkwell::context::Context,
+    tb_mgr: TbManager<'ctx>,
+    prev_pc: Option<u32>,
+    // Cycle count that chains of linked blocks stop at, while running to a budget
+    deadline: Option<u64>,
+}
+
+impl<'ctx> Jit<'ctx> {
+    pub fn new(ctx: &'ctx inkwell::context::Context) -> Self {
+        Self {
+            ctx,
+            tb_mgr: TbManager::new(),
+            prev_pc: None,
+            deadline: None,
+        }
+    }
 
-    let mut mips_avg: f64 = 0.0;
-    let mut mips_min: f64 = f64::MAX;
-    let mut mips_max: f64 = 0.0;
-    let mut mips_avg_count: u128 = 0;
+    pub fn with_config(ctx: &'ctx inkwell::context::Context, config: JitConfig) -> Self {
+        Self {
+            ctx,
+            tb_mgr: TbManager::with_config(config),
+            prev_pc: None,
+            deadline: None,
+        }
+    }
+}
 
-    let timing_scale = 1_000;
+impl<'ctx> CpuBackend for Jit<'ctx> {
+    fn step(&mut self, bus: &mut BusType, state: &mut CpuState) -> Result<StepResult, String> {
+        // Nothing is executing now, so blocks invalidated by the previous step can go
+        self.tb_mgr.retired.clear();
 
-    loop {
-        // FIXME: Raise alignment exception instead of throwing
-        assert!((state.pc & 0x3) == 0);
+        // The guest flushes the I-cache after changing code, which may not have been caught by
+        // the invalidation on stores
+        if state.take_icache_flush() {
+            self.tb_mgr.flush();
+        }
 
-        let tb = tb_mgr.get_tb(&ctx, state.pc, bus)?;
+        // Blocks never end ahead of a delay slot, so interrupts can be taken between any two
+        if state.raise_if_interrupt_pending() || state.raise_if_pc_misaligned() {
+            return Ok(StepResult::Executed(0));
+        }
 
-        if state.pc == prev_pc && tb.count_uniq == 2 {
-            break;
+        let tb = self.tb_mgr.get_tb(self.ctx, state.pc, bus)?;
+        if let Some(src) = self.tb_mgr.pending_link.take() {
+            self.tb_mgr.link(src, state.pc, &tb);
         }
-        prev_pc = state.pc;
-
-        tb.execute(state, bus, &mut tb_mgr)?;
-        icount += tb.count_uniq;
-
-        if icount > timing_scale {
-            let elapsed_micros_tot = now.elapsed().as_micros();
-            let elapsed_micros = elapsed_micros_tot - prev_elapsed;
-            prev_elapsed = elapsed_micros_tot;
-            let elapsed = (elapsed_micros as f64) / 1_000_000.0;
-            let mips = (icount as f64) / elapsed / 1_000_000.0;
-            mips_min = f64::min(mips_min, mips);
-            mips_max = f64::max(mips_max, mips);
-            mips_avg += mips;
-            mips_avg_count += 1;
-
-            icount_tot += icount;
-            icount = 0;
+
+        if self.prev_pc == Some(state.pc) && tb.spins {
+            return Ok(StepResult::Halted);
         }
+        self.prev_pc = Some(state.pc);
+
+        // Interrupts and device events are only seen here, so chains of linked blocks are kept
+        // short, and don't run past what the caller has budgeted for
+        let chain_limit = state.cycles + chain::CHAIN_CYCLES;
+        state.chain_limit = self.deadline.map_or(chain_limit, |d| chain_limit.min(d));
+        state.chain_icount = 0;
+
+        tb.execute(state, bus, &mut self.tb_mgr)?;
+        state.take_bus_error()?;
+
+        Ok(StepResult::Executed(state.chain_icount))
     }
 
-    let elapsed_micros = now.elapsed().as_micros();
-    let elapsed = (elapsed_micros as f64) / 1_000_000.0;
+    fn set_deadline(&mut self, deadline: Option<u64>) {
+        self.deadline = deadline;
+    }
 
-    println!("CpuState: {:x?}", state);
-    println!("elapsed time: {}", elapsed);
-    println!("icount: {}", icount_tot + icount);
-    println!("MIPS (average): {}", mips_avg / (mips_avg_count as f64));
-    println!("MIPS (min): {}", mips_min);
-    println!("MIPS (max): {}", mips_max);
+    fn translation_stats(&self) -> Option<TranslationStats> {
+        Some(self.tb_mgr.stats)
+    }
 
-    Ok(())
+    fn reset(&mut self, state: &mut CpuState) {
+        self.tb_mgr = TbManager::with_config(self.tb_mgr.config.clone());
+        self.prev_pc = None;
+        state.reset();
+    }
 }
@@ -1,37 +1,82 @@
 use super::decode;
 use super::TranslationBlock;
+use crate::cpu::opcode::MipsFunction;
 
 impl<'ctx> TranslationBlock<'ctx> {
+    // Starts the multiplier's timer, as of the current instruction
+    fn emit_hilo_start(
+        &mut self,
+        function: MipsFunction,
+        s_reg: inkwell::values::IntValue<'ctx>,
+        prefix: &str,
+    ) {
+        self.emit_writeback();
+        let i8_type = self.ctx.i8_type();
+        let i32_type = self.ctx.i32_type();
+        let hilo_start_fn = self.module.get_function("tb_hilo_start").unwrap();
+        self.builder.build_call(
+            hilo_start_fn,
+            &[
+                self.state_arg.into(),
+                i8_type.const_int(function as u64, false).into(),
+                s_reg.into(),
+                i32_type.const_int(self.cycles, false).into(),
+            ],
+            &format!("{}_{}_hilo_start", prefix, self.count_uniq),
+        );
+    }
+
+    // Stalls until the multiplier has finished, as of the current instruction
+    fn emit_hilo_wait(&mut self, prefix: &str) {
+        self.emit_writeback();
+        let i32_type = self.ctx.i32_type();
+        let hilo_wait_fn = self.module.get_function("tb_hilo_wait").unwrap();
+        self.builder.build_call(
+            hilo_wait_fn,
+            &[
+                self.state_arg.into(),
+                i32_type.const_int(self.cycles, false).into(),
+            ],
+            &format!("{}_{}_hilo_wait", prefix, self.count_uniq),
+        );
+    }
+
     pub(super) fn emit_mflo(&mut self, instr: &decode::MipsRInstr) {
-        if self.finalized || instr.d_reg == 0 {
+        if self.finalized {
             self.instr_finished_emitting();
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("mflo_{}_d_reg", self.count_uniq));
-        let lo_ptr = self.gep_lo(&format!("mflo_{}_lo_ptr", self.count_uniq));
-        let lo = self
-            .builder
-            .build_load(lo_ptr, &format!("mflo_{}_lo", self.count_uniq));
+        // Even a read into r0 waits for the multiplier
+        self.emit_hilo_wait("mflo");
+        if instr.d_reg == 0 {
+            self.instr_finished_emitting();
+            return;
+        }
+
+        let lo = self.get_lo(&format!("mflo_{}", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, lo);
+        self.set_gpr_value(instr.d_reg, lo);
     }
 
     pub(super) fn emit_mfhi(&mut self, instr: &decode::MipsRInstr) {
-        if self.finalized || instr.d_reg == 0 {
+        if self.finalized {
             self.instr_finished_emitting();
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("mfhi_{}_d_reg", self.count_uniq));
-        let hi_ptr = self.gep_hi(&format!("mfhi_{}_hi_ptr", self.count_uniq));
-        let hi = self
-            .builder
-            .build_load(hi_ptr, &format!("mfhi_{}_hi", self.count_uniq));
+        // Even a read into r0 waits for the multiplier
+        self.emit_hilo_wait("mfhi");
+        if instr.d_reg == 0 {
+            self.instr_finished_emitting();
+            return;
+        }
+
+        let hi = self.get_hi(&format!("mfhi_{}", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, hi);
+        self.set_gpr_value(instr.d_reg, hi);
     }
 
     pub(super) fn emit_mtlo(&mut self, instr: &decode::MipsRInstr) {
@@ -41,8 +86,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         }
 
         let lo_val = self.get_gpr_value(instr.s_reg, &format!("mtlo_s_val_{}", self.count_uniq));
-        let lo_ptr = self.gep_lo(&format!("mtlo_{}_lo_ptr", self.count_uniq));
-        self.builder.build_store(lo_ptr, lo_val);
+        self.set_lo(lo_val);
 
         self.instr_finished_emitting();
     }
@@ -54,8 +98,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         }
 
         let hi_val = self.get_gpr_value(instr.s_reg, &format!("mthi_s_val_{}", self.count_uniq));
-        let hi_ptr = self.gep_hi(&format!("mthi_{}_lo_ptr", self.count_uniq));
-        self.builder.build_store(hi_ptr, hi_val);
+        self.set_hi(hi_val);
 
         self.instr_finished_emitting();
     }
@@ -63,6 +106,7 @@ impl<'ctx> TranslationBlock<'ctx> {
     pub(super) fn emit_divu(&mut self, instr: &decode::MipsRInstr) {
         let s_reg = self.get_gpr_value(instr.s_reg, &format!("divu_{}_s", self.count_uniq));
         let t_reg = self.get_gpr_value(instr.t_reg, &format!("divu_{}_t", self.count_uniq));
+        self.emit_hilo_start(MipsFunction::DivU, s_reg, "divu");
 
         let i32_type = self.ctx.i32_type();
         let div_by_zero = self.builder.build_int_compare(
@@ -91,14 +135,13 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("divu_{}_quotient", self.count_uniq),
         );
 
-        let lo = self.gep_lo(&format!("divu_{}_lo", self.count_uniq));
         let lo_result = self.builder.build_select(
             div_by_zero,
             i32_type.const_all_ones(),
             div,
             &format!("divu_{}_lo_res", self.count_uniq),
         );
-        self.builder.build_store(lo, lo_result);
+        self.set_lo(lo_result.into_int_value());
 
         let modulo = self.builder.build_int_unsigned_rem(
             s_reg,
@@ -106,14 +149,13 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("divu_{}_mod", self.count_uniq),
         );
 
-        let hi = self.gep_hi(&format!("divu_{}_hi", self.count_uniq));
         let hi_result = self.builder.build_select(
             div_by_zero,
             s_reg,
             modulo,
             &format!("divu_{}_hi_res", self.count_uniq),
         );
-        self.builder.build_store(hi, hi_result);
+        self.set_hi(hi_result.into_int_value());
 
         self.instr_finished_emitting();
     }
@@ -121,6 +163,7 @@ impl<'ctx> TranslationBlock<'ctx> {
     pub(super) fn emit_div(&mut self, instr: &decode::MipsRInstr) {
         let s_reg = self.get_gpr_value(instr.s_reg, &format!("div_{}_s", self.count_uniq));
         let t_reg = self.get_gpr_value(instr.t_reg, &format!("div_{}_t", self.count_uniq));
+        self.emit_hilo_start(MipsFunction::Div, s_reg, "div");
 
         let i32_type = self.ctx.i32_type();
         let div_by_zero = self.builder.build_int_compare(
@@ -149,14 +192,13 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("div_{}_quotient", self.count_uniq),
         );
 
-        let lo = self.gep_lo(&format!("div_{}_lo", self.count_uniq));
         let lo_result = self.builder.build_select(
             div_by_zero,
             i32_type.const_all_ones(),
             div,
             &format!("div_{}_lo_res", self.count_uniq),
         );
-        self.builder.build_store(lo, lo_result);
+        self.set_lo(lo_result.into_int_value());
 
         let modulo = self.builder.build_int_signed_rem(
             s_reg,
@@ -164,14 +206,13 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("div_{}_mod", self.count_uniq),
         );
 
-        let hi = self.gep_hi(&format!("div_{}_hi", self.count_uniq));
         let hi_result = self.builder.build_select(
             div_by_zero,
             s_reg,
             modulo,
             &format!("div_{}_hi_res", self.count_uniq),
         );
-        self.builder.build_store(hi, hi_result);
+        self.set_hi(hi_result.into_int_value());
 
         self.instr_finished_emitting();
     }
@@ -179,6 +220,7 @@ impl<'ctx> TranslationBlock<'ctx> {
     pub(super) fn emit_mult(&mut self, instr: &decode::MipsRInstr) {
         let s_reg = self.get_gpr_value(instr.s_reg, &format!("mult_{}_s", self.count_uniq));
         let t_reg = self.get_gpr_value(instr.t_reg, &format!("mult_{}_t", self.count_uniq));
+        self.emit_hilo_start(MipsFunction::Mult, s_reg, "mult");
 
         let i64_type = self.ctx.i64_type();
         let s_ext = self.builder.build_int_s_extend(
@@ -203,21 +245,19 @@ impl<'ctx> TranslationBlock<'ctx> {
             false,
             &format!("mult_{}_hi", self.count_uniq),
         );
-        let hi_reg = self.gep_hi(&format!("mult_{}_hi_reg", self.count_uniq));
         let mult_hi_32 = self.builder.build_int_truncate(
             mult_hi,
             i32_type,
             &format!("mult_{}_hi_cast", self.count_uniq),
         );
-        self.builder.build_store(hi_reg, mult_hi_32);
+        self.set_hi(mult_hi_32);
 
         let mult_lo = self.builder.build_int_truncate(
             mult_v,
             i32_type,
             &format!("mult_{}_lo", self.count_uniq),
         );
-        let lo_reg = self.gep_lo(&format!("mult_{}_lo_reg", self.count_uniq));
-        self.builder.build_store(lo_reg, mult_lo);
+        self.set_lo(mult_lo);
 
         self.instr_finished_emitting();
     }
@@ -225,6 +265,7 @@ impl<'ctx> TranslationBlock<'ctx> {
     pub(super) fn emit_multu(&mut self, instr: &decode::MipsRInstr) {
         let s_reg = self.get_gpr_value(instr.s_reg, &format!("multu_{}_s", self.count_uniq));
         let t_reg = self.get_gpr_value(instr.t_reg, &format!("multu_{}_t", self.count_uniq));
+        self.emit_hilo_start(MipsFunction::MultU, s_reg, "multu");
 
         let i64_type = self.ctx.i64_type();
         let s_ext = self.builder.build_int_z_extend(
@@ -249,21 +290,19 @@ impl<'ctx> TranslationBlock<'ctx> {
             false,
             &format!("multu_{}_hi", self.count_uniq),
         );
-        let hi_reg = self.gep_hi(&format!("multu_{}_hi_reg", self.count_uniq));
         let mult_hi_32 = self.builder.build_int_truncate(
             mult_hi,
             i32_type,
             &format!("multu_{}_hi_cast", self.count_uniq),
         );
-        self.builder.build_store(hi_reg, mult_hi_32);
+        self.set_hi(mult_hi_32);
 
         let mult_lo = self.builder.build_int_truncate(
             mult_v,
             i32_type,
             &format!("multu_{}_lo", self.count_uniq),
         );
-        let lo_reg = self.gep_lo(&format!("multu_{}_lo_reg", self.count_uniq));
-        self.builder.build_store(lo_reg, mult_lo);
+        self.set_lo(mult_lo);
 
         self.instr_finished_emitting();
     }
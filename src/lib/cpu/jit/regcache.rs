@@ -0,0 +1,164 @@
+use super::TranslationBlock;
+use inkwell::values::IntValue;
+
+// Indices of HI and LO, which follow the GPRs in the state
+const HI: usize = 31;
+const LO: usize = 32;
+
+// Guest registers a block has loaded or written so far, so that each is only loaded from the state
+// once and only stored back when the block needs the state to be up to date. Indexed like the
+// state, by GPR number minus one, followed by HI and LO.
+//
+// The values must dominate every later use, so registers are only loaded on the block's main path.
+// Exception exits branch off it and never return to it.
+pub(super) struct RegCache<'ctx> {
+    values: [Option<IntValue<'ctx>>; 33],
+    // Written since last being stored back to the state
+    dirty: [bool; 33],
+}
+
+impl<'ctx> Default for RegCache<'ctx> {
+    fn default() -> Self {
+        Self {
+            values: [None; 33],
+            dirty: [false; 33],
+        }
+    }
+}
+
+impl<'ctx> TranslationBlock<'ctx> {
+    fn get_cached(&mut self, index: usize, name: &str) -> IntValue<'ctx> {
+        if let Some(val) = self.regs.values[index] {
+            return val;
+        }
+
+        let ptr = self
+            .builder
+            .build_struct_gep(self.state_arg, index as u32, &format!("{}_ptr", name))
+            .unwrap();
+        let val = self.builder.build_load(ptr, name).into_int_value();
+        self.regs.values[index] = Some(val);
+        val
+    }
+
+    fn set_cached(&mut self, index: usize, val: IntValue<'ctx>) {
+        self.regs.values[index] = Some(val);
+        self.regs.dirty[index] = true;
+    }
+
+    pub(super) fn get_gpr_value(&mut self, reg: u8, prefix: &str) -> IntValue<'ctx> {
+        if reg == 0 {
+            return self.ctx.i32_type().const_zero();
+        }
+
+        self.get_cached(reg as usize - 1, &format!("{}_src_reg", prefix))
+    }
+
+    // Writes a GPR, discarding writes to r0
+    pub(super) fn set_gpr_value(&mut self, reg: u8, val: IntValue<'ctx>) {
+        if reg != 0 {
+            self.set_cached(reg as usize - 1, val);
+        }
+    }
+
+    pub(super) fn get_hi(&mut self, prefix: &str) -> IntValue<'ctx> {
+        self.get_cached(HI, &format!("{}_hi", prefix))
+    }
+
+    pub(super) fn get_lo(&mut self, prefix: &str) -> IntValue<'ctx> {
+        self.get_cached(LO, &format!("{}_lo", prefix))
+    }
+
+    pub(super) fn set_hi(&mut self, val: IntValue<'ctx>) {
+        self.set_cached(HI, val);
+    }
+
+    pub(super) fn set_lo(&mut self, val: IntValue<'ctx>) {
+        self.set_cached(LO, val);
+    }
+
+    // Stores the registers written since they were last stored back, leaving them marked as
+    // written. Used on exception exits, which branch off the main path and leave it to store them
+    // again itself.
+    pub(super) fn emit_dirty_stores(&self) {
+        for (index, val) in self.regs.values.iter().enumerate() {
+            if let (Some(val), true) = (val, self.regs.dirty[index]) {
+                let ptr = self
+                    .builder
+                    .build_struct_gep(self.state_arg, index as u32, "writeback_ptr")
+                    .unwrap();
+                self.builder.build_store(ptr, *val);
+            }
+        }
+    }
+
+    // Brings the registers in the state up to date, ahead of a helper call or the block exiting.
+    // The values stay cached, so they don't need loading again.
+    pub(super) fn emit_writeback(&mut self) {
+        self.emit_dirty_stores();
+        self.regs.dirty = [false; 33];
+    }
+
+    // Writes back and forgets every cached register, for code that accesses registers in the
+    // state directly
+    pub(super) fn flush_regs(&mut self) {
+        self.emit_writeback();
+        self.regs = RegCache::default();
+    }
+
+    // Completes a pending load into `reg`, once the load's delay slot has executed
+    pub(super) fn apply_load_delay(&mut self, reg: u8) {
+        let i32_type = self.ctx.i32_type();
+
+        let value_ptr = self.gep_load_delay_value("ld");
+        let value = self
+            .builder
+            .build_load(value_ptr, "ld_delay_value")
+            .into_int_value();
+
+        let register_ptr = self.gep_load_delay_register("ld");
+        self.builder
+            .build_store(register_ptr, i32_type.const_zero());
+
+        self.set_gpr_value(reg, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::jit::harness::TestHarness;
+    use crate::cpu::jit::TbManager;
+
+    #[test]
+    fn jit_test_regcache_single_load() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+        state.gpr[0] = 3;
+
+        th.push_instr("addu", 2, 1, 1, 0, 0);
+        th.push_instr("addu", 3, 1, 2, 0, 0);
+        th.push_instr("addu", 4, 1, 3, 0, 0);
+        th.finish();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut tb_mgr = TbManager::new();
+
+                let tb = tb_mgr.get_tb(&ctx, 0x1000, bus)?;
+                tb.execute(state, bus, &mut tb_mgr)?;
+
+                // Only r1 comes from the state, and only r31 is loaded besides, for the jr
+                let ir = tb.to_string();
+                assert_eq!(ir.matches("_src_reg = load").count(), 2);
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[1], 6);
+        assert_eq!(state.gpr[2], 9);
+        assert_eq!(state.gpr[3], 12);
+    }
+}
@@ -2,6 +2,7 @@ use inkwell::values::IntValue;
 
 use super::decode;
 use super::TranslationBlock;
+use crate::cpu::cop0;
 
 impl<'ctx> TranslationBlock<'ctx> {
     fn emit_left_shift(&mut self, instr: &decode::MipsRInstr, shamt: IntValue) {
@@ -10,10 +11,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(
-            instr.d_reg,
-            &format!("{}_{}_d_reg", instr.function, self.count_uniq),
-        );
         let t_val = self.get_gpr_value(
             instr.t_reg,
             &format!("{}_{}", instr.function, self.count_uniq),
@@ -26,7 +23,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         );
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, sll_val);
+        self.set_gpr_value(instr.d_reg, sll_val);
     }
 
     pub(super) fn emit_sllv(&mut self, instr: &decode::MipsRInstr) {
@@ -47,10 +44,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(
-            instr.d_reg,
-            &format!("{}_{}_d_reg", instr.function, self.count_uniq),
-        );
         let t_val = self.get_gpr_value(
             instr.t_reg,
             &format!("{}_{}", instr.function, self.count_uniq),
@@ -64,7 +57,7 @@ impl<'ctx> TranslationBlock<'ctx> {
         );
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, sll_val);
+        self.set_gpr_value(instr.d_reg, sll_val);
     }
 
     pub(super) fn emit_srl(&mut self, instr: &decode::MipsRInstr) {
@@ -89,24 +82,80 @@ impl<'ctx> TranslationBlock<'ctx> {
         self.emit_right_shift(instr, s_reg, true);
     }
 
-    pub(super) fn emit_add(&mut self, instr: &decode::MipsRInstr) {
-        if self.finalized || instr.d_reg == 0 {
+    // Emits ADD, ADDI or SUB, which raise an overflow exception on signed overflow and leave the
+    // destination register unchanged
+    pub(super) fn emit_overflow_checked(
+        &mut self,
+        name: &str,
+        dest: u8,
+        s_val: IntValue<'ctx>,
+        t_val: IntValue<'ctx>,
+        sub: bool,
+    ) {
+        if self.finalized {
             self.instr_finished_emitting();
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("add_{}_d_reg", self.count_uniq));
+        let i32_type = self.ctx.i32_type();
+        let count = self.count_uniq;
 
-        let s_val = self.get_gpr_value(instr.s_reg, &format!("add_{}", self.count_uniq));
-        let t_val = self.get_gpr_value(instr.t_reg, &format!("add_{}", self.count_uniq));
+        let res = if sub {
+            self.builder
+                .build_int_sub(s_val, t_val, &format!("{}_{}_res", name, count))
+        } else {
+            self.builder
+                .build_int_add(s_val, t_val, &format!("{}_{}_res", name, count))
+        };
 
-        // FIXME: Throw arithmetic exception on signed overflow
-        let add_val =
+        // Overflow occurs when the sign of the result differs from the sign of s, and the operands
+        // had the same sign (add) or differing signs (sub)
+        let s_res_sign = self
+            .builder
+            .build_xor(s_val, res, &format!("{}_{}_s_res", name, count));
+        let operand_sign = if sub {
+            self.builder
+                .build_xor(s_val, t_val, &format!("{}_{}_s_t", name, count))
+        } else {
             self.builder
-                .build_int_add(s_val, t_val, &format!("add_{}_res", self.count_uniq));
+                .build_xor(t_val, res, &format!("{}_{}_t_res", name, count))
+        };
+        let overflow_bits = self.builder.build_and(
+            s_res_sign,
+            operand_sign,
+            &format!("{}_{}_ovf_bits", name, count),
+        );
+        let overflow = self.builder.build_int_compare(
+            inkwell::IntPredicate::SLT,
+            overflow_bits,
+            i32_type.const_zero(),
+            &format!("{}_{}_ovf", name, count),
+        );
+
+        let pc_reg = self.gep_pc(&format!("{}_{}", name, count));
+        let pc_val = self
+            .builder
+            .build_load(pc_reg, &format!("{}_{}_pc_val", name, count))
+            .into_int_value();
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, add_val);
+
+        self.raise_exception_if(
+            overflow,
+            &cop0::ExceptionCause::Overflow,
+            name,
+            &pc_reg,
+            &pc_val,
+            count,
+        );
+
+        self.set_gpr_value(dest, res);
+    }
+
+    pub(super) fn emit_add(&mut self, instr: &decode::MipsRInstr) {
+        let s_val = self.get_gpr_value(instr.s_reg, &format!("add_{}", self.count_uniq));
+        let t_val = self.get_gpr_value(instr.t_reg, &format!("add_{}", self.count_uniq));
+        self.emit_overflow_checked("add", instr.d_reg, s_val, t_val, false);
     }
 
     pub(super) fn emit_addu(&mut self, instr: &decode::MipsRInstr) {
@@ -115,8 +164,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("addu_{}_d_reg", self.count_uniq));
-
         let s_val = self.get_gpr_value(instr.s_reg, &format!("addu_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("addu_{}", self.count_uniq));
 
@@ -125,27 +172,13 @@ impl<'ctx> TranslationBlock<'ctx> {
                 .build_int_add(s_val, t_val, &format!("addu_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, add_val);
+        self.set_gpr_value(instr.d_reg, add_val);
     }
 
     pub(super) fn emit_sub(&mut self, instr: &decode::MipsRInstr) {
-        if self.finalized || instr.d_reg == 0 {
-            self.instr_finished_emitting();
-            return;
-        }
-
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("sub_{}_d_reg", self.count_uniq));
-
         let s_val = self.get_gpr_value(instr.s_reg, &format!("sub_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("sub_{}", self.count_uniq));
-
-        // FIXME: Throw integer overflow exception
-        let sub_val =
-            self.builder
-                .build_int_sub(s_val, t_val, &format!("sub_{}_res", self.count_uniq));
-
-        self.instr_finished_emitting();
-        self.builder.build_store(d_reg, sub_val);
+        self.emit_overflow_checked("sub", instr.d_reg, s_val, t_val, true);
     }
 
     pub(super) fn emit_subu(&mut self, instr: &decode::MipsRInstr) {
@@ -154,8 +187,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("sub_{}_d_reg", self.count_uniq));
-
         let s_val = self.get_gpr_value(instr.s_reg, &format!("sub_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("sub_{}", self.count_uniq));
 
@@ -164,7 +195,7 @@ impl<'ctx> TranslationBlock<'ctx> {
                 .build_int_sub(s_val, t_val, &format!("sub_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, sub_val);
+        self.set_gpr_value(instr.d_reg, sub_val);
     }
 
     pub(super) fn emit_or(&mut self, instr: &decode::MipsRInstr) {
@@ -173,8 +204,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("or_{}_d_reg", self.count_uniq));
-
         let s_val = self.get_gpr_value(instr.s_reg, &format!("or_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("or_{}", self.count_uniq));
 
@@ -183,7 +212,7 @@ impl<'ctx> TranslationBlock<'ctx> {
             .build_or(s_val, t_val, &format!("or_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, or_val);
+        self.set_gpr_value(instr.d_reg, or_val);
     }
 
     pub(super) fn emit_nor(&mut self, instr: &decode::MipsRInstr) {
@@ -192,8 +221,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("nor_{}_d_reg", self.count_uniq));
-
         let s_val = self.get_gpr_value(instr.s_reg, &format!("nor_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("nor_{}", self.count_uniq));
 
@@ -206,7 +233,7 @@ impl<'ctx> TranslationBlock<'ctx> {
             .build_not(or_val, &format!("nor_{}_nor", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, nor_val);
+        self.set_gpr_value(instr.d_reg, nor_val);
     }
 
     pub(super) fn emit_xor(&mut self, instr: &decode::MipsRInstr) {
@@ -215,8 +242,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("xor_{}_d_reg", self.count_uniq));
-
         let s_val = self.get_gpr_value(instr.s_reg, &format!("xor_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("xor_{}", self.count_uniq));
 
@@ -225,7 +250,7 @@ impl<'ctx> TranslationBlock<'ctx> {
             .build_xor(s_val, t_val, &format!("xor_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, xor_val);
+        self.set_gpr_value(instr.d_reg, xor_val);
     }
 
     pub(super) fn emit_and(&mut self, instr: &decode::MipsRInstr) {
@@ -234,8 +259,6 @@ impl<'ctx> TranslationBlock<'ctx> {
             return;
         }
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("and_{}_d_reg", self.count_uniq));
-
         let s_val = self.get_gpr_value(instr.s_reg, &format!("and_{}", self.count_uniq));
         let t_val = self.get_gpr_value(instr.t_reg, &format!("and_{}", self.count_uniq));
 
@@ -244,7 +267,7 @@ impl<'ctx> TranslationBlock<'ctx> {
             .build_and(s_val, t_val, &format!("and_{}_res", self.count_uniq));
 
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, and_val);
+        self.set_gpr_value(instr.d_reg, and_val);
     }
 
     fn emit_int_compare(&mut self, instr: &decode::MipsRInstr, pred: inkwell::IntPredicate) {
@@ -268,9 +291,8 @@ impl<'ctx> TranslationBlock<'ctx> {
             &format!("sltu_{}_zext", self.count_uniq),
         );
 
-        let d_reg = self.gep_gp_register(instr.d_reg, &format!("sltu_{}_d", self.count_uniq));
         self.instr_finished_emitting();
-        self.builder.build_store(d_reg, cmp_zext);
+        self.set_gpr_value(instr.d_reg, cmp_zext);
     }
 
     pub(super) fn emit_sltu(&mut self, instr: &decode::MipsRInstr) {
@@ -318,6 +340,31 @@ mod test {
         assert_eq!(state.gpr[0], 42);
     }
 
+    #[test]
+    fn jit_test_add_overflow() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.load32(1, 0x7fff_ffff);
+        th.load32(2, 1);
+        th.load32(3, 0xabcd);
+        th.push_instr("add", 3, 1, 2, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 0xabcd);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000 + 6 * 4
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xc << 2
+        );
+    }
+
     #[test]
     fn jit_test_subu() {
         let mut th = TestHarness::default();
@@ -350,6 +397,52 @@ mod test {
         assert_eq!(state.gpr[0], 5);
     }
 
+    #[test]
+    fn jit_test_sub_overflow() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.load32(1, 0x8000_0000);
+        th.load32(2, 1);
+        th.push_instr("sub", 2, 1, 2, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 1);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xc << 2
+        );
+    }
+
+    #[test]
+    fn jit_test_sub_overflow_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::jit::CpuState::default();
+
+        th.load32(1, 0x8000_0000);
+        th.load32(2, 1);
+        let branch_pc = th.current_pc_head();
+        th.push_instr("jr", 0, 31, 0, 0, 0);
+        th.push_instr("sub", 2, 1, 2, 0, 0);
+        th.finish();
+
+        th.execute(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 1);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            branch_pc
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            (1 << 31) | (0xc << 2)
+        );
+    }
+
     #[test]
     fn jit_test_or() {
         let mut th = TestHarness::default();
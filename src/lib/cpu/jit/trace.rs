@@ -0,0 +1,310 @@
+use super::chain::LINK_SLOTS;
+use super::{TbManager, TranslationBlock};
+use crate::cpu::bus::BusDevice;
+use inkwell::values::IntValue;
+use std::collections::HashMap;
+
+// Instructions a block is translated up to, after which it ends as soon as it isn't in the middle
+// of a delay slot
+const MAX_BLOCK_INSTRS: u64 = 128;
+
+// Instructions a trace is translated up to, which are longer than blocks as they are worth the
+// time spent compiling them
+const MAX_TRACE_INSTRS: u64 = 512;
+
+// Times a block is entered before it is translated again as a trace. Blocks are only checked as
+// the dispatcher runs them, so one that is only ever chained into is never made a trace.
+pub(super) const TRACE_THRESHOLD: u64 = 1024;
+
+// Where translation carries on once the delay slot of a jump or branch the block follows has been
+// emitted, given as the offset of the instruction from the block's start
+pub(super) struct Follow<'ctx> {
+    pub(super) offset: u32,
+    // For a branch followed the way it mostly goes, the condition for it going the other way, and
+    // the offset the block leaves to then
+    pub(super) side_exit: Option<(IntValue<'ctx>, u32)>,
+}
+
+impl<'ctx> TranslationBlock<'ctx> {
+    // Translates the block at `pc` as a trace, which follows its branches the way they mostly go
+    // according to `profile`, and leaves through a side exit wherever they go the other way
+    pub fn translate_trace(
+        &mut self,
+        bus: &mut dyn BusDevice,
+        pc: u32,
+        profile: HashMap<u32, u64>,
+    ) -> Result<(), String> {
+        self.profile = Some(profile);
+        self.trace = true;
+
+        let result = self.translate(bus, pc);
+        self.profile = None;
+        result
+    }
+
+    // Offset from the block's start of the instruction at position `count`
+    pub(super) fn pc_offset(&self, count: u64) -> u32 {
+        self.offsets[count as usize]
+    }
+
+    pub(super) fn max_instrs(&self) -> u64 {
+        if self.trace {
+            MAX_TRACE_INSTRS
+        } else {
+            MAX_BLOCK_INSTRS
+        }
+    }
+
+    // Whether translation can carry on at `offset` rather than leaving the block. Code the block
+    // already covers isn't translated again, so that loops still leave and chain back round.
+    fn can_follow(&self, offset: u32) -> bool {
+        let addr = self.pc.wrapping_add(offset);
+
+        self.count_uniq < self.max_instrs()
+            && !self
+                .ranges
+                .iter()
+                .any(|(start, end)| *start <= addr && addr < *end)
+    }
+
+    // Carries on translating at `offset` once the delay slot is emitted, returning false if the
+    // jump must leave the block instead
+    pub(super) fn follow_jump(&mut self, offset: u32) -> bool {
+        if !self.can_follow(offset) {
+            return false;
+        }
+
+        self.follow = Some(Follow {
+            offset,
+            side_exit: None,
+        });
+        true
+    }
+
+    // Carries on translating on one side of a branch once the delay slot is emitted, returning
+    // false if the branch must leave the block instead. Only traces follow branches, the way the
+    // block there has been entered more often.
+    pub(super) fn follow_branch(
+        &mut self,
+        cond: IntValue<'ctx>,
+        taken: u32,
+        not_taken: u32,
+    ) -> bool {
+        let profile = match &self.profile {
+            Some(profile) => profile,
+            None => return false,
+        };
+        let count = |offset: u32| {
+            let addr = self.pc.wrapping_add(offset) & 0x1fff_ffff;
+            profile.get(&addr).copied().unwrap_or(0)
+        };
+
+        let (hot, cold, goes_cold) = match count(taken).cmp(&count(not_taken)) {
+            std::cmp::Ordering::Greater => (taken, not_taken, None),
+            std::cmp::Ordering::Less => (not_taken, taken, Some(cond)),
+            std::cmp::Ordering::Equal => return false,
+        };
+
+        // Each side exit takes a link slot, and enough must be left for the exits at the end
+        if self.successors.len() + 4 > LINK_SLOTS || !self.can_follow(hot) {
+            return false;
+        }
+
+        let leave = goes_cold.unwrap_or_else(|| {
+            self.builder
+                .build_not(cond, &format!("b_{}_not_taken", self.count_uniq))
+        });
+        self.follow = Some(Follow {
+            offset: hot,
+            side_exit: Some((leave, cold)),
+        });
+        true
+    }
+
+    // Leaves the block for the instruction at `offset` when `leave` is set, and carries on
+    // emitting the block where it isn't
+    pub(super) fn emit_side_exit(&mut self, leave: IntValue<'ctx>, offset: u32) {
+        let exit_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("side_exit_{}", self.count_uniq));
+        let trace_block = self
+            .ctx
+            .append_basic_block(self.func, &format!("trace_{}", self.count_uniq));
+        self.builder
+            .build_conditional_branch(leave, exit_block, trace_block);
+
+        self.builder.position_at_end(exit_block);
+        self.emit_store_pc(offset, &format!("side_exit_{}", self.count_uniq));
+        self.add_successor(self.pc.wrapping_add(offset));
+
+        // The registers stay cached on the way through the trace, which stores them back itself
+        self.emit_dirty_stores();
+        self.emit_chained_exit();
+
+        self.builder.position_at_end(trace_block);
+    }
+
+    // Counts the block being entered, for finding the blocks hot enough to make traces of
+    pub(super) fn emit_exec_count(&self) {
+        let i64_type = self.ctx.i64_type();
+
+        let count_ptr = self.exec_count_global().as_pointer_value();
+        let count = self
+            .builder
+            .build_load(count_ptr, "exec_count")
+            .into_int_value();
+        let new_count =
+            self.builder
+                .build_int_add(count, i64_type.const_int(1, false), "exec_count_new");
+        self.builder.build_store(count_ptr, new_count);
+    }
+}
+
+impl<'ctx> TbManager<'ctx> {
+    pub(super) fn exec_count(&self, addr: u32) -> u64 {
+        self.exec_counts
+            .get(&(addr & 0x1fff_ffff))
+            .map_or(0, |count| count.get())
+    }
+
+    // Where the block at `addr` counts the times it is entered
+    pub(super) fn exec_count_ptr(&mut self, addr: u32) -> *const u64 {
+        self.exec_counts
+            .entry(addr & 0x1fff_ffff)
+            .or_default()
+            .as_ptr()
+    }
+
+    // How many times each block has been entered so far, for translating a trace
+    pub(super) fn profile(&self) -> HashMap<u32, u64> {
+        self.exec_counts
+            .iter()
+            .map(|(addr, count)| (*addr, count.get()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::backend::run_until_halted;
+    use crate::cpu::jit::harness::TestHarness;
+    use crate::cpu::jit::{Jit, TbManager};
+
+    #[test]
+    fn jit_test_block_past_line_end() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // Straight-line code running from one line of 64 instructions into the next
+        th.addr = 0x10f0;
+        for _ in 0..8 {
+            th.push_instr("addiu", 0, 1, 1, 1, 0);
+        }
+        th.finish();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut tb_mgr = TbManager::new();
+
+                let tb = tb_mgr.get_tb(&ctx, 0x10f0, bus)?;
+                assert_eq!(tb.ranges, vec![(0x10f0, 0x1118)]);
+                assert_eq!(tb.count_uniq, 10);
+                tb.execute(state, bus, &mut tb_mgr)?;
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[0], 8);
+    }
+
+    #[test]
+    fn jit_test_follow_jump() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("jal", 0, 0, 0, 0, 0x1100 >> 2);
+        th.push_instr("addiu", 0, 1, 1, 2, 0);
+        th.push_at(0x1100);
+        th.push_instr("addiu", 0, 1, 2, 4, 0);
+        th.push_instr("j", 0, 0, 0, 0, 0x1200 >> 2);
+        // A load in the delay slot completes after the instruction at the target
+        th.push_instr("lw", 0, 0, 3, 0x1000, 0);
+        th.push_at(0x1200);
+        th.push_instr("addu", 4, 3, 0, 0, 0);
+        th.push_instr("addu", 5, 3, 0, 0, 0);
+        th.push_instr("syscall", 0, 0, 0, 0, 0);
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut tb_mgr = TbManager::new();
+
+                let tb = tb_mgr.get_tb(&ctx, 0x1000, bus)?;
+                assert_eq!(
+                    tb.ranges,
+                    vec![(0x1000, 0x100c), (0x1100, 0x110c), (0x1200, 0x120c)]
+                );
+                tb.execute(state, bus, &mut tb_mgr)?;
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        let first_instr = crate::cpu::decode::mips_encode_str("addiu", 0, 0, 1, 1, 0).unwrap();
+        assert_eq!(state.gpr[0], 3);
+        assert_eq!(state.gpr[1], 7);
+        assert_eq!(state.gpr[2], first_instr);
+        assert_eq!(state.gpr[3], 0);
+        assert_eq!(state.gpr[4], first_instr);
+        assert_eq!(state.gpr[30], 0x100c);
+
+        // The syscall's EPC is its own address, not its position in the block
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1208
+        );
+    }
+
+    #[test]
+    fn jit_test_hot_loop_trace() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // A loop that takes a rare branch every fourth iteration, and jumps past it otherwise
+        th.load32(1, 4000);
+        th.push_instr("andi", 0, 1, 2, 3, 0);
+        th.push_instr("beq", 0, 2, 0, 3, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.push_instr("j", 0, 0, 0, 0, 0x1020 >> 2);
+        th.push_instr("addiu", 0, 4, 4, 1, 0);
+        th.push_instr("addiu", 0, 3, 3, 1, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.push_instr("bne", 0, 1, 0, -8i16 as u16, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let ctx = inkwell::context::Context::create();
+                let mut backend = Jit::new(&ctx);
+                let stats = run_until_halted(&mut backend, bus, state)?;
+
+                // The loop is made a trace, which leaves through a side exit for the rare branch
+                assert!(stats.translation.unwrap().traces_formed > 0);
+                assert_eq!(stats.icount, 2 + 3000 * 8 + 1000 * 7 + 2);
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.gpr[2], 1000);
+        assert_eq!(state.gpr[3], 3000);
+    }
+}
@@ -0,0 +1,155 @@
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::module::Module;
+use inkwell::passes::PassManager;
+use inkwell::values::FunctionValue;
+
+// How hard LLVM works on the code generated for each block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptLevel {
+    None,
+    Less,
+    Default,
+    Aggressive,
+}
+
+impl std::str::FromStr for OptLevel {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "0" | "none" => Ok(Self::None),
+            "1" | "less" => Ok(Self::Less),
+            "2" | "default" => Ok(Self::Default),
+            "3" | "aggressive" => Ok(Self::Aggressive),
+            _ => Err(String::from("Invalid optimization level")),
+        }
+    }
+}
+
+impl From<OptLevel> for inkwell::OptimizationLevel {
+    fn from(level: OptLevel) -> Self {
+        match level {
+            OptLevel::None => Self::None,
+            OptLevel::Less => Self::Less,
+            OptLevel::Default => Self::Default,
+            OptLevel::Aggressive => Self::Aggressive,
+        }
+    }
+}
+
+// Function passes that can be run over each block before it is compiled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    Mem2Reg,
+    InstCombine,
+    Reassociate,
+    Gvn,
+    EarlyCse,
+    Sccp,
+    Dse,
+    SimplifyCfg,
+    Adce,
+}
+
+impl std::str::FromStr for Pass {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mem2reg" => Ok(Self::Mem2Reg),
+            "instcombine" => Ok(Self::InstCombine),
+            "reassociate" => Ok(Self::Reassociate),
+            "gvn" => Ok(Self::Gvn),
+            "early-cse" | "earlycse" => Ok(Self::EarlyCse),
+            "sccp" => Ok(Self::Sccp),
+            "dse" => Ok(Self::Dse),
+            "simplifycfg" => Ok(Self::SimplifyCfg),
+            "adce" => Ok(Self::Adce),
+            _ => Err(format!("Invalid pass: {}", s)),
+        }
+    }
+}
+
+// Settings for compiling blocks with LLVM in the JIT
+#[derive(Debug, Clone, PartialEq)]
+pub struct JitConfig {
+    // Level the execution engine generates machine code at
+    pub opt_level: OptLevel,
+    // Passes run over each block's function, in order, before machine code is generated
+    pub passes: Vec<Pass>,
+    // Directory to keep translated blocks in between runs, if any
+    pub cache_dir: Option<std::path::PathBuf>,
+}
+
+impl Default for JitConfig {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::Less,
+            passes: Vec::new(),
+            cache_dir: None,
+        }
+    }
+}
+
+impl JitConfig {
+    // Parses a comma separated list of passes, such as "mem2reg,instcombine,gvn"
+    pub fn parse_passes(s: &str) -> Result<Vec<Pass>, String> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    pub fn create_engine<'ctx>(
+        &self,
+        module: &Module<'ctx>,
+    ) -> Result<ExecutionEngine<'ctx>, String> {
+        module
+            .create_jit_execution_engine(self.opt_level.into())
+            .map_err(|e| e.to_string())
+    }
+
+    // Runs the configured passes over `func`, which must be in `module`
+    pub fn run_passes<'ctx>(&self, module: &Module<'ctx>, func: FunctionValue<'ctx>) {
+        if self.passes.is_empty() {
+            return;
+        }
+
+        let fpm = PassManager::create(module);
+        for pass in &self.passes {
+            match pass {
+                Pass::Mem2Reg => fpm.add_promote_memory_to_register_pass(),
+                Pass::InstCombine => fpm.add_instruction_combining_pass(),
+                Pass::Reassociate => fpm.add_reassociate_pass(),
+                Pass::Gvn => fpm.add_gvn_pass(),
+                Pass::EarlyCse => fpm.add_early_cse_pass(),
+                Pass::Sccp => fpm.add_sccp_pass(),
+                Pass::Dse => fpm.add_dead_store_elimination_pass(),
+                Pass::SimplifyCfg => fpm.add_cfg_simplification_pass(),
+                Pass::Adce => fpm.add_aggressive_dce_pass(),
+            }
+        }
+
+        fpm.initialize();
+        fpm.run_on(&func);
+        fpm.finalize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JitConfig, OptLevel, Pass};
+
+    #[test]
+    fn jit_test_config_parse() {
+        assert_eq!("2".parse::<OptLevel>(), Ok(OptLevel::Default));
+        assert_eq!("Aggressive".parse::<OptLevel>(), Ok(OptLevel::Aggressive));
+        assert!("4".parse::<OptLevel>().is_err());
+
+        assert_eq!(
+            JitConfig::parse_passes("mem2reg, instcombine,gvn"),
+            Ok(vec![Pass::Mem2Reg, Pass::InstCombine, Pass::Gvn])
+        );
+        assert_eq!(JitConfig::parse_passes(""), Ok(vec![]));
+        assert!(JitConfig::parse_passes("mem2reg,licm").is_err());
+    }
+}
@@ -1,10 +1,19 @@
+pub mod backend;
 pub mod bus;
 pub mod bus_vec;
+pub mod code_map;
 pub mod decode;
+pub mod icache;
 pub mod interpret;
+pub mod interrupt;
+#[cfg(feature = "jit")]
 pub mod jit;
+#[cfg(feature = "jit")]
+pub mod llvm;
 pub mod opcode;
+#[cfg(feature = "threaded")]
 pub mod threaded;
+pub mod timing;
 pub mod trie;
 
 #[cfg(test)]
@@ -24,11 +33,13 @@ pub mod cop0 {
         Interrupt = 0x0,
         AddressErrOnLoad = 0x4,
         AddressErrOnStore = 0x5,
+        InstructionBusError = 0x6,
+        DataBusError = 0x7,
         Syscall = 0x8,
         Break = 0x9,
-        ReservedInstruction = 0x10,
-        CopUnusable(u8) = 0x11,
-        Overflow = 0x12,
+        ReservedInstruction = 0xa,
+        CopUnusable(u8) = 0xb,
+        Overflow = 0xc,
     }
 
     impl ExceptionCause {
@@ -38,6 +49,26 @@ pub mod cop0 {
     }
 }
 
+// How failed bus accesses by the guest are handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusErrorPolicy {
+    // Raise a bus error exception for the guest to handle
+    Exception,
+    // Raise the exception, then stop execution and return the error to the caller
+    Abort,
+}
+
+impl std::str::FromStr for BusErrorPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "exception" | "exc" => Ok(Self::Exception),
+            "abort" => Ok(Self::Abort),
+            _ => Err(String::from("Invalid bus error policy")),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct CpuState {
@@ -52,6 +83,25 @@ pub struct CpuState {
     pub(super) load_delay_register_value: u32,
 
     pub(super) cop0_reg: [u32; 16],
+
+    // Cycles elapsed since reset, for scheduling devices against
+    pub(super) cycles: u64,
+    // The JIT chains blocks together while `cycles` is below the limit, counting the instructions
+    // they execute
+    pub(super) chain_limit: u64,
+    pub(super) chain_icount: u64,
+
+    // Not accessed by translated code, so these must stay after the registers above
+    bus_error_policy: BusErrorPolicy,
+    bus_error: Option<bus::MemAccessError>,
+    interrupts: interrupt::InterruptLines,
+
+    // The cycle at which the multiply or divide last started will have written HI/LO
+    hilo_ready: u64,
+
+    icache: Option<icache::ICache>,
+    // Set by stores while the cache is isolated, which is how the BIOS flushes it
+    icache_flushed: bool,
 }
 
 impl CpuState {
@@ -59,6 +109,114 @@ impl CpuState {
         self.pc = pc;
     }
 
+    // Resets the registers to their power-on values, with execution starting from the reset vector
+    // in the BIOS. The bus error policy and the interrupt lines are left in place.
+    pub fn reset(&mut self) {
+        self.gpr = [0; 31];
+        self.hi = 0;
+        self.lo = 0;
+        self.pc = 0xbfc0_0000;
+        self.load_delay_register = 0;
+        self.load_delay_register_value = 0;
+
+        // Exceptions vector to the BIOS until it clears BEV
+        self.cop0_reg = [0; 16];
+        self.cop0_reg[cop0::Register::Sr as usize] = 1 << 22;
+
+        self.bus_error = None;
+
+        self.cycles = 0;
+        self.chain_limit = 0;
+        self.chain_icount = 0;
+        self.hilo_ready = 0;
+
+        if let Some(icache) = self.icache.as_mut() {
+            *icache = icache::ICache::default();
+        }
+        self.icache_flushed = false;
+    }
+
+    pub fn set_bus_error_policy(&mut self, policy: BusErrorPolicy) {
+        self.bus_error_policy = policy;
+    }
+
+    // Models the instruction cache, so that fetches that hit in it don't stall. Only the
+    // interpreter looks fetches up in the cache. The translated backends count a block's cycles
+    // when translating it, so always count fetches as uncached. Stores while the cache is isolated
+    // are kept out of memory by every backend, whether or not the cache is modelled.
+    pub fn enable_icache(&mut self) {
+        self.icache = Some(icache::ICache::default());
+    }
+
+    // Handle for devices to raise hardware interrupts with
+    pub fn interrupt_lines(&self) -> interrupt::InterruptLines {
+        self.interrupts.clone()
+    }
+
+    // Cycles elapsed since reset. Backends that execute a block at a time only update this between
+    // blocks.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub(super) fn add_cycles(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    // Starts a multiply or divide taking `latency` cycles. `offset` is how many cycles into the
+    // current block the instruction is, for the backends that only count cycles between blocks.
+    pub(super) fn start_hilo_op(&mut self, latency: u64, offset: u64) {
+        self.hilo_ready = self.cycles + offset + latency;
+    }
+
+    // Stalls until the multiply or divide in progress has written HI/LO, when they are read by an
+    // instruction `offset` cycles into the current block
+    pub(super) fn wait_for_hilo(&mut self, offset: u64) {
+        let now = self.cycles + offset;
+        if now < self.hilo_ready {
+            self.cycles += self.hilo_ready - now;
+        }
+    }
+
+    // Stall cycles for fetching the instruction at `addr`. Fetches that hit in the I-cache, when it
+    // is modelled, don't stall.
+    pub(super) fn fetch_wait_states(&mut self, bus: &dyn bus::BusDevice, addr: u32) -> u64 {
+        if let Some(icache) = self.icache.as_mut() {
+            if icache::ICache::is_cacheable(addr) && icache.fetch(addr) {
+                return 0;
+            }
+        }
+
+        bus.wait_states(addr, 32) as u64
+    }
+
+    // Stores don't reach memory while SR.IsC isolates the cache. Returns whether the store to
+    // `addr` was taken by the cache, in which case it must not be written to the bus.
+    pub(super) fn store_isolated(&mut self, addr: u32) -> bool {
+        if self.cop0_reg[cop0::Register::Sr as usize] & (1 << 16) == 0 {
+            return false;
+        }
+
+        if let Some(icache) = self.icache.as_mut() {
+            icache.isolated_store(addr);
+        }
+
+        self.icache_flushed = true;
+        true
+    }
+
+    // Returns whether the guest has flushed the I-cache since the last call, so translated code
+    // must be discarded. A flush is only reported once the cache is no longer isolated, as the
+    // BIOS stores to every line in turn.
+    #[cfg_attr(not(any(feature = "jit", feature = "threaded")), allow(dead_code))]
+    pub(super) fn take_icache_flush(&mut self) -> bool {
+        if self.cop0_reg[cop0::Register::Sr as usize] & (1 << 16) != 0 {
+            return false;
+        }
+
+        std::mem::take(&mut self.icache_flushed)
+    }
+
     pub fn get_reg_val(&self, reg: u8) -> u32 {
         if reg == 0 {
             0
@@ -72,8 +230,174 @@ impl CpuState {
             return;
         }
 
+        // A write by the instruction in the load delay slot takes priority over the load
+        if self.load_delay_register == reg as u32 {
+            self.load_delay_register = 0;
+        }
+
         self.gpr[(reg - 1) as usize] = val;
     }
+
+    // Stages a load into `reg`, to be written back after the following instruction has executed.
+    // Any load that is still pending is written back first, unless it is to the same register, in
+    // which case it is replaced like the JIT does.
+    pub(super) fn stage_load(&mut self, reg: u8, val: u32) {
+        if self.load_delay_register != reg as u32 {
+            self.apply_load_delay();
+        }
+
+        self.load_delay_register = reg as u32;
+        self.load_delay_register_value = val;
+    }
+
+    // Writes back the pending load, if there is one.
+    // This mirrors TranslationBlock::apply_load_delay_if_present for the backends that execute in
+    // Rust.
+    pub(super) fn apply_load_delay(&mut self) {
+        let reg = self.load_delay_register as usize;
+        if reg != 0 {
+            self.gpr[reg - 1] = self.load_delay_register_value;
+        }
+
+        self.load_delay_register = 0;
+    }
+
+    // Checks that `vaddr` is aligned for an access of `size` bits. If it isn't, BadVaddr is set to
+    // the faulting address and the address error to raise is returned.
+    pub(super) fn check_alignment(
+        &mut self,
+        vaddr: u32,
+        size: u32,
+        store: bool,
+    ) -> Result<(), cop0::ExceptionCause> {
+        if vaddr & (size / 8 - 1) == 0 {
+            return Ok(());
+        }
+
+        self.cop0_reg[cop0::Register::BadVaddr as usize] = vaddr;
+
+        if store {
+            Err(cop0::ExceptionCause::AddressErrOnStore)
+        } else {
+            Err(cop0::ExceptionCause::AddressErrOnLoad)
+        }
+    }
+
+    // Checks that coprocessor `cop` is usable, returning the exception to raise if it isn't.
+    // COP0 is always usable since user mode isn't modelled, and the PS1 has no COP1 or COP3.
+    pub(super) fn check_cop_usable(&self, cop: u8) -> Result<(), cop0::ExceptionCause> {
+        let usable = match cop {
+            0 => true,
+            2 => self.cop0_reg[cop0::Register::Sr as usize] & (1 << 30) != 0,
+            _ => false,
+        };
+
+        if usable {
+            Ok(())
+        } else {
+            Err(cop0::ExceptionCause::CopUnusable(cop))
+        }
+    }
+
+    // Raises an address error if the PC is misaligned, which happens when fetching from a bad jump
+    // target. Returns whether the exception was raised.
+    pub(super) fn raise_if_pc_misaligned(&mut self) -> bool {
+        match self.check_alignment(self.pc, 32, false) {
+            Ok(_) => false,
+            Err(cause) => {
+                // The fetch faults at the target itself, so EPC holds the bad address
+                self.pc = self.raise_exception(&cause, self.pc, false);
+                true
+            }
+        }
+    }
+
+    // Handles a failed bus access, returning the bus error exception to raise.
+    // Under the abort policy, the error is also kept for the backend to return once the exception
+    // has been raised.
+    pub(super) fn bus_error(
+        &mut self,
+        err: bus::MemAccessError,
+        instr_fetch: bool,
+    ) -> cop0::ExceptionCause {
+        if self.bus_error_policy == BusErrorPolicy::Abort {
+            self.bus_error = Some(err);
+        }
+
+        if instr_fetch {
+            cop0::ExceptionCause::InstructionBusError
+        } else {
+            cop0::ExceptionCause::DataBusError
+        }
+    }
+
+    // Returns the bus error that execution should stop on, if there is one
+    pub(super) fn take_bus_error(&mut self) -> Result<(), String> {
+        match self.bus_error.take() {
+            Some(e) => Err(format!("Bus error: {:#08x?}", e)),
+            None => Ok(()),
+        }
+    }
+
+    // Latches the hardware interrupt lines into Cause, and raises an interrupt if one is pending
+    // and enabled in SR. Should only be called between instructions, outside of a delay slot, as
+    // the PC is taken to be the next instruction to execute.
+    // Returns whether the interrupt was raised.
+    pub(super) fn raise_if_interrupt_pending(&mut self) -> bool {
+        let cause = self.cop0_reg[cop0::Register::Cause as usize];
+        let cause = (cause & !0xfc00) | ((self.interrupts.asserted() as u32) << 10);
+        self.cop0_reg[cop0::Register::Cause as usize] = cause;
+
+        // IEc must be set, and the pending interrupt must be unmasked by IM
+        let sr = self.cop0_reg[cop0::Register::Sr as usize];
+        if sr & 1 == 0 || cause & sr & 0xff00 == 0 {
+            return false;
+        }
+
+        // A load from the interrupted code completes before the handler runs
+        self.apply_load_delay();
+
+        self.pc = self.raise_exception(&cop0::ExceptionCause::Interrupt, self.pc, false);
+        true
+    }
+
+    // Updates COP0 for an exception raised by the instruction at `pc`, and returns the address of
+    // the exception vector to continue execution from.
+    // This mirrors TranslationBlock::raise_exception for the backends that execute in Rust.
+    pub(super) fn raise_exception(
+        &mut self,
+        cause: &cop0::ExceptionCause,
+        pc: u32,
+        in_delay_slot: bool,
+    ) -> u32 {
+        let mut cause_val = (cause.to_int() as u32) << 2;
+
+        // In delay slot, EPC should point to the branch instruction
+        if in_delay_slot {
+            self.cop0_reg[cop0::Register::Epc as usize] = pc - 4;
+            cause_val |= 1 << 31;
+        } else {
+            self.cop0_reg[cop0::Register::Epc as usize] = pc;
+        }
+
+        if let cop0::ExceptionCause::CopUnusable(cop) = *cause {
+            cause_val |= ((cop & 0b11) as u32) << 28;
+        }
+
+        // The pending interrupt bits are left in place
+        let ip = self.cop0_reg[cop0::Register::Cause as usize] & 0xff00;
+        self.cop0_reg[cop0::Register::Cause as usize] = cause_val | ip;
+
+        // Push the KU/IE mode stack, entering kernel mode with interrupts disabled
+        let sr = self.cop0_reg[cop0::Register::Sr as usize];
+        self.cop0_reg[cop0::Register::Sr as usize] = (sr & !0x3f) | ((sr << 2) & 0x3f);
+
+        if sr & (1 << 22) != 0 {
+            0xbfc0_0180
+        } else {
+            0x8000_0080
+        }
+    }
 }
 
 impl Default for CpuState {
@@ -86,6 +410,15 @@ impl Default for CpuState {
             load_delay_register: 0,
             load_delay_register_value: 0,
             cop0_reg: [0; 16],
+            cycles: 0,
+            chain_limit: 0,
+            chain_icount: 0,
+            bus_error_policy: BusErrorPolicy::Exception,
+            bus_error: None,
+            interrupts: interrupt::InterruptLines::default(),
+            hilo_ready: 0,
+            icache: None,
+            icache_flushed: false,
         }
     }
 }
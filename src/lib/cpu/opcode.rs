@@ -235,11 +235,23 @@ pub enum MipsBranchSpecial {
     Invalid = 0x1f,
 }
 
+impl MipsBranchSpecial {
+    pub(crate) fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "bltz" => Some(MipsBranchSpecial::Bltz),
+            "bgez" => Some(MipsBranchSpecial::Bgez),
+            "bltzal" => Some(MipsBranchSpecial::Bltzal),
+            "bgezal" => Some(MipsBranchSpecial::Bgezal),
+            _ => None,
+        }
+    }
+}
+
 impl std::fmt::Display for MipsBranchSpecial {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             MipsBranchSpecial::Bltz => write!(f, "bltz"),
-            MipsBranchSpecial::Bgez => write!(f, "begz"),
+            MipsBranchSpecial::Bgez => write!(f, "bgez"),
             MipsBranchSpecial::Bltzal => write!(f, "bltzal"),
             MipsBranchSpecial::Bgezal => write!(f, "bgezal"),
             _ => write!(f, "INVALID"),
@@ -247,6 +259,31 @@ impl std::fmt::Display for MipsBranchSpecial {
     }
 }
 
+// Commands for COP0, encoded in the function field of a coprocessor instruction with the CO bit set
+#[derive(Debug, FromPrimitive, Copy, Clone)]
+pub enum MipsCop0Command {
+    Rfe = 0x10,
+    Invalid = 0x3f,
+}
+
+impl MipsCop0Command {
+    pub(crate) fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "rfe" => Some(MipsCop0Command::Rfe),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MipsCop0Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MipsCop0Command::Rfe => write!(f, "rfe"),
+            _ => write!(f, "INVALID"),
+        }
+    }
+}
+
 #[derive(Debug, FromPrimitive, Copy, Clone)]
 pub enum MipsCopOperation {
     MoveFrom = 0x0,
@@ -26,20 +26,27 @@ impl Default for TestHarness {
 }
 
 impl TestHarness {
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
     pub(crate) fn push_dummy_load(&mut self, target_reg: u8) {
         // This is a dummy load with the intention to overwrite the target register in the delay
         // slot if the register write staging is not done correctly.
         self.push_instr("lw", 0, 0, target_reg, 0x2000 - 0x4, 0);
     }
 
-    pub(crate) fn push_instr(&mut self, op: &str, d: u8, s: u8, t: u8, imm: u16, tgt: u32) {
-        let instr_bin = decode::mips_encode_str(op, d, s, t, imm, tgt).unwrap();
+    // Pushes an already encoded instruction, for encodings that mips_encode_str can't express
+    pub(crate) fn push_raw(&mut self, instr_bin: u32) {
         self.bus
             .write(self.addr + 4 * self.icount, 32, instr_bin)
             .unwrap();
         self.icount += 1;
     }
 
+    pub(crate) fn push_instr(&mut self, op: &str, d: u8, s: u8, t: u8, imm: u16, tgt: u32) {
+        let instr_bin = decode::mips_encode_str(op, d, s, t, imm, tgt).unwrap();
+        self.push_raw(instr_bin);
+    }
+
+    #[cfg_attr(not(any(feature = "jit", feature = "threaded")), allow(dead_code))]
     pub(crate) fn finish(&mut self) {
         // Simulate a return and nop in delay slot
         // Don't need the return address to be valid since we only execute one block
@@ -47,15 +54,54 @@ impl TestHarness {
         self.push_instr("sll", 0, 0, 0, 0, 0);
     }
 
+    // Pushes the instructions that follow from `addr` on, for code that is jumped to. Execution
+    // still starts at the first instruction pushed.
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub(crate) fn push_at(&mut self, addr: u32) {
+        assert!(addr >= self.addr + 4 * self.icount);
+        self.icount = (addr - self.addr) / 4;
+    }
+
+    pub(crate) fn finish_loop(&mut self) {
+        // Branch to self, which ends execution for backends that run until the PC stops changing
+        self.push_instr("beq", 0, 0, 0, 0xffff, 0);
+        self.push_instr("sll", 0, 0, 0, 0, 0);
+    }
+
+    pub(crate) fn map_exception_vector(&mut self) {
+        // Map both the RAM and ROM exception vectors, and have them loop on themselves
+        for (base, vector) in [(0x0, 0x80), (0x1fc0_0000, 0x180)] {
+            self.bus.map(base, 0x1000, Box::new(RAM::new(0x1000)));
+
+            let spin = decode::mips_encode_str("beq", 0, 0, 0, 0xffff, 0).unwrap();
+            self.bus.write(base + vector, 32, spin).unwrap();
+        }
+    }
+
+    // Puts an instruction at the start of the RAM exception handler, ahead of the loop it then
+    // spins on. Must be called after map_exception_vector.
+    pub(crate) fn push_handler_instr(&mut self, op: &str, d: u8, s: u8, t: u8, imm: u16, tgt: u32) {
+        let instr_bin = decode::mips_encode_str(op, d, s, t, imm, tgt).unwrap();
+        let spin = decode::mips_encode_str("beq", 0, 0, 0, 0xffff, 0).unwrap();
+        self.bus.write(0x80, 32, instr_bin).unwrap();
+        self.bus.write(0x84, 32, spin).unwrap();
+    }
+
+    pub(crate) fn set_wait_states(&mut self, addr: u32, wait_states: u32) {
+        self.bus.set_wait_states(addr, wait_states);
+    }
+
     pub(crate) fn load32(&mut self, reg: u8, imm: u32) {
         self.push_instr("lui", 0, 0, reg, (imm >> 16) as u16, 0);
         self.push_instr("ori", 0, reg, reg, (imm & 0xffff) as u16, 0);
     }
 
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
     pub(crate) fn current_pc_head(&self) -> u32 {
         self.addr + self.icount * 4
     }
 
+    #[cfg(feature = "jit")]
     pub(crate) fn execute(&mut self, state: &mut CpuState) -> Result<(), String> {
         let ctx = inkwell::context::Context::create();
         let mut tb_mgr = crate::cpu::jit::TbManager::new();
@@ -70,6 +116,7 @@ impl TestHarness {
     }
 
     // Debug version of execute, that prints the generated TB IR to stderr
+    #[cfg(feature = "jit")]
     #[allow(dead_code)]
     pub(crate) fn execute_dbg(&mut self, state: &mut CpuState) -> Result<(), String> {
         let ctx = inkwell::context::Context::create();
@@ -85,7 +132,32 @@ impl TestHarness {
         Ok(())
     }
 
-    pub(crate) fn _execute_generic(
+    // Executes a single block with the threaded backend, like execute does with the JIT
+    #[cfg(feature = "threaded")]
+    pub(crate) fn execute_threaded(&mut self, state: &mut CpuState) -> Result<(), String> {
+        let mut tb_mgr = crate::cpu::threaded::TbManager::new();
+
+        state.set_pc(self.addr);
+        let tb = tb_mgr.get_tb(self.addr, &mut self.bus)?;
+        tb.execute(state, &mut self.bus, &mut tb_mgr);
+
+        self.addr = state.pc;
+
+        Ok(())
+    }
+
+    pub(crate) fn execute_interpreter(&mut self, state: &mut CpuState) -> Result<(), String> {
+        self.execute_generic(
+            state,
+            Box::new(|state, bus| {
+                let mut backend = crate::cpu::interpret::Interpreter::default();
+                crate::cpu::backend::run_until_halted(&mut backend, bus, state)?;
+                Ok(())
+            }),
+        )
+    }
+
+    pub(crate) fn execute_generic(
         &mut self,
         state: &mut CpuState,
         executor: Box<dyn Fn(&mut CpuState, &mut VecBus) -> Result<(), String>>,
@@ -0,0 +1,164 @@
+use super::cop0;
+use super::decode;
+use super::opcode::{MipsCop0Command, MipsCopOperation};
+use super::threaded_raise_exception;
+
+use super::{BusType, CpuState, Flow, Op, TbManager, ThreadBlock};
+
+pub(super) fn threaded_mtc0(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    state.cop0_reg[op.d_reg as usize] = state.get_reg_val(op.t_reg);
+    Flow::Next
+}
+
+pub(super) fn threaded_mfc0(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    // Like loads, the result isn't available to the following instruction
+    state.stage_load(op.t_reg, state.cop0_reg[op.d_reg as usize]);
+    Flow::Next
+}
+
+pub(super) fn threaded_rfe(
+    _op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    // Pop the KU/IE mode stack, leaving the old mode bits in place
+    let sr = state.cop0_reg[cop0::Register::Sr as usize];
+    state.cop0_reg[cop0::Register::Sr as usize] = (sr & !0xf) | ((sr >> 2) & 0xf);
+    Flow::Next
+}
+
+// Raises the exception for an instruction for a coprocessor other than COP0
+pub(super) fn threaded_missing_cop(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let cause = match state.check_cop_usable(op.cop) {
+        Err(cause) => cause,
+        // FIXME: COP2 should be handled once the GTE exists
+        Ok(_) => cop0::ExceptionCause::ReservedInstruction,
+    };
+
+    threaded_raise_exception(state, cause, op)
+}
+
+impl ThreadBlock {
+    fn emit_cop0_operation(&mut self, instr: &decode::MipsCopInstr) {
+        let op = Op {
+            t_reg: instr.t_reg,
+            d_reg: instr.d_reg,
+            ..self.op(threaded_mtc0)
+        };
+
+        match instr.operation {
+            MipsCopOperation::MoveTo => self.push(op),
+            MipsCopOperation::MoveFrom => self.push_load(Op {
+                handler: threaded_mfc0,
+                ..op
+            }),
+            // COP0 has no control registers
+            _ => self.push_exception(self.op(super::threaded_reserved_instruction)),
+        }
+    }
+
+    pub(super) fn emit_cop_operation(&mut self, instr: &decode::MipsCopInstr) {
+        match instr.cop {
+            0 => self.emit_cop0_operation(instr),
+            cop => self.emit_missing_cop(cop),
+        }
+    }
+
+    fn emit_cop0_command(&mut self, instr: &decode::MipsCopCmdInstr) {
+        match instr.cop0_command() {
+            MipsCop0Command::Rfe => self.push(self.op(threaded_rfe)),
+            // The PS1 has no TLB, so RFE is the only command
+            MipsCop0Command::Invalid => {
+                self.push_exception(self.op(super::threaded_reserved_instruction))
+            }
+        }
+    }
+
+    pub(super) fn emit_cop_command(&mut self, instr: &decode::MipsCopCmdInstr) {
+        match instr.cop {
+            0 => self.emit_cop0_command(instr),
+            cop => self.emit_missing_cop(cop),
+        }
+    }
+
+    pub(super) fn emit_cop_mem(&mut self, instr: &decode::MipsCopMemInstr) {
+        match instr.cop {
+            // There are no COP0 loads or stores
+            0 => self.push_exception(self.op(super::threaded_reserved_instruction)),
+            cop => self.emit_missing_cop(cop),
+        }
+    }
+
+    pub(super) fn emit_missing_cop(&mut self, cop: u8) {
+        self.push_exception(Op {
+            cop,
+            ..self.op(threaded_missing_cop)
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn threaded_test_mtc0_mfc0() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let value = 10;
+
+        th.load32(1, value);
+
+        th.push_instr("mtc0", 1, 0, 1, 0, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.push_instr("mfc0", 1, 0, 2, 0, 0);
+        th.push_instr("addu", 3, 2, 0, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], value);
+        assert_eq!(state.gpr[2], 0);
+    }
+
+    #[test]
+    fn threaded_test_cop0_reserved_command() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // TLBR, which the PS1 has no TLB for
+        th.push_raw(0x4200_0001);
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
+}
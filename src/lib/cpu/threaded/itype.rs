@@ -1,268 +1,405 @@
+use super::cop0::ExceptionCause;
 use super::mem;
-use super::opcode::MipsOpcode;
-use super::{decode, DelaySlotArg};
-
-use super::{BusType, CpuState, TbManager, ThreadBlock};
-
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_bne(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-    icount: u32,
-) -> u32 {
-    let s_val = (*state).get_reg_val(s_reg);
-    let t_val = (*state).get_reg_val(t_reg);
-
-    let pc = (*state).pc + 4 * icount;
+use super::opcode::{MipsBranchSpecial, MipsOpcode};
+use super::{decode, threaded_raise_exception};
+
+use super::{BusType, CpuState, Flow, Handler, Op, TbManager, ThreadBlock};
+
+pub(super) fn threaded_bne(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg);
+    let t_val = state.get_reg_val(op.t_reg);
+
+    let pc = state.pc + 4 * op.icount;
 
     let target = if s_val != t_val {
-        (pc as i32 + (immed as i16 as i32) * 4 + 4) as u32
+        (pc as i32 + (op.immed as i16 as i32) * 4 + 4) as u32
     } else {
         pc + 8
     };
 
-    target
+    Flow::Jump(target)
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_beq(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-    icount: u32,
-) -> u32 {
-    let s_val = (*state).get_reg_val(s_reg);
-    let t_val = (*state).get_reg_val(t_reg);
-
-    let pc = (*state).pc + 4 * icount;
+pub(super) fn threaded_beq(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg);
+    let t_val = state.get_reg_val(op.t_reg);
+
+    let pc = state.pc + 4 * op.icount;
 
     let target = if s_val == t_val {
-        (pc as i32 + (immed as i16 as i32) * 4 + 4) as u32
+        (pc as i32 + (op.immed as i16 as i32) * 4 + 4) as u32
     } else {
         pc + 8
     };
 
-    target
+    Flow::Jump(target)
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_bgtz(
-    s_reg: u8,
-    _t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-    icount: u32,
-) -> u32 {
-    let s_val = (*state).get_reg_val(s_reg);
+pub(super) fn threaded_bgtz(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg) as i32;
 
-    let pc = (*state).pc + 4 * icount;
+    let pc = state.pc + 4 * op.icount;
 
     let target = if s_val > 0 {
-        (pc as i32 + (immed as i16 as i32) * 4 + 4) as u32
+        (pc as i32 + (op.immed as i16 as i32) * 4 + 4) as u32
     } else {
         pc + 8
     };
 
-    target
+    Flow::Jump(target)
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_addi(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let val = (*state).get_reg_val(s_reg) + immed as i16 as i32 as u32;
-    (*state).set_reg_val(t_reg, val);
+pub(super) fn threaded_blez(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg) as i32;
+
+    let pc = state.pc + 4 * op.icount;
+
+    let target = if s_val <= 0 {
+        (pc as i32 + (op.immed as i16 as i32) * 4 + 4) as u32
+    } else {
+        pc + 8
+    };
+
+    Flow::Jump(target)
+}
+
+// Branches encoded under the REGIMM opcode, with the branch type in the t register field
+pub(super) fn threaded_special_branch(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let special_op = num::FromPrimitive::from_u8(op.t_reg).unwrap_or(MipsBranchSpecial::Invalid);
+    let s_val = state.get_reg_val(op.s_reg) as i32;
+
+    let pc = state.pc + 4 * op.icount;
+
+    let (taken, link) = match special_op {
+        MipsBranchSpecial::Bltz => (s_val < 0, false),
+        MipsBranchSpecial::Bgez => (s_val >= 0, false),
+        MipsBranchSpecial::Bltzal => (s_val < 0, true),
+        MipsBranchSpecial::Bgezal => (s_val >= 0, true),
+        MipsBranchSpecial::Invalid => unreachable!("Reserved REGIMM encoding {}", op.t_reg),
+    };
+
+    // The link register is written regardless of whether the branch is taken
+    if link {
+        state.set_reg_val(31, pc + 8);
+    }
+
+    let target = if taken {
+        (pc as i32 + (op.immed as i16 as i32) * 4 + 4) as u32
+    } else {
+        pc + 8
+    };
+
+    Flow::Jump(target)
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_sltiu(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let s_val = (*state).get_reg_val(s_reg);
-    let val = if s_val < immed as u32 { 1 } else { 0 };
-
-    (*state).set_reg_val(t_reg, val as u32);
+pub(super) fn threaded_addi(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg) as i32;
+
+    // The destination is left unchanged on overflow
+    match s_val.checked_add(op.immed as i16 as i32) {
+        Some(val) => {
+            state.set_reg_val(op.t_reg, val as u32);
+            Flow::Next
+        }
+        None => threaded_raise_exception(state, ExceptionCause::Overflow, op),
+    }
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_slti(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let s_val = (*state).get_reg_val(s_reg) as i32;
-    let val = if s_val < immed as i16 as i32 { 1 } else { 0 };
-
-    (*state).set_reg_val(t_reg, val as u32);
+pub(super) fn threaded_addiu(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state
+        .get_reg_val(op.s_reg)
+        .wrapping_add(op.immed as i16 as i32 as u32);
+    state.set_reg_val(op.t_reg, val);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_ori(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let val = (*state).get_reg_val(s_reg) | (immed as u32);
-    (*state).set_reg_val(t_reg, val);
+pub(super) fn threaded_sltiu(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg);
+    let val = if s_val < op.immed as i16 as u32 { 1 } else { 0 };
+
+    state.set_reg_val(op.t_reg, val as u32);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_lui(
-    _s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let val = (immed as u32) << 16;
-    (*state).set_reg_val(t_reg, val);
+pub(super) fn threaded_slti(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg) as i32;
+    let val = if s_val < op.immed as i16 as i32 { 1 } else { 0 };
+
+    state.set_reg_val(op.t_reg, val as u32);
+    Flow::Next
 }
 
-impl<'ctx> ThreadBlock<'ctx> {
-    pub(super) fn register_itypes(
-        &mut self,
-        i_jmp_fn_type: &inkwell::types::FunctionType<'ctx>,
-        i_fn_type: &inkwell::types::FunctionType<'ctx>,
-    ) {
-        self.register_itype_jmp_fn(&i_jmp_fn_type, MipsOpcode::Bne, threaded_bne as usize);
-        self.register_itype_jmp_fn(&i_jmp_fn_type, MipsOpcode::Beq, threaded_beq as usize);
-        self.register_itype_jmp_fn(&i_jmp_fn_type, MipsOpcode::Bgtz, threaded_bgtz as usize);
-
-        //self.register_itype_fn(&i_fn_type, MipsOpcode::AddI, threaded_addi as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::AddIU, threaded_addi as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::SltI, threaded_slti as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::SltIU, threaded_sltiu as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::OrI, threaded_ori as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::Lui, threaded_lui as usize);
-
-        self.register_itype_fn(&i_fn_type, MipsOpcode::Lb, mem::threaded_lb as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::Lbu, mem::threaded_lbu as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::Lw, mem::threaded_lw as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::Sb, mem::threaded_sb as usize);
-        self.register_itype_fn(&i_fn_type, MipsOpcode::Sw, mem::threaded_sw as usize);
-    }
+pub(super) fn threaded_andi(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.s_reg) & (op.immed as u32);
+    state.set_reg_val(op.t_reg, val);
+    Flow::Next
+}
 
-    fn emit_itype_jmp(&mut self, instr: &decode::MipsIInstr) {
-        let i8_type = self.ctx.i8_type();
-        let i16_type = self.ctx.i16_type();
-        let i32_type = self.ctx.i32_type();
-        let s_reg = i8_type.const_int(instr.s_reg as u64, false);
-        let t_reg = i8_type.const_int(instr.t_reg as u64, false);
-        let immed = i16_type.const_int(instr.immediate as u64, false);
-        let icount = i32_type.const_int(self.icount as u64, false);
-
-        let fn_name = format!("itype_jmp_fn_{}", instr.opcode);
-        let func = self
-            .module
-            .get_function(&fn_name)
-            .expect(&format!("Not implemented: {}", instr.opcode));
-
-        let cv = self
-            .builder
-            .build_call(
-                func,
-                &[
-                    s_reg.into(),
-                    t_reg.into(),
-                    immed.into(),
-                    self.state_arg.into(),
-                    self.bus_arg.into(),
-                    self.mgr_arg.into(),
-                    icount.into(),
-                ],
-                &format!("itype_jmp_call_{}", self.icount),
-            )
-            .try_as_basic_value()
-            .left()
-            .unwrap();
-
-        self.instr_finished_emitting();
-
-        self.delay_slot_arg = Some(DelaySlotArg {
-            count: self.icount as u64,
-            value: cv,
-        });
-
-        self.delay_slot_fn = Some(|tb| {
-            let arg = tb.delay_slot_arg.as_ref().unwrap();
-            let pc = tb.gep_pc(&format!("itype_jmp_{}", arg.count));
-            tb.builder.build_store(pc, arg.value);
-            tb.builder.build_return(None);
-
-            tb.finalized = true;
-        });
-    }
+pub(super) fn threaded_ori(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.s_reg) | (op.immed as u32);
+    state.set_reg_val(op.t_reg, val);
+    Flow::Next
+}
 
-    fn emit_itype_nojmp(&mut self, instr: &decode::MipsIInstr) {
-        let i8_type = self.ctx.i8_type();
-        let i16_type = self.ctx.i16_type();
-        let s_reg = i8_type.const_int(instr.s_reg as u64, false);
-        let t_reg = i8_type.const_int(instr.t_reg as u64, false);
-        let immed = i16_type.const_int(instr.immediate as u64, false);
-
-        let fn_name = format!("itype_fn_{}", instr.opcode);
-        let func = self
-            .module
-            .get_function(&fn_name)
-            .expect(&format!("Not implemented: {}", instr.opcode));
-
-        self.builder.build_call(
-            func,
-            &[
-                s_reg.into(),
-                t_reg.into(),
-                immed.into(),
-                self.state_arg.into(),
-                self.bus_arg.into(),
-                self.mgr_arg.into(),
-            ],
-            &format!("itype_call_{}", self.icount),
-        );
+pub(super) fn threaded_xori(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.s_reg) ^ (op.immed as u32);
+    state.set_reg_val(op.t_reg, val);
+    Flow::Next
+}
 
-        self.instr_finished_emitting();
-    }
+pub(super) fn threaded_lui(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = (op.immed as u32) << 16;
+    state.set_reg_val(op.t_reg, val);
+    Flow::Next
+}
 
+impl ThreadBlock {
     pub(super) fn emit_itype(&mut self, instr: &decode::MipsIInstr) {
+        if let MipsOpcode::RegisterImm = instr.opcode {
+            let special_op =
+                num::FromPrimitive::from_u8(instr.t_reg).unwrap_or(MipsBranchSpecial::Invalid);
+            if let MipsBranchSpecial::Invalid = special_op {
+                // REGIMM encodings that aren't branches are reserved
+                self.push_exception(self.op(super::threaded_reserved_instruction));
+                return;
+            }
+        }
+
+        let handler: Handler = match instr.opcode {
+            MipsOpcode::Beq => threaded_beq,
+            MipsOpcode::Bne => threaded_bne,
+            MipsOpcode::Blez => threaded_blez,
+            MipsOpcode::Bgtz => threaded_bgtz,
+            MipsOpcode::RegisterImm => threaded_special_branch,
+            MipsOpcode::AddI => threaded_addi,
+            MipsOpcode::AddIU => threaded_addiu,
+            MipsOpcode::SltI => threaded_slti,
+            MipsOpcode::SltIU => threaded_sltiu,
+            MipsOpcode::AndI => threaded_andi,
+            MipsOpcode::OrI => threaded_ori,
+            MipsOpcode::XorI => threaded_xori,
+            MipsOpcode::Lui => threaded_lui,
+            MipsOpcode::Lb => mem::threaded_lb,
+            MipsOpcode::Lbu => mem::threaded_lbu,
+            MipsOpcode::Lh => mem::threaded_lh,
+            MipsOpcode::Lhu => mem::threaded_lhu,
+            MipsOpcode::Lw => mem::threaded_lw,
+            MipsOpcode::Lwl => mem::threaded_lwl,
+            MipsOpcode::Lwr => mem::threaded_lwr,
+            MipsOpcode::Sb => mem::threaded_sb,
+            MipsOpcode::Sh => mem::threaded_sh,
+            MipsOpcode::Sw => mem::threaded_sw,
+            MipsOpcode::Swl => mem::threaded_swl,
+            MipsOpcode::Swr => mem::threaded_swr,
+            _ => panic!("Not implemented: {}", instr.opcode),
+        };
+
+        let op = Op {
+            s_reg: instr.s_reg,
+            t_reg: instr.t_reg,
+            immed: instr.immediate,
+            ..self.op(handler)
+        };
+
         match instr.opcode {
-            MipsOpcode::Beq | MipsOpcode::Bne | MipsOpcode::Bgtz => self.emit_itype_jmp(instr),
-            MipsOpcode::AddI
-            | MipsOpcode::AddIU
-            | MipsOpcode::SltI
-            | MipsOpcode::SltIU
-            | MipsOpcode::OrI
-            | MipsOpcode::Lui
-            | MipsOpcode::Lb
+            MipsOpcode::Beq
+            | MipsOpcode::Bne
+            | MipsOpcode::Blez
+            | MipsOpcode::Bgtz
+            | MipsOpcode::RegisterImm => self.push_jump(op),
+            MipsOpcode::Lb
             | MipsOpcode::Lbu
+            | MipsOpcode::Lh
+            | MipsOpcode::Lhu
             | MipsOpcode::Lw
-            | MipsOpcode::Sb
-            | MipsOpcode::Sw => self.emit_itype_nojmp(instr),
-            _ => panic!("Not implemented: {}", instr.opcode),
+            | MipsOpcode::Lwl
+            | MipsOpcode::Lwr => self.push_load(op),
+            _ => self.push(op),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn threaded_test_andi_xori() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("ori", 0, 0, 1, 42, 0);
+        th.push_instr("andi", 0, 1, 2, 0xf, 0);
+        th.push_instr("xori", 0, 1, 3, 42, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 42 & 0xf);
+        assert_eq!(state.gpr[2], 0);
+    }
+
+    #[test]
+    fn threaded_test_sltiu_sign_extended() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("ori", 0, 0, 1, 42, 0);
+        th.push_instr("sltiu", 0, 1, 2, -1i16 as u16, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 1);
+    }
+
+    #[test]
+    fn threaded_test_blez_taken() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("blez", 0, 0, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 4 + (target << 2) as u32);
+    }
+
+    #[test]
+    fn threaded_test_bgtz_signed() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("addiu", 0, 0, 1, -1i16 as u16, 0);
+        th.push_instr("bgtz", 0, 1, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 0xc);
+    }
+
+    #[test]
+    fn threaded_test_bltzal_not_taken_links() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("bltzal", 0, 1, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 0xc);
+        assert_eq!(state.gpr[30], 0x1000 + 0xc);
+    }
+
+    #[test]
+    fn threaded_test_bgezal_taken_links() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+        let target = 0x100;
+
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("bgezal", 0, 1, 0, target, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1000 + 8 + (target << 2) as u32);
+        assert_eq!(state.gpr[30], 0x1000 + 0xc);
+    }
+
+    #[test]
+    fn threaded_test_regimm_reserved() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // A REGIMM encoding whose t register field isn't one of the branches
+        th.push_raw(0x0402_0002);
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0xa << 2
+        );
+    }
+}
@@ -1,78 +1,68 @@
 use super::opcode::MipsOpcode;
 use super::CpuState;
-use super::{decode, BusType, DelaySlotArg, TbManager, ThreadBlock};
+use super::{decode, BusType, Flow, Handler, Op, TbManager, ThreadBlock};
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_j(
-    target: u32,
-    _state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-    _icount: u32,
-) -> u32 {
-    target << 2
+// Jumps keep the upper bits of the delay slot address
+fn jump_target(op: &Op, state: &CpuState) -> u32 {
+    let delay_slot_pc = state.pc + 4 * op.icount + 4;
+    (delay_slot_pc & 0xf000_0000) | (op.target << 2)
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_jal(
-    target: u32,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-    icount: u32,
-) -> u32 {
-    (*state).set_reg_val(31, (*state).pc + 4 * icount + 8);
-    target << 2
+pub(super) fn threaded_j(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    Flow::Jump(jump_target(op, state))
 }
 
-impl<'ctx> ThreadBlock<'ctx> {
-    pub(super) fn register_jtypes(&mut self, j_fn_type: &inkwell::types::FunctionType<'ctx>) {
-        self.register_jtype_fn(&j_fn_type, MipsOpcode::J, threaded_j as usize);
-        self.register_jtype_fn(&j_fn_type, MipsOpcode::Jal, threaded_jal as usize);
-    }
+pub(super) fn threaded_jal(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let target = jump_target(op, state);
+    state.set_reg_val(31, state.pc + 4 * op.icount + 8);
+    Flow::Jump(target)
+}
 
+impl ThreadBlock {
     pub(super) fn emit_jtype(&mut self, instr: &decode::MipsJInstr) {
-        let i32_type = self.ctx.i32_type();
-        let target = i32_type.const_int(instr.target as u64, false);
-        let icount = i32_type.const_int(self.icount as u64, false);
+        let handler: Handler = match instr.opcode {
+            MipsOpcode::J => threaded_j,
+            MipsOpcode::Jal => threaded_jal,
+            _ => panic!("Not implemented: {}", instr.opcode),
+        };
 
-        let fn_name = format!("jtype_fn_{}", instr.opcode);
-        let func = self
-            .module
-            .get_function(&fn_name)
-            .expect(&format!("Not implemented: {}", instr.opcode));
+        self.push_jump(Op {
+            target: instr.target,
+            ..self.op(handler)
+        });
+    }
+}
 
-        let cv = self
-            .builder
-            .build_call(
-                func,
-                &[
-                    target.into(),
-                    self.state_arg.into(),
-                    self.bus_arg.into(),
-                    self.mgr_arg.into(),
-                    icount.into(),
-                ],
-                &format!("jtype_call_{}", self.icount),
-            )
-            .try_as_basic_value()
-            .left()
-            .unwrap();
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
 
-        self.instr_finished_emitting();
+    #[test]
+    fn threaded_test_jal_upper_bits_retained() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
 
-        self.delay_slot_arg = Some(DelaySlotArg {
-            count: self.icount as u64,
-            value: cv,
-        });
+        let target = 0x0100;
 
-        self.delay_slot_fn = Some(|tb| {
-            let arg = tb.delay_slot_arg.as_ref().unwrap();
-            let pc = tb.gep_pc(&format!("jtype_{}", arg.count));
-            tb.builder.build_store(pc, arg.value);
-            tb.builder.build_return(None);
+        let addr_mask = 0x8000_0000;
+        th.addr |= addr_mask;
 
-            tb.finalized = true;
-        });
+        th.push_instr("jal", 0, 0, 0, 0, target);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.pc, addr_mask | (target << 2));
+        assert_eq!(state.gpr[30], addr_mask | 0x1008);
     }
 }
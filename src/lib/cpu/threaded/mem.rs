@@ -1,4 +1,14 @@
-use super::{BusDevice, BusType, CpuState, SizedReadResult, TbManager};
+use super::cop0::ExceptionCause;
+use super::{threaded_raise_exception, BusDevice, BusType, CpuState, SizedReadResult};
+use super::{Flow, Op, TbManager};
+
+// Raises the exception from a failed access, if there was one
+fn raise_on_err(state: &mut CpuState, res: Result<(), ExceptionCause>, op: &Op) -> Flow {
+    match res {
+        Ok(_) => Flow::Next,
+        Err(cause) => threaded_raise_exception(state, cause, op),
+    }
+}
 
 fn interpret_mem_read(
     s_reg: &u8,
@@ -8,20 +18,21 @@ fn interpret_mem_read(
     bus: &mut BusType,
     state: &mut CpuState,
     sign_extend: bool,
-) {
+) -> Result<(), ExceptionCause> {
     let base = if *s_reg == 0 {
         0
     } else {
         state.gpr[(*s_reg - 1) as usize]
     };
     let addr = (base as i32 + *immed as i16 as i32) as u32;
+    state.check_alignment(addr, size, false)?;
 
-    let read_result = bus.read(addr, size).unwrap();
-    if *t_reg == 0 {
-        return;
-    }
+    let read_result = bus
+        .read(addr, size)
+        .map_err(|e| state.bus_error(e, false))?;
+    state.add_cycles(bus.wait_states(addr, size) as u64);
 
-    state.gpr[(*t_reg - 1) as usize] = match read_result {
+    let val = match read_result {
         SizedReadResult::Byte(b) => {
             if sign_extend {
                 b as i8 as u32
@@ -38,59 +49,144 @@ fn interpret_mem_read(
         }
         SizedReadResult::Dword(d) => d,
     };
+
+    state.stage_load(*t_reg, val);
+    Ok(())
+}
+
+pub(super) fn threaded_lb(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_read(&op.s_reg, &op.t_reg, &op.immed, 8, bus, state, true);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_lbu(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_read(&op.s_reg, &op.t_reg, &op.immed, 8, bus, state, false);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_lh(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_read(&op.s_reg, &op.t_reg, &op.immed, 16, bus, state, true);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_lhu(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_read(&op.s_reg, &op.t_reg, &op.immed, 16, bus, state, false);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_lw(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_read(&op.s_reg, &op.t_reg, &op.immed, 32, bus, state, false);
+    raise_on_err(state, res, op)
+}
+
+fn read_aligned_word(
+    addr: u32,
+    bus: &mut BusType,
+    state: &mut CpuState,
+) -> Result<u32, ExceptionCause> {
+    match bus
+        .read(addr & 0xffff_fffc, 32)
+        .map_err(|e| state.bus_error(e, false))?
+    {
+        SizedReadResult::Dword(d) => Ok(d),
+        r => panic!("Read size of 32 didn't return dword, instead have {:?}", r),
+    }
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_lb(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    interpret_mem_read(&s_reg, &t_reg, &immed, 8, &mut *bus, &mut *state, true);
-}
-
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_lbu(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    interpret_mem_read(&s_reg, &t_reg, &immed, 8, &mut *bus, &mut *state, false);
-}
-
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_lw(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    interpret_mem_read(&s_reg, &t_reg, &immed, 32, &mut *bus, &mut *state, false);
-}
-
-fn interpret_mem_write<'ctx>(
+fn interpret_unaligned_load(
+    s_reg: &u8,
+    t_reg: &u8,
+    immed: &u16,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    left: bool,
+) -> Result<(), ExceptionCause> {
+    let addr = (state.get_reg_val(*s_reg) as i32 + *immed as i16 as i32) as u32;
+    let mem_val = read_aligned_word(addr, bus, state)?;
+    state.add_cycles(bus.wait_states(addr, 32) as u64);
+
+    // If the previous instruction was a load to the same register, merge with its value rather
+    // than waiting for the load delay
+    let curr_val = if state.load_delay_register == *t_reg as u32 {
+        state.load_delay_register_value
+    } else {
+        state.get_reg_val(*t_reg)
+    };
+
+    let alignment = (addr & 0x3) * 8;
+    let inv_alignment = 24 - alignment;
+
+    let new_val = if left {
+        (curr_val & (0x00ff_ffff >> alignment)) | (mem_val << inv_alignment)
+    } else {
+        (curr_val & (0xffff_ff00 << inv_alignment)) | (mem_val >> alignment)
+    };
+
+    state.stage_load(*t_reg, new_val);
+    Ok(())
+}
+
+pub(super) fn threaded_lwl(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_unaligned_load(&op.s_reg, &op.t_reg, &op.immed, bus, state, true);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_lwr(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_unaligned_load(&op.s_reg, &op.t_reg, &op.immed, bus, state, false);
+    raise_on_err(state, res, op)
+}
+
+fn interpret_mem_write(
     s_reg: &u8,
     t_reg: &u8,
     immed: &u16,
     size: u32,
     bus: &mut BusType,
     state: &mut CpuState,
-    mgr: &mut TbManager<'ctx>,
-) {
+    mgr: &mut TbManager,
+) -> Result<(), ExceptionCause> {
     let base = if *s_reg == 0 {
         0
     } else {
         state.gpr[(*s_reg - 1) as usize]
     };
     let addr = (base as i32 + *immed as i16 as i32) as u32;
+    state.check_alignment(addr, size, true)?;
 
     let value = if *t_reg == 0 {
         0
@@ -98,38 +194,188 @@ fn interpret_mem_write<'ctx>(
         state.gpr[(*t_reg - 1) as usize]
     };
 
+    if state.store_isolated(addr) {
+        return Ok(());
+    }
+
+    mgr.invalidate(addr);
+    bus.write(addr, size, value)
+        .map_err(|e| state.bus_error(e, false))
+}
+
+pub(super) fn threaded_sb(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_write(&op.s_reg, &op.t_reg, &op.immed, 8, bus, state, mgr);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_sh(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_write(&op.s_reg, &op.t_reg, &op.immed, 16, bus, state, mgr);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_sw(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_mem_write(&op.s_reg, &op.t_reg, &op.immed, 32, bus, state, mgr);
+    raise_on_err(state, res, op)
+}
+
+fn interpret_unaligned_store(
+    s_reg: &u8,
+    t_reg: &u8,
+    immed: &u16,
+    bus: &mut BusType,
+    state: &mut CpuState,
+    mgr: &mut TbManager,
+    left: bool,
+) -> Result<(), ExceptionCause> {
+    let addr = (state.get_reg_val(*s_reg) as i32 + *immed as i16 as i32) as u32;
+    if state.store_isolated(addr) {
+        return Ok(());
+    }
+
+    let mem_val = read_aligned_word(addr, bus, state)?;
+    let source_val = state.get_reg_val(*t_reg);
+
+    let alignment = (addr & 0x3) * 8;
+    let inv_alignment = 24 - alignment;
+
+    let new_val = if left {
+        (mem_val & (0xffff_ff00 << alignment)) | (source_val >> inv_alignment)
+    } else {
+        (mem_val & (0x00ff_ffff >> inv_alignment)) | (source_val << alignment)
+    };
+
     mgr.invalidate(addr);
-    bus.write(addr, size, value).unwrap();
-}
-
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_sb(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    bus: *mut BusType,
-    mgr: *mut TbManager,
-) {
-    interpret_mem_write(&s_reg, &t_reg, &immed, 8, &mut *bus, &mut *state, &mut *mgr);
-}
-
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_sw(
-    s_reg: u8,
-    t_reg: u8,
-    immed: u16,
-    state: *mut CpuState,
-    bus: *mut BusType,
-    mgr: *mut TbManager,
-) {
-    interpret_mem_write(
-        &s_reg,
-        &t_reg,
-        &immed,
-        32,
-        &mut *bus,
-        &mut *state,
-        &mut *mgr,
-    );
+    bus.write(addr & 0xffff_fffc, 32, new_val)
+        .map_err(|e| state.bus_error(e, false))
+}
+
+pub(super) fn threaded_swl(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_unaligned_store(&op.s_reg, &op.t_reg, &op.immed, bus, state, mgr, true);
+    raise_on_err(state, res, op)
+}
+
+pub(super) fn threaded_swr(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    mgr: &mut TbManager,
+) -> Flow {
+    let res = interpret_unaligned_store(&op.s_reg, &op.t_reg, &op.immed, bus, state, mgr, false);
+    raise_on_err(state, res, op)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn threaded_test_sh_lh() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 0x1400, 0);
+        th.push_instr("addiu", 0, 0, 2, -2i16 as u16, 0);
+        th.push_instr("sh", 0, 1, 2, 0, 0);
+        th.push_instr("lh", 0, 1, 3, 0, 0);
+        th.push_instr("lhu", 0, 1, 4, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], -2i32 as u32);
+        assert_eq!(state.gpr[3], 0xfffe);
+    }
+
+    #[test]
+    fn threaded_test_lwr_lwl() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let addr = 0x1400;
+        let delay_val = 10;
+
+        th.load32(10, 0xffffdead);
+        th.load32(11, 0xbeefffff);
+
+        th.push_instr("addiu", 0, 0, 1, addr, 0);
+        th.push_instr("sw", 0, 1, 10, 0, 0);
+        th.push_instr("sw", 0, 1, 11, 4, 0);
+        th.push_instr("addiu", 0, 0, 3, delay_val, 0);
+        th.push_instr("lwl", 0, 1, 3, 1, 0);
+        th.push_instr("lwr", 0, 1, 3, 6, 0);
+        th.push_instr("addu", 4, 3, 0, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[3], delay_val as u32);
+        assert_eq!(state.gpr[2], 0xdeadbeef);
+    }
+
+    #[test]
+    fn threaded_test_swr_swl() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let addr = 0x1400;
+
+        th.load32(10, 0xdeadbeef);
+
+        th.push_instr("addiu", 0, 0, 1, addr, 0);
+        th.push_instr("swl", 0, 1, 10, 1, 0);
+        th.push_instr("swr", 0, 1, 10, 6, 0);
+
+        th.push_instr("lw", 0, 1, 2, 0, 0);
+        th.push_instr("lw", 0, 1, 3, 4, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[1], 0x0000_dead);
+        assert_eq!(state.gpr[2], 0xbeef_0000);
+    }
+
+    #[test]
+    fn threaded_test_sw_cache_isolated() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        let sr = crate::cpu::cop0::Register::Sr as u8;
+
+        th.load32(1, 1 << 16);
+        th.push_instr("addiu", 0, 0, 2, 0x1400, 0);
+        th.push_instr("addiu", 0, 0, 3, 42, 0);
+        th.push_instr("mtc0", sr, 0, 1, 0, 0);
+        th.push_instr("sw", 0, 2, 3, 0, 0);
+        th.push_instr("mtc0", sr, 0, 0, 0, 0);
+        th.push_instr("lw", 0, 2, 4, 0, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        // The store went to the cache rather than memory
+        assert_eq!(state.gpr[3], 0);
+        assert!(state.take_icache_flush());
+    }
 }
@@ -1,213 +1,157 @@
+use super::backend::{spins_at, CpuBackend, StepResult, TranslationStats};
 use super::bus::{BusDevice, SizedReadResult};
 use super::CpuState;
-use super::{decode, opcode};
+use super::{cop0, decode, opcode};
 use std::rc::Rc;
 
+mod cop;
 mod itype;
 mod jtype;
 mod mem;
 mod rtype;
 
 type BusType = super::bus_vec::VecBus;
-type TbDynFunc<'ctx> =
-    unsafe extern "C" fn(state: *mut CpuState, bus: *mut BusType, mgr: *mut TbManager<'ctx>);
-
-fn new_tb<'ctx>(
-    id: u64,
-    ctx: &'ctx inkwell::context::Context,
-) -> Result<ThreadBlock<'ctx>, String> {
-    let module = ctx.create_module(&format!("tb_mod_{}", id));
-    let ee = module
-        .create_jit_execution_engine(inkwell::OptimizationLevel::Less)
-        .map_err(|e| e.to_string())?;
-    let builder = ctx.create_builder();
-
-    let i32_type = ctx.i32_type();
-    let mips_state_type = ctx.opaque_struct_type("mips_state");
-    mips_state_type.set_body(&[i32_type.into(); 34], false);
-
-    let bus_type = ctx
-        .opaque_struct_type("mips_bus")
-        .ptr_type(inkwell::AddressSpace::Generic);
-    let state_type = module
-        .get_struct_type("mips_state")
-        .unwrap()
-        .ptr_type(inkwell::AddressSpace::Generic);
-    let tb_mgr_type = ctx
-        .opaque_struct_type("tb_manager")
-        .ptr_type(inkwell::AddressSpace::Generic);
-
-    let void_type = ctx.void_type();
-    let fn_type = void_type.fn_type(
-        &[state_type.into(), bus_type.into(), tb_mgr_type.into()],
-        false,
-    );
-    let func_name = format!("tb_func_{}", id);
-    let func = module.add_function(&func_name, fn_type, None);
-
-    let block = ctx.append_basic_block(func, &func_name);
-    builder.position_at_end(block);
-
-    let state_arg = func
-        .get_nth_param(0)
-        .ok_or("No state arg")?
-        .into_pointer_value();
-    let bus_arg = func
-        .get_nth_param(1)
-        .ok_or("No bus arg")?
-        .into_pointer_value();
-    let mgr_arg = func
-        .get_nth_param(2)
-        .ok_or("No manager arg")?
-        .into_pointer_value();
-
-    let i8_type = ctx.i8_type();
-    let i16_type = ctx.i16_type();
-    let r_fn_type = void_type.fn_type(
-        &[
-            i8_type.into(),
-            i8_type.into(),
-            i8_type.into(),
-            i8_type.into(),
-            state_type.into(),
-            bus_type.into(),
-            tb_mgr_type.into(),
-        ],
-        false,
-    );
-
-    let r_jmp_fn_type = i32_type.fn_type(
-        &[
-            i8_type.into(),
-            i8_type.into(),
-            i8_type.into(),
-            i8_type.into(),
-            state_type.into(),
-            bus_type.into(),
-            tb_mgr_type.into(),
-            i32_type.into(),
-        ],
-        false,
-    );
-
-    let i_fn_type = void_type.fn_type(
-        &[
-            i8_type.into(),
-            i8_type.into(),
-            i16_type.into(),
-            state_type.into(),
-            bus_type.into(),
-            tb_mgr_type.into(),
-        ],
-        false,
-    );
-
-    let i_jmp_fn_type = i32_type.fn_type(
-        &[
-            i8_type.into(),
-            i8_type.into(),
-            i16_type.into(),
-            state_type.into(),
-            bus_type.into(),
-            tb_mgr_type.into(),
-            i32_type.into(),
-        ],
-        false,
-    );
-
-    let j_fn_type = i32_type.fn_type(
-        &[
-            i32_type.into(),
-            state_type.into(),
-            bus_type.into(),
-            tb_mgr_type.into(),
-            i32_type.into(),
-        ],
-        false,
-    );
-
-    let mut tb = ThreadBlock {
-        id,
-        icount: 0,
-        finalized: false,
-        ctx,
-        module,
-        ee,
-        builder,
-        state_arg,
-        bus_arg,
-        mgr_arg,
-        tb_func: None,
-        delay_slot_arg: None,
-        delay_slot_fn: None,
-    };
-
-    tb.register_rtypes(&r_jmp_fn_type, &r_fn_type);
-    tb.register_itypes(&i_jmp_fn_type, &i_fn_type);
-    tb.register_jtypes(&j_fn_type);
 
-    Ok(tb)
+// Executes a single pre-decoded instruction
+type Handler = fn(op: &Op, state: &mut CpuState, bus: &mut BusType, mgr: &mut TbManager) -> Flow;
+
+// What a handler wants to happen after it has run
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    Next,
+    // Jump to the target once the delay slot has executed
+    Jump(u32),
+    // The PC has already been vectored to the exception handler, so the block must return
+    Exception,
 }
 
-struct DelaySlotArg<'ctx> {
-    count: u64,
-    value: inkwell::values::BasicValueEnum<'ctx>,
+// An instruction decoded ahead of time, with the handler that executes it.
+// Fields that an instruction doesn't use are left as 0.
+#[derive(Clone, Copy)]
+struct Op {
+    handler: Handler,
+    s_reg: u8,
+    t_reg: u8,
+    d_reg: u8,
+    shamt: u8,
+    immed: u16,
+    target: u32,
+    cop: u8,
+    // Position of the instruction in the block
+    icount: u32,
+    // Cycles taken by the block ahead of the instruction, for instructions using the multiplier
+    offset: u32,
+    delay_slot: bool,
+    // Set when the previous instruction staged a load, which must be written back once this one
+    // has executed
+    apply_load_delay: bool,
 }
 
-pub(super) struct ThreadBlock<'ctx> {
-    id: u64,
+pub(super) struct ThreadBlock {
+    ops: Vec<Op>,
     icount: u64,
+    // Cycles taken by the instructions translated so far, including fetch wait states
+    cycles: u64,
+    // Address after the last instruction, where execution carries on if the block runs to its end
+    end: u32,
     finalized: bool,
 
-    ctx: &'ctx inkwell::context::Context,
-    module: inkwell::module::Module<'ctx>,
-    ee: inkwell::execution_engine::ExecutionEngine<'ctx>,
-    builder: inkwell::builder::Builder<'ctx>,
-
-    state_arg: inkwell::values::PointerValue<'ctx>,
-    bus_arg: inkwell::values::PointerValue<'ctx>,
-    mgr_arg: inkwell::values::PointerValue<'ctx>,
+    // Set while translating the delay slot of a jump, which ends the block
+    in_delay_slot: bool,
+    // Set when the previous instruction staged a load
+    load_delay_pending: bool,
 
-    tb_func: Option<inkwell::execution_engine::JitFunction<'ctx, TbDynFunc<'ctx>>>,
-    delay_slot_arg: Option<DelaySlotArg<'ctx>>,
-    delay_slot_fn: Option<fn(&mut ThreadBlock)>,
+    // Set for a block that does nothing but branch back to itself
+    spins: bool,
 }
 
-impl<'ctx> ThreadBlock<'ctx> {
-    fn emit_nop(&mut self) {
-        self.emit_rtype(&decode::MipsRInstr {
+impl ThreadBlock {
+    fn new(pc: u32) -> Self {
+        Self {
+            ops: Vec::new(),
+            icount: 0,
+            cycles: 0,
+            end: pc,
+            finalized: false,
+            in_delay_slot: false,
+            load_delay_pending: false,
+            spins: false,
+        }
+    }
+
+    // An op for the next instruction, with no operands set
+    fn op(&self, handler: Handler) -> Op {
+        Op {
+            handler,
             s_reg: 0,
             t_reg: 0,
             d_reg: 0,
             shamt: 0,
-            function: opcode::MipsFunction::Sll,
-        });
+            immed: 0,
+            target: 0,
+            cop: 0,
+            icount: self.icount as u32,
+            offset: self.cycles as u32,
+            delay_slot: self.in_delay_slot,
+            apply_load_delay: false,
+        }
     }
 
-    fn gep_pc(&self, prefix: &str) -> inkwell::values::PointerValue<'ctx> {
-        self.builder
-            .build_struct_gep(self.state_arg, 33, &format!("{}_pc", prefix))
-            .unwrap()
-    }
+    // Appends an instruction that may execute normally. An instruction in a delay slot ends the
+    // block.
+    fn push(&mut self, mut op: Op) {
+        // Write back the load before any branch in the delay slot exits the block
+        op.apply_load_delay = self.load_delay_pending;
+        self.load_delay_pending = false;
 
-    fn instr_finished_emitting(&mut self) {
-        if self.finalized {
-            return;
-        }
+        self.ops.push(op);
+        self.icount += 1;
 
-        if let Some(f) = self.delay_slot_fn {
-            f(self);
-            self.delay_slot_fn = None;
-            self.delay_slot_arg = None;
+        if self.in_delay_slot {
+            self.in_delay_slot = false;
+            self.finalized = true;
         }
+    }
+
+    fn push_jump(&mut self, op: Op) {
+        self.push(op);
+        self.in_delay_slot = !self.finalized;
+    }
+
+    fn push_load(&mut self, op: Op) {
+        // The helper writes back any load that is still pending before staging its own
+        self.load_delay_pending = false;
+        self.push(op);
+
+        // In a branch delay slot the block has already returned, so the load will be written back
+        // at the start of the next block
+        self.load_delay_pending = !self.finalized;
+    }
 
+    // Appends an instruction that unconditionally raises an exception, and ends the block. Nothing
+    // pending from previous instructions is applied, as the exception takes priority over the
+    // delay slot.
+    fn push_exception(&mut self, op: Op) {
+        self.ops.push(op);
         self.icount += 1;
+        self.in_delay_slot = false;
+        self.load_delay_pending = false;
+        self.finalized = true;
     }
 
     fn translate(&mut self, bus: &mut dyn BusDevice, pc: u32) -> Result<(), String> {
         let mut addr = pc;
         while !self.finalized {
-            let read_result = bus.read(addr, 32).map_err(|_| "Failed to read instr")?;
+            let read_result = match bus.read(addr, 32) {
+                Ok(r) => r,
+                Err(_) => {
+                    // The block may branch away before reaching the failed fetch, so the bus
+                    // error is only raised if the instruction is reached
+                    self.push_exception(self.op(threaded_instruction_bus_error));
+                    continue;
+                }
+            };
+
             if let SizedReadResult::Dword(instr_raw) = read_result {
                 let instr = decode::mips_decode(instr_raw);
 
@@ -215,7 +159,12 @@ impl<'ctx> ThreadBlock<'ctx> {
                     decode::MipsInstr::RType(r) => self.emit_rtype(&r),
                     decode::MipsInstr::IType(i) => self.emit_itype(&i),
                     decode::MipsInstr::JType(j) => self.emit_jtype(&j),
-                    _ => self.emit_nop(),
+                    decode::MipsInstr::Cop(c) => self.emit_cop_operation(&c),
+                    decode::MipsInstr::CopCmd(c) => self.emit_cop_command(&c),
+                    decode::MipsInstr::CopMem(c) => self.emit_cop_mem(&c),
+                    decode::MipsInstr::Invalid => {
+                        self.push_exception(self.op(threaded_reserved_instruction))
+                    }
                 }
             } else {
                 panic!(
@@ -224,182 +173,259 @@ impl<'ctx> ThreadBlock<'ctx> {
                 );
             }
 
+            // Counted after decoding, so that the instruction sees the cycles ahead of it
+            self.cycles += 1 + bus.wait_states(addr, 32) as u64;
             addr += 4;
-            if ((addr >> 2) & 0x3f == 0) && !self.finalized {
-                let i32_type = self.ctx.i32_type();
-                let pc_val = i32_type.const_int(addr as u64, false);
-                let pc_ptr = self.gep_pc("block_end");
-                self.builder.build_store(pc_ptr, pc_val);
-                self.builder.build_return(None);
+
+            // A delay slot past the end of the line is still included, so that the jump completes
+            if (addr >> 2) & 0x3f == 0 && !self.in_delay_slot {
                 self.finalized = true;
             }
         }
 
-        assert!(self.delay_slot_fn.is_none());
+        self.end = addr;
         Ok(())
     }
 
-    pub fn finalize(&mut self) {
-        unsafe { self.tb_func = self.ee.get_function(&format!("tb_func_{}", self.id)).ok() }
-    }
+    pub(crate) fn execute(&self, state: &mut CpuState, bus: &mut BusType, mgr: &mut TbManager) {
+        // A load may still be pending from the end of the previous block
+        state.apply_load_delay();
+
+        let mut jump_target = None;
+        for op in &self.ops {
+            let flow = (op.handler)(op, state, bus, mgr);
+            if flow == Flow::Exception {
+                return;
+            }
+
+            if op.apply_load_delay {
+                state.apply_load_delay();
+            }
 
-    pub(crate) fn execute(
-        &self,
-        state: &mut CpuState,
-        bus: &mut BusType,
-        mgr: &mut TbManager<'ctx>,
-    ) -> Result<(), String> {
-        if let Some(func) = self.tb_func.as_ref() {
-            unsafe { func.call(state, bus, mgr) }
-            Ok(())
-        } else {
-            Err(String::from("Failed to compile TB"))
+            // The previous instruction was a jump, and its delay slot has now executed
+            if let Some(target) = jump_target {
+                state.pc = target;
+                return;
+            }
+
+            if let Flow::Jump(target) = flow {
+                jump_target = Some(target);
+            }
         }
-    }
 
-    fn register_rtype_fn(
-        &mut self,
-        fn_type: &inkwell::types::FunctionType<'ctx>,
-        mips_func: opcode::MipsFunction,
-        func: usize,
-    ) {
-        let name = format!("rtype_fn_{}", mips_func);
-        let mod_fn = self.module.add_function(&name, *fn_type, None);
-        self.ee.add_global_mapping(&mod_fn, func as usize);
+        state.pc = self.end;
     }
+}
 
-    fn register_rtype_jmp_fn(
-        &mut self,
-        fn_type: &inkwell::types::FunctionType<'ctx>,
-        mips_func: opcode::MipsFunction,
-        func: usize,
-    ) {
-        let name = format!("rtype_jmp_fn_{}", mips_func);
-        let mod_fn = self.module.add_function(&name, *fn_type, None);
-        self.ee.add_global_mapping(&mod_fn, func);
-    }
+fn threaded_reserved_instruction(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    threaded_raise_exception(state, cop0::ExceptionCause::ReservedInstruction, op)
+}
 
-    fn register_itype_fn(
-        &mut self,
-        fn_type: &inkwell::types::FunctionType<'ctx>,
-        opcode: opcode::MipsOpcode,
-        func: usize,
-    ) {
-        let name = format!("itype_fn_{}", opcode);
-        let mod_fn = self.module.add_function(&name, *fn_type, None);
-        self.ee.add_global_mapping(&mod_fn, func);
-    }
+fn threaded_instruction_bus_error(
+    op: &Op,
+    state: &mut CpuState,
+    bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    // The fetch failed during translation, so repeat it to find out why
+    let pc = state.pc + 4 * op.icount;
+    let cause = match bus.read(pc, 32) {
+        Err(e) => state.bus_error(e, true),
+        Ok(_) => cop0::ExceptionCause::InstructionBusError,
+    };
 
-    fn register_itype_jmp_fn(
-        &mut self,
-        fn_type: &inkwell::types::FunctionType<'ctx>,
-        opcode: opcode::MipsOpcode,
-        func: usize,
-    ) {
-        let name = format!("itype_jmp_fn_{}", opcode);
-        let mod_fn = self.module.add_function(&name, *fn_type, None);
-        self.ee.add_global_mapping(&mod_fn, func);
-    }
+    threaded_raise_exception(state, cause, op)
+}
 
-    fn register_jtype_fn(
-        &mut self,
-        fn_type: &inkwell::types::FunctionType<'ctx>,
-        opcode: opcode::MipsOpcode,
-        func: usize,
-    ) {
-        let name = format!("jtype_fn_{}", opcode);
-        let mod_fn = self.module.add_function(&name, *fn_type, None);
-        self.ee.add_global_mapping(&mod_fn, func as usize);
-    }
+// Vectors to the exception handler for `op`. Always returns Flow::Exception, so that handlers can
+// return its result to end the block.
+fn threaded_raise_exception(state: &mut CpuState, cause: cop0::ExceptionCause, op: &Op) -> Flow {
+    let pc = state.pc + 4 * op.icount;
+    state.pc = state.raise_exception(&cause, pc, op.delay_slot);
+    Flow::Exception
 }
 
-pub(crate) struct TbManager<'ctx> {
-    trie: super::trie::Trie<ThreadBlock<'ctx>>,
+pub(crate) struct TbManager {
+    trie: super::trie::Trie<ThreadBlock>,
+    stats: TranslationStats,
 }
 
-impl<'ctx> TbManager<'ctx> {
+impl TbManager {
     pub(super) fn new() -> Self {
         Self {
             trie: super::trie::Trie::default(),
+            stats: TranslationStats::default(),
         }
     }
 
     pub(super) fn get_tb(
         &mut self,
-        ctx: &'ctx inkwell::context::Context,
         addr: u32,
         bus: &mut impl BusDevice,
-    ) -> Result<Rc<ThreadBlock<'ctx>>, String> {
+    ) -> Result<Rc<ThreadBlock>, String> {
         if let Some(tb) = self.trie.lookup(addr) {
             return Ok(tb.clone());
         }
 
-        let mut tb = new_tb(addr as u64, ctx)?;
+        let start = std::time::Instant::now();
+        let mut tb = ThreadBlock::new(addr);
         tb.translate(bus, addr)?;
-        tb.finalize();
+        tb.spins = spins_at(bus, addr);
+        self.stats.blocks_compiled += 1;
+        self.stats.compile_time += start.elapsed();
+
         let tb_rc = Rc::new(tb);
         self.trie.insert(addr, &tb_rc)?;
-        return Ok(tb_rc);
+        Ok(tb_rc)
     }
 
     fn invalidate(&mut self, addr: u32) {
-        self.trie.invalidate(addr);
+        if self.trie.invalidate(addr) {
+            self.stats.invalidations += 1;
+        }
+    }
+
+    // Discards every translated block
+    fn flush(&mut self) {
+        self.trie = super::trie::Trie::default();
+        self.stats.invalidations += 1;
     }
 }
 
-pub fn execute(bus: &mut BusType, state: &mut CpuState) -> Result<(), String> {
-    let ctx = inkwell::context::Context::create();
-    let mut tb_mgr = TbManager::new();
-    let mut prev_pc = 0;
-    let mut icount_tot = 0;
-    let mut icount = 0;
-    let now = std::time::Instant::now();
-    let mut prev_elapsed: u128 = 0;
+// Translates blocks of guest code to a list of pre-decoded instructions, each executed by a
+// helper. Unlike the JIT this needs no code generation, so blocks are cheap to translate.
+pub struct Threaded {
+    tb_mgr: TbManager,
+    prev_pc: Option<u32>,
+}
+
+impl Default for Threaded {
+    fn default() -> Self {
+        Self {
+            tb_mgr: TbManager::new(),
+            prev_pc: None,
+        }
+    }
+}
 
-    let mut mips_avg: f64 = 0.0;
-    let mut mips_min: f64 = f64::MAX;
-    let mut mips_max: f64 = 0.0;
-    let mut mips_avg_count: u128 = 0;
+impl CpuBackend for Threaded {
+    fn step(&mut self, bus: &mut BusType, state: &mut CpuState) -> Result<StepResult, String> {
+        // The guest flushes the I-cache after changing code, which may not have been caught by
+        // the invalidation on stores
+        if state.take_icache_flush() {
+            self.tb_mgr.flush();
+        }
 
-    let timing_scale = 1_000;
+        // Blocks never end ahead of a delay slot, so interrupts can be taken between any two
+        if state.raise_if_interrupt_pending() || state.raise_if_pc_misaligned() {
+            return Ok(StepResult::Executed(0));
+        }
 
-    loop {
-        let tb = tb_mgr.get_tb(&ctx, state.pc, bus)?;
+        let tb = self.tb_mgr.get_tb(state.pc, bus)?;
 
-        if state.pc == prev_pc && tb.icount == 2 {
-            break;
+        if self.prev_pc == Some(state.pc) && tb.spins {
+            return Ok(StepResult::Halted);
         }
+        self.prev_pc = Some(state.pc);
 
-        prev_pc = state.pc;
+        tb.execute(state, bus, &mut self.tb_mgr);
+        state.take_bus_error()?;
 
-        tb.execute(state, bus, &mut tb_mgr)?;
-        icount += tb.icount;
+        // A block left early through an exception is still charged in full
+        state.add_cycles(tb.cycles);
 
-        if icount > timing_scale {
-            let elapsed_micros_tot = now.elapsed().as_micros();
-            let elapsed_micros = elapsed_micros_tot - prev_elapsed;
-            prev_elapsed = elapsed_micros_tot;
-            let elapsed = (elapsed_micros as f64) / 1_000_000.0;
-            let mips = (icount as f64) / elapsed / 1_000_000.0;
-            mips_min = f64::min(mips_min, mips);
-            mips_max = f64::max(mips_max, mips);
-            mips_avg += mips;
-            mips_avg_count += 1;
+        Ok(StepResult::Executed(tb.icount))
+    }
 
-            icount_tot += icount;
-            icount = 0;
-        }
+    fn translation_stats(&self) -> Option<TranslationStats> {
+        Some(self.tb_mgr.stats)
+    }
+
+    fn reset(&mut self, state: &mut CpuState) {
+        self.tb_mgr = TbManager::new();
+        self.prev_pc = None;
+        state.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Threaded;
+    use crate::cpu::backend::run_until_halted;
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn threaded_test_backend_loop() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 5, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.push_instr("bne", 0, 1, 0, -2i16 as u16, 0);
+        th.push_instr("addiu", 0, 2, 2, 1, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let mut backend = Threaded::default();
+                let stats = run_until_halted(&mut backend, bus, state)?;
+
+                // The loop body is translated once and reused for every iteration
+                assert_eq!(stats.translation.unwrap().blocks_compiled, 3);
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.gpr[1], 5);
     }
 
-    let elapsed_micros = now.elapsed().as_micros();
-    let elapsed = (elapsed_micros as f64) / 1_000_000.0;
+    #[test]
+    fn threaded_test_delay_slot_past_line_end() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
 
-    println!("CpuState: {:x?}", state);
-    println!("elapsed time: {}", elapsed);
-    println!("icount: {}", icount_tot + icount);
-    println!("MIPS (average): {}", mips_avg / (mips_avg_count as f64));
-    println!("MIPS (min): {}", mips_min);
-    println!("MIPS (max): {}", mips_max);
+        // The jump is the last instruction of its line, so its delay slot is in the next one
+        th.addr = 0x10f8;
+        th.push_instr("addiu", 0, 0, 1, 1, 0);
+        th.push_instr("j", 0, 0, 0, 0, 0x1200 >> 2);
+        th.push_instr("addiu", 0, 0, 2, 2, 0);
 
-    Ok(())
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x1200);
+        assert_eq!(state.gpr[0], 1);
+        assert_eq!(state.gpr[1], 2);
+    }
+
+    #[test]
+    fn threaded_test_two_instruction_loop() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        // A loop to itself that isn't a spin, as its delay slot counts down
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.push_instr("bne", 0, 1, 0, -1i16 as u16, 0);
+        th.push_instr("addiu", 0, 1, 1, -1i16 as u16, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let mut backend = Threaded::default();
+                run_until_halted(&mut backend, bus, state)?;
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[0], -1i32 as u32);
+    }
 }
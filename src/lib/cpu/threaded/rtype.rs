@@ -1,350 +1,545 @@
+use super::cop0::ExceptionCause;
 use super::opcode::MipsFunction;
 use super::CpuState;
-use super::{decode, DelaySlotArg};
-
-use super::{BusType, TbManager, ThreadBlock};
-
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_jr(
-    s_reg: u8,
-    _t_reg: u8,
-    _d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-    _icount: u32,
-) -> u32 {
-    (*state).get_reg_val(s_reg)
+use super::{decode, threaded_raise_exception};
+
+use super::{BusType, Flow, Handler, Op, TbManager, ThreadBlock};
+use crate::cpu::timing;
+
+pub(super) fn threaded_jr(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    Flow::Jump(state.get_reg_val(op.s_reg))
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_jalr(
-    s_reg: u8,
-    _t_reg: u8,
-    d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-    icount: u32,
-) -> u32 {
-    let target = (*state).get_reg_val(s_reg);
-    (*state).set_reg_val(d_reg, (*state).pc + 4 * icount + 8);
-
-    target
+pub(super) fn threaded_jalr(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let target = state.get_reg_val(op.s_reg);
+    state.set_reg_val(op.d_reg, state.pc + 4 * op.icount + 8);
+
+    Flow::Jump(target)
+}
+
+pub(super) fn threaded_sll(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.t_reg) << op.shamt;
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
+}
+
+pub(super) fn threaded_srl(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.t_reg) >> op.shamt;
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_sll(
-    _s_reg: u8,
-    t_reg: u8,
-    d_reg: u8,
-    shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let val = (*state).get_reg_val(t_reg) << shamt;
-    (*state).set_reg_val(d_reg, val as u32);
+pub(super) fn threaded_sra(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = (state.get_reg_val(op.t_reg) as i32) >> op.shamt;
+    state.set_reg_val(op.d_reg, val as u32);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_srl(
-    _s_reg: u8,
-    t_reg: u8,
-    d_reg: u8,
-    shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let val = (*state).get_reg_val(t_reg) >> shamt;
-    (*state).set_reg_val(d_reg, val as u32);
+// Variable shifts only use the low 5 bits of the shift amount register
+
+pub(super) fn threaded_sllv(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let shamt = state.get_reg_val(op.s_reg) & 0x1f;
+    let val = state.get_reg_val(op.t_reg) << shamt;
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_add(
-    s_reg: u8,
-    t_reg: u8,
-    d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let val = (*state).get_reg_val(t_reg) + (*state).get_reg_val(s_reg);
-    (*state).set_reg_val(d_reg, val as u32);
+pub(super) fn threaded_srlv(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let shamt = state.get_reg_val(op.s_reg) & 0x1f;
+    let val = state.get_reg_val(op.t_reg) >> shamt;
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_or(
-    s_reg: u8,
-    t_reg: u8,
-    d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let val = (*state).get_reg_val(t_reg) | (*state).get_reg_val(s_reg);
-    (*state).set_reg_val(d_reg, val as u32);
+pub(super) fn threaded_srav(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let shamt = state.get_reg_val(op.s_reg) & 0x1f;
+    let val = (state.get_reg_val(op.t_reg) as i32) >> shamt;
+    state.set_reg_val(op.d_reg, val as u32);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_mflo(
-    _s_reg: u8,
-    _t_reg: u8,
-    d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    (*state).set_reg_val(d_reg, (*state).lo);
+pub(super) fn threaded_add(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg) as i32;
+    let t_val = state.get_reg_val(op.t_reg) as i32;
+
+    // The destination is left unchanged on overflow
+    match s_val.checked_add(t_val) {
+        Some(val) => {
+            state.set_reg_val(op.d_reg, val as u32);
+            Flow::Next
+        }
+        None => threaded_raise_exception(state, ExceptionCause::Overflow, op),
+    }
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_mfhi(
-    _s_reg: u8,
-    _t_reg: u8,
-    d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    (*state).set_reg_val(d_reg, (*state).hi);
+pub(super) fn threaded_addu(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state
+        .get_reg_val(op.t_reg)
+        .wrapping_add(state.get_reg_val(op.s_reg));
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_mtlo(
-    s_reg: u8,
-    _t_reg: u8,
-    _d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    (*state).lo = (*state).get_reg_val(s_reg);
+pub(super) fn threaded_sub(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg) as i32;
+    let t_val = state.get_reg_val(op.t_reg) as i32;
+
+    match s_val.checked_sub(t_val) {
+        Some(val) => {
+            state.set_reg_val(op.d_reg, val as u32);
+            Flow::Next
+        }
+        None => threaded_raise_exception(state, ExceptionCause::Overflow, op),
+    }
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_mthi(
-    s_reg: u8,
-    _t_reg: u8,
-    _d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    (*state).hi = (*state).get_reg_val(s_reg);
+pub(super) fn threaded_subu(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state
+        .get_reg_val(op.s_reg)
+        .wrapping_sub(state.get_reg_val(op.t_reg));
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_mult(
-    s_reg: u8,
-    t_reg: u8,
-    _d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let multiplier = (*state).get_reg_val(s_reg) as i32;
-    let multiplicand = (*state).get_reg_val(t_reg) as i32;
+pub(super) fn threaded_or(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.t_reg) | state.get_reg_val(op.s_reg);
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
+}
+
+pub(super) fn threaded_nor(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = !(state.get_reg_val(op.t_reg) | state.get_reg_val(op.s_reg));
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
+}
+
+pub(super) fn threaded_xor(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.t_reg) ^ state.get_reg_val(op.s_reg);
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
+}
+
+pub(super) fn threaded_and(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let val = state.get_reg_val(op.t_reg) & state.get_reg_val(op.s_reg);
+    state.set_reg_val(op.d_reg, val);
+    Flow::Next
+}
+
+pub(super) fn threaded_slt(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg) as i32;
+    let t_val = state.get_reg_val(op.t_reg) as i32;
+    state.set_reg_val(op.d_reg, (s_val < t_val) as u32);
+    Flow::Next
+}
+
+pub(super) fn threaded_sltu(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let s_val = state.get_reg_val(op.s_reg);
+    let t_val = state.get_reg_val(op.t_reg);
+    state.set_reg_val(op.d_reg, (s_val < t_val) as u32);
+    Flow::Next
+}
+
+pub(super) fn threaded_syscall(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    threaded_raise_exception(state, ExceptionCause::Syscall, op)
+}
+
+pub(super) fn threaded_break(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    threaded_raise_exception(state, ExceptionCause::Break, op)
+}
+
+pub(super) fn threaded_mflo(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    state.wait_for_hilo(op.offset as u64);
+    state.set_reg_val(op.d_reg, state.lo);
+    Flow::Next
+}
+
+pub(super) fn threaded_mfhi(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    state.wait_for_hilo(op.offset as u64);
+    state.set_reg_val(op.d_reg, state.hi);
+    Flow::Next
+}
+
+pub(super) fn threaded_mtlo(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    state.lo = state.get_reg_val(op.s_reg);
+    Flow::Next
+}
+
+pub(super) fn threaded_mthi(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    state.hi = state.get_reg_val(op.s_reg);
+    Flow::Next
+}
+
+pub(super) fn threaded_mult(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let multiplier = state.get_reg_val(op.s_reg) as i32;
+    let multiplicand = state.get_reg_val(op.t_reg) as i32;
+    state.start_hilo_op(
+        timing::hilo_latency(&MipsFunction::Mult, multiplier as u32),
+        op.offset as u64,
+    );
 
     let product = (multiplier as i64) * (multiplicand as i64);
-    (*state).lo = product as u32;
-    (*state).hi = (product >> 32) as u32;
+    state.lo = product as u32;
+    state.hi = (product >> 32) as u32;
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_multu(
-    s_reg: u8,
-    t_reg: u8,
-    _d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let multiplier = (*state).get_reg_val(s_reg);
-    let multiplicand = (*state).get_reg_val(t_reg);
+pub(super) fn threaded_multu(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let multiplier = state.get_reg_val(op.s_reg);
+    let multiplicand = state.get_reg_val(op.t_reg);
+    state.start_hilo_op(
+        timing::hilo_latency(&MipsFunction::MultU, multiplier),
+        op.offset as u64,
+    );
 
     let product = (multiplier as u64) * (multiplicand as u64);
-    (*state).lo = product as u32;
-    (*state).hi = (product >> 32) as u32;
+    state.lo = product as u32;
+    state.hi = (product >> 32) as u32;
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_div(
-    s_reg: u8,
-    t_reg: u8,
-    _d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let dividend = (*state).get_reg_val(s_reg) as i32;
-    let divisor = (*state).get_reg_val(t_reg) as i32;
+pub(super) fn threaded_div(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let dividend = state.get_reg_val(op.s_reg) as i32;
+    let divisor = state.get_reg_val(op.t_reg) as i32;
+    state.start_hilo_op(timing::DIV_LATENCY, op.offset as u64);
 
     if divisor == 0 {
-        return;
+        return Flow::Next;
     }
 
-    (*state).lo = ((dividend as i64) / (divisor as i64)) as u32;
-    (*state).hi = ((dividend as i64) % (divisor as i64)) as u32;
+    state.lo = ((dividend as i64) / (divisor as i64)) as u32;
+    state.hi = ((dividend as i64) % (divisor as i64)) as u32;
+    Flow::Next
 }
 
-#[no_mangle]
-pub(super) unsafe extern "C" fn threaded_divu(
-    s_reg: u8,
-    t_reg: u8,
-    _d_reg: u8,
-    _shamt: u8,
-    state: *mut CpuState,
-    _bus: *mut BusType,
-    _mgr: *mut TbManager,
-) {
-    let dividend = (*state).get_reg_val(s_reg);
-    let divisor = (*state).get_reg_val(t_reg);
+pub(super) fn threaded_divu(
+    op: &Op,
+    state: &mut CpuState,
+    _bus: &mut BusType,
+    _mgr: &mut TbManager,
+) -> Flow {
+    let dividend = state.get_reg_val(op.s_reg);
+    let divisor = state.get_reg_val(op.t_reg);
+    state.start_hilo_op(timing::DIV_LATENCY, op.offset as u64);
 
     if divisor == 0 {
-        return;
+        return Flow::Next;
     }
 
-    (*state).lo = ((dividend as u64) / (divisor as u64)) as u32;
-    (*state).hi = ((dividend as u64) % (divisor as u64)) as u32;
+    state.lo = ((dividend as u64) / (divisor as u64)) as u32;
+    state.hi = ((dividend as u64) % (divisor as u64)) as u32;
+    Flow::Next
+}
+
+impl ThreadBlock {
+    pub(super) fn emit_rtype(&mut self, instr: &decode::MipsRInstr) {
+        let handler: Handler = match instr.function {
+            MipsFunction::Sll => threaded_sll,
+            MipsFunction::Srl => threaded_srl,
+            MipsFunction::Sra => threaded_sra,
+            MipsFunction::Sllv => threaded_sllv,
+            MipsFunction::Slrv => threaded_srlv,
+            MipsFunction::Srav => threaded_srav,
+            MipsFunction::Jr => threaded_jr,
+            MipsFunction::Jalr => threaded_jalr,
+            MipsFunction::Syscall => return self.push_exception(self.op(threaded_syscall)),
+            MipsFunction::Brk => return self.push_exception(self.op(threaded_break)),
+            MipsFunction::Mfhi => threaded_mfhi,
+            MipsFunction::Mthi => threaded_mthi,
+            MipsFunction::Mflo => threaded_mflo,
+            MipsFunction::Mtlo => threaded_mtlo,
+            MipsFunction::Mult => threaded_mult,
+            MipsFunction::MultU => threaded_multu,
+            MipsFunction::Div => threaded_div,
+            MipsFunction::DivU => threaded_divu,
+            MipsFunction::Add => threaded_add,
+            MipsFunction::AddU => threaded_addu,
+            MipsFunction::Sub => threaded_sub,
+            MipsFunction::Subu => threaded_subu,
+            MipsFunction::And => threaded_and,
+            MipsFunction::Or => threaded_or,
+            MipsFunction::Xor => threaded_xor,
+            MipsFunction::Nor => threaded_nor,
+            MipsFunction::Slt => threaded_slt,
+            MipsFunction::Sltu => threaded_sltu,
+        };
+
+        let op = Op {
+            s_reg: instr.s_reg,
+            t_reg: instr.t_reg,
+            d_reg: instr.d_reg,
+            shamt: instr.shamt,
+            ..self.op(handler)
+        };
+
+        match instr.function {
+            MipsFunction::Jr | MipsFunction::Jalr => self.push_jump(op),
+            _ => self.push(op),
+        }
+    }
 }
 
-impl<'ctx> ThreadBlock<'ctx> {
-    pub(super) fn register_rtypes(
-        &mut self,
-        r_jmp_fn_type: &inkwell::types::FunctionType<'ctx>,
-        r_fn_type: &inkwell::types::FunctionType<'ctx>,
-    ) {
-        self.register_rtype_jmp_fn(r_jmp_fn_type, MipsFunction::Jr, threaded_jr as usize);
-        self.register_rtype_jmp_fn(r_jmp_fn_type, MipsFunction::Jalr, threaded_jalr as usize);
-
-        self.register_rtype_fn(r_fn_type, MipsFunction::Sll, threaded_sll as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Srl, threaded_srl as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Or, threaded_or as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Add, threaded_add as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::AddU, threaded_add as usize);
-
-        self.register_rtype_fn(r_fn_type, MipsFunction::Mflo, threaded_mflo as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Mfhi, threaded_mfhi as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Mtlo, threaded_mtlo as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Mthi, threaded_mthi as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Mult, threaded_mult as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::MultU, threaded_multu as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::Div, threaded_div as usize);
-        self.register_rtype_fn(r_fn_type, MipsFunction::DivU, threaded_divu as usize);
+#[cfg(test)]
+mod test {
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn threaded_test_subu() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 1);
+        th.load32(2, 2);
+        th.push_instr("subu", 3, 1, 2, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], -1i32 as u32);
     }
 
-    fn emit_rtype_jmp(&mut self, instr: &decode::MipsRInstr) {
-        let i8_type = self.ctx.i8_type();
-        let i32_type = self.ctx.i32_type();
-        let s_reg = i8_type.const_int(instr.s_reg as u64, false);
-        let t_reg = i8_type.const_int(instr.t_reg as u64, false);
-        let d_reg = i8_type.const_int(instr.d_reg as u64, false);
-        let shamt = i8_type.const_int(instr.shamt as u64, false);
-        let icount = i32_type.const_int(self.icount as u64, false);
-
-        let fn_name = format!("rtype_jmp_fn_{}", instr.function);
-        let func = self
-            .module
-            .get_function(&fn_name)
-            .expect(&format!("Not implemented: {}", instr.function));
-
-        let cv = self
-            .builder
-            .build_call(
-                func,
-                &[
-                    s_reg.into(),
-                    t_reg.into(),
-                    d_reg.into(),
-                    shamt.into(),
-                    self.state_arg.into(),
-                    self.bus_arg.into(),
-                    self.mgr_arg.into(),
-                    icount.into(),
-                ],
-                &format!("rtype_jmp_call_{}", self.icount),
-            )
-            .try_as_basic_value()
-            .left()
-            .unwrap();
-
-        self.instr_finished_emitting();
-
-        self.delay_slot_arg = Some(DelaySlotArg {
-            count: self.icount as u64,
-            value: cv,
-        });
-
-        self.delay_slot_fn = Some(|tb| {
-            let arg = tb.delay_slot_arg.as_ref().unwrap();
-            let pc = tb.gep_pc(&format!("rtype_jmp_{}", arg.count));
-            tb.builder.build_store(pc, arg.value);
-            tb.builder.build_return(None);
-
-            tb.finalized = true;
-        });
+    #[test]
+    fn threaded_test_nor_xor_and() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 0b1100);
+        th.load32(2, 0b1010);
+        th.push_instr("nor", 3, 1, 2, 0, 0);
+        th.push_instr("xor", 4, 1, 2, 0, 0);
+        th.push_instr("and", 5, 1, 2, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], !0b1110);
+        assert_eq!(state.gpr[3], 0b0110);
+        assert_eq!(state.gpr[4], 0b1000);
     }
 
-    fn emit_rtype_nojmp(&mut self, instr: &decode::MipsRInstr) {
-        let i8_type = self.ctx.i8_type();
-        let s_reg = i8_type.const_int(instr.s_reg as u64, false);
-        let t_reg = i8_type.const_int(instr.t_reg as u64, false);
-        let d_reg = i8_type.const_int(instr.d_reg as u64, false);
-        let shamt = i8_type.const_int(instr.shamt as u64, false);
-
-        let fn_name = format!("rtype_fn_{}", instr.function);
-        let func = self
-            .module
-            .get_function(&fn_name)
-            .expect(&format!("Not implemented: {}", instr.function));
-
-        self.builder.build_call(
-            func,
-            &[
-                s_reg.into(),
-                t_reg.into(),
-                d_reg.into(),
-                shamt.into(),
-                self.state_arg.into(),
-                self.bus_arg.into(),
-                self.mgr_arg.into(),
-            ],
-            &format!("rtype_call_{}", self.icount),
-        );
+    #[test]
+    fn threaded_test_slt_sltu() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, -1i32 as u32);
+        th.load32(2, 1);
+        th.push_instr("slt", 3, 1, 2, 0, 0);
+        th.push_instr("sltu", 4, 1, 2, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
 
-        self.instr_finished_emitting();
+        assert_eq!(state.gpr[2], 1);
+        assert_eq!(state.gpr[3], 0);
     }
 
-    pub(super) fn emit_rtype(&mut self, instr: &decode::MipsRInstr) {
-        match instr.function {
-            MipsFunction::Jr | MipsFunction::Jalr => self.emit_rtype_jmp(instr),
-            MipsFunction::Srl
-            | MipsFunction::Or
-            | MipsFunction::Add
-            | MipsFunction::AddU
-            | MipsFunction::Mflo
-            | MipsFunction::Mfhi
-            | MipsFunction::Mtlo
-            | MipsFunction::Mthi
-            | MipsFunction::Mult
-            | MipsFunction::MultU
-            | MipsFunction::Div
-            | MipsFunction::DivU
-            | MipsFunction::Sll => self.emit_rtype_nojmp(instr),
-            _ => panic!("Not implemented: {}", instr.function),
-        }
+    #[test]
+    fn threaded_test_sra_srav() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, -8i32 as u32);
+        th.load32(2, 0x22);
+        th.push_instr("sra", 3, 0, 1, 1, 0);
+        th.push_instr("srav", 4, 2, 1, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], -4i32 as u32);
+        assert_eq!(state.gpr[3], -2i32 as u32);
+    }
+
+    #[test]
+    fn threaded_test_sllv_slrv() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.load32(1, 4);
+        th.load32(2, 1);
+        th.push_instr("sllv", 3, 2, 1, 0, 0);
+        th.push_instr("slrv", 4, 2, 1, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[2], 8);
+        assert_eq!(state.gpr[3], 2);
+    }
+
+    #[test]
+    fn threaded_test_syscall_delay_slot() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("jr", 31, 0, 0, 0, 0);
+        th.push_instr("syscall", 0, 0, 0, 0, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Epc as usize],
+            0x1000
+        );
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            (1 << 31) | (0x8 << 2)
+        );
+    }
+
+    #[test]
+    fn threaded_test_break() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("break", 0, 0, 0, 0, 0);
+        th.push_instr("addiu", 0, 0, 1, 10, 0);
+        th.finish();
+
+        th.execute_threaded(&mut state).unwrap();
+
+        assert_eq!(state.gpr[0], 0);
+        assert_eq!(state.pc, 0x8000_0080);
+        assert_eq!(
+            state.cop0_reg[crate::cpu::cop0::Register::Cause as usize],
+            0x9 << 2
+        );
     }
 }
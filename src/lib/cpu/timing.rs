@@ -0,0 +1,111 @@
+// Approximate cycle costs for the R3000A, for the parts of the pipeline that stall.
+// Every instruction otherwise takes a single cycle, plus any wait states for its instruction fetch
+// and data reads as reported by the bus. Stores go through the write buffer, so don't stall.
+use super::opcode::MipsFunction;
+
+// Divides take the same time regardless of their operands
+pub const DIV_LATENCY: u64 = 36;
+
+// Multiplies finish early when the upper bits of rs are all clear, or all set for a signed
+// multiply
+fn mult_latency(magnitude: u32) -> u64 {
+    if magnitude < 0x800 {
+        6
+    } else if magnitude < 0x10_0000 {
+        9
+    } else {
+        13
+    }
+}
+
+// Cycles until the result of a multiply or divide is available in HI/LO, given the value of rs.
+// Other functions don't use the multiplier and take no time.
+pub fn hilo_latency(function: &MipsFunction, rs: u32) -> u64 {
+    match function {
+        MipsFunction::Mult if (rs as i32) < 0 => mult_latency(!rs),
+        MipsFunction::Mult | MipsFunction::MultU => mult_latency(rs),
+        MipsFunction::Div | MipsFunction::DivU => DIV_LATENCY,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::hilo_latency;
+    use crate::cpu::backend::run_until_halted;
+    use crate::cpu::interpret::Interpreter;
+    use crate::cpu::opcode::MipsFunction;
+    use crate::cpu::test::harness::TestHarness;
+
+    #[test]
+    fn timing_test_mult_latency() {
+        assert_eq!(hilo_latency(&MipsFunction::MultU, 0x7ff), 6);
+        assert_eq!(hilo_latency(&MipsFunction::MultU, 0x800), 9);
+        assert_eq!(hilo_latency(&MipsFunction::MultU, 0xffff_ffff), 13);
+
+        // Small negative numbers are as quick as small positive ones when signed
+        assert_eq!(hilo_latency(&MipsFunction::Mult, 0xffff_ffff), 6);
+        assert_eq!(hilo_latency(&MipsFunction::Mult, 0xfff0_0000), 9);
+        assert_eq!(hilo_latency(&MipsFunction::Div, 0), 36);
+    }
+
+    #[test]
+    fn interpret_test_mult_interlock() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("addiu", 0, 0, 1, 2, 0);
+        th.push_instr("addiu", 0, 0, 2, 3, 0);
+        th.push_instr("multu", 0, 1, 2, 0, 0);
+        th.push_instr("mflo", 3, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_generic(
+            &mut state,
+            Box::new(|state, bus| {
+                let stats = run_until_halted(&mut Interpreter::default(), bus, state)?;
+
+                // The mflo stalls until 6 cycles after the multu, then the branch takes one more
+                assert_eq!(stats.cycles, 10);
+                Ok(())
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(state.gpr[2], 6);
+        assert_eq!(state.cycles(), 10);
+    }
+
+    #[test]
+    fn interpret_test_mult_no_stall() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.push_instr("multu", 0, 0, 0, 0, 0);
+        for _ in 0..6 {
+            th.push_instr("sll", 0, 0, 0, 0, 0);
+        }
+        th.push_instr("mfhi", 3, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        assert_eq!(state.cycles(), 9);
+    }
+
+    #[test]
+    fn interpret_test_wait_states() {
+        let mut th = TestHarness::default();
+        let mut state = crate::cpu::CpuState::default();
+
+        th.set_wait_states(0x1000, 2);
+        th.push_instr("lw", 0, 0, 1, 0x1000, 0);
+        th.push_instr("sll", 0, 0, 0, 0, 0);
+        th.finish_loop();
+
+        th.execute_interpreter(&mut state).unwrap();
+
+        // Each fetch stalls, as does the load
+        assert_eq!(state.cycles(), 3 * 3 + 2);
+    }
+}
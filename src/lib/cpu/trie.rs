@@ -30,15 +30,24 @@ impl<T> Trie<T> {
         Ok(())
     }
 
-    pub fn invalidate(&mut self, addr: u32) {
+    // Removes every entry sharing a node with `addr`, returning whether there were any
+    pub fn invalidate(&mut self, addr: u32) -> bool {
         let addr_no_ss = addr & 0x1fffffff;
         let hi = addr_no_ss >> 8;
 
         // FIXME: Rust will bounds check this and it is expensive
         // Maybe using boxed slice instead of vec can avoid unsafe?
-        unsafe {
-            *self.l1.get_unchecked_mut(hi as usize) = None;
-        }
+        unsafe { self.l1.get_unchecked_mut(hi as usize).take().is_some() }
+    }
+
+    // Removes the entry for `addr` alone, returning it if there was one
+    pub fn remove(&mut self, addr: u32) -> Option<Rc<T>> {
+        let addr_no_ss = addr & 0x1fffffff;
+        let hi = addr_no_ss >> 8;
+        let lo = (addr_no_ss >> 2) & 0x3f;
+
+        let l1 = self.l1[hi as usize].as_mut()?;
+        l1[lo as usize].take()
     }
 
     pub fn lookup(&self, addr: u32) -> Option<std::rc::Rc<T>> {
@@ -70,4 +79,29 @@ mod test {
 
         assert_eq!(&10, trie.lookup(0x1000).unwrap().as_ref());
     }
+
+    #[test]
+    fn trie_test_invalidate() {
+        let mut trie: Trie<u32> = Trie::default();
+
+        trie.insert(0x1000, &Rc::new(10)).unwrap();
+
+        assert!(!trie.invalidate(0x2000));
+        assert!(trie.invalidate(0x1004));
+        assert!(trie.lookup(0x1000).is_none());
+        assert!(!trie.invalidate(0x1000));
+    }
+
+    #[test]
+    fn trie_test_remove() {
+        let mut trie: Trie<u32> = Trie::default();
+
+        trie.insert(0x1000, &Rc::new(10)).unwrap();
+        trie.insert(0x1010, &Rc::new(15)).unwrap();
+
+        assert_eq!(&15, trie.remove(0x1010).unwrap().as_ref());
+        assert!(trie.remove(0x1010).is_none());
+        assert!(trie.remove(0x2000).is_none());
+        assert_eq!(&10, trie.lookup(0x1000).unwrap().as_ref());
+    }
 }
@@ -1,4 +1,6 @@
-use crate::cpu::{bus, bus::MemAccessError, bus::MemAccessErrorType, bus::SizedReadResult};
+use crate::cpu::{
+    bus, bus::HostRegion, bus::MemAccessError, bus::MemAccessErrorType, bus::SizedReadResult,
+};
 
 pub struct RAM {
     mem: Box<[u8]>,
@@ -21,12 +23,15 @@ impl bus::BusDevice for RAM {
 
     fn read(&mut self, addr: u32, size: u32) -> Result<SizedReadResult, MemAccessError> {
         assert!(addr < self.size);
+
+        // The CPU raises address errors for misaligned accesses, but other bus masters may not
+        // be as careful
         unsafe {
             let mem: *const u8 = self.mem.as_ptr().add(addr as usize);
             match size {
                 8 => Ok(SizedReadResult::Byte(*mem)),
-                16 => Ok(SizedReadResult::Word(*(mem as *const u16))),
-                32 => Ok(SizedReadResult::Dword(*(mem as *const u32))),
+                16 => Ok(SizedReadResult::Word((mem as *const u16).read_unaligned())),
+                32 => Ok(SizedReadResult::Dword((mem as *const u32).read_unaligned())),
                 _ => Err(MemAccessError {
                     addr,
                     err: MemAccessErrorType::BadSize,
@@ -44,10 +49,10 @@ impl bus::BusDevice for RAM {
                     *mem = value as u8;
                 }
                 16 => {
-                    *(mem as *mut u16) = value as u16;
+                    (mem as *mut u16).write_unaligned(value as u16);
                 }
                 32 => {
-                    *(mem as *mut u32) = value;
+                    (mem as *mut u32).write_unaligned(value);
                 }
                 _ => {
                     return Err(MemAccessError {
@@ -60,6 +65,16 @@ impl bus::BusDevice for RAM {
 
         Ok(())
     }
+
+    // The backing store is never reallocated, so its address is stable
+    fn host_regions(&mut self) -> Vec<HostRegion> {
+        vec![HostRegion {
+            addr: 0,
+            size: self.size,
+            ptr: self.mem.as_mut_ptr(),
+            wait_states: 0,
+        }]
+    }
 }
 
 #[cfg(test)]
@@ -96,4 +111,17 @@ mod test {
             Err(e) => panic!("Memory error {:?}", e),
         }
     }
+
+    #[test]
+    fn ram_test_host_regions() {
+        let mut ram = super::RAM::new(0x1000);
+        ram.write(0x10, 32, 0x1234_5678).unwrap();
+
+        let regions = ram.host_regions();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].size, 0x1000);
+
+        let val = unsafe { (regions[0].ptr.add(0x10) as *const u32).read_unaligned() };
+        assert_eq!(val, 0x1234_5678);
+    }
 }